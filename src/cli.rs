@@ -40,6 +40,39 @@ pub struct Cli {
     /// Edit files in-place (sed mode)
     pub in_place: bool,
 
+    /// Backup suffix for in-place edits (sed mode, -i[SUFFIX]); a `*` in it
+    /// stands for the file name
+    pub backup_suffix: Option<String>,
+
+    /// Treat input files as separate streams (sed mode, --separate flag)
+    pub separate: bool,
+
+    /// Edit the target of symbolic links in place instead of replacing the
+    /// link (sed mode)
+    pub follow_symlinks: bool,
+
+    /// Disable GNU extensions (sed mode)
+    pub posix: bool,
+
+    /// Print a unified diff of the changes instead of applying them (sed
+    /// mode); outside sed mode, compare the two files given side by side
+    pub diff: bool,
+
+    /// Ask before applying each changed hunk (sed mode)
+    pub interactive: bool,
+
+    /// Step through the script in the editor's sed debugger
+    pub sed_debug: bool,
+
+    /// Use extended regular expressions (sed mode, -E/-r flag)
+    pub extended_regex: bool,
+
+    /// Separate input records with NUL bytes (sed mode, -z flag)
+    pub null_data: bool,
+
+    /// Field separator enabling `$N` field references (sed mode, -F flag)
+    pub field_separator: Option<String>,
+
     /// Color theme override
     pub theme: Option<String>,
 }
@@ -56,11 +89,22 @@ impl Cli {
         while let Some(arg) = args.next() {
             match arg.as_str() {
                 "-s" | "--sed" => cli.sed = true,
+                "--separate" => {
+                    cli.sed = true;
+                    cli.separate = true;
+                }
                 "-a" | "--ascii" => cli.ascii = true,
                 "-u" | "--utf8" => cli.utf8 = true,
                 "-g" | "--gui" => cli.gui = true,
                 "-n" | "--quiet" => cli.quiet = true,
                 "-i" | "--in-place" => cli.in_place = true,
+                "--follow-symlinks" => cli.follow_symlinks = true,
+                "--posix" => cli.posix = true,
+                "--diff" => cli.diff = true,
+                "--interactive" => cli.interactive = true,
+                "--sed-debug" => cli.sed_debug = true,
+                "-E" | "-r" | "--regexp-extended" => cli.extended_regex = true,
+                "-z" | "--null-data" => cli.null_data = true,
                 "-e" | "--expression" => {
                     if let Some(expr) = args.next() {
                         cli.expression.push(expr);
@@ -68,6 +112,14 @@ impl Cli {
                         return Err("--expression requires a value".into());
                     }
                 }
+                "-F" | "--field-separator" => {
+                    if let Some(separator) = args.next() {
+                        cli.sed = true;
+                        cli.field_separator = Some(separator);
+                    } else {
+                        return Err("--field-separator requires a value".into());
+                    }
+                }
                 "-f" | "--file" => {
                     if let Some(path) = args.next() {
                         cli.script_file = Some(PathBuf::from(path));
@@ -93,15 +145,53 @@ impl Cli {
                     println!("  -u, --utf8        Force UTF-8 terminal mode");
                     println!("  -a, --ascii       Force ASCII terminal mode");
                     println!("  -t, --theme NAME  Set color theme");
+                    println!("      --diff A B    Compare two files side by side");
                     println!();
                     println!("Sed mode options:");
                     println!("  -s, --sed         Force stream editor mode");
                     println!("  -n, --quiet       Suppress automatic printing");
                     println!("  -e, --expression  Add script to commands");
                     println!("  -f, --file        Add script file");
-                    println!("  -i, --in-place    Edit files in place");
+                    println!("  -i[SUFFIX], --in-place[=SUFFIX]");
+                    println!(
+                        "                    Edit files in place (makes backup if SUFFIX given)"
+                    );
+                    println!("      --separate    Consider files as separate streams");
+                    println!("      --follow-symlinks");
+                    println!("                    Follow symlinks when editing in place");
+                    println!("      --posix       Disable GNU extensions");
+                    println!("      --diff        Show changes as a unified diff without writing");
+                    println!("      --interactive Ask before applying each changed hunk");
+                    println!("      --sed-debug   Step through the script in the editor");
+                    println!("  -E, -r, --regexp-extended");
+                    println!("                    Use extended regular expressions");
+                    println!("  -z, --null-data   Separate lines with NUL characters");
+                    println!("  -F, --field-separator SEP");
+                    println!("                    Split lines into fields for $N in addresses");
+                    println!("                    ($N/re/) and replacements, e.g.");
+                    println!("                    -F: -n '$1/^root$/s/.*/$7/p'");
+                    println!();
+                    println!("A script starting with ',' is a structural expression run over the");
+                    println!("whole input: x/re/, y/re/, g/re/ and v/re/ select text for the");
+                    println!("c/text/, a/text/, i/text/ and d commands, e.g. ',x/foo/ c/bar/'.");
                     std::process::exit(0);
                 }
+                arg if arg.starts_with("--field-separator=") || arg.starts_with("-F") => {
+                    let separator = arg
+                        .strip_prefix("--field-separator=")
+                        .or_else(|| arg.strip_prefix("-F"))
+                        .unwrap_or_default();
+                    cli.sed = true;
+                    cli.field_separator = Some(separator.to_string());
+                }
+                arg if arg.starts_with("--in-place=") || arg.starts_with("-i") => {
+                    let suffix = arg
+                        .strip_prefix("--in-place=")
+                        .or_else(|| arg.strip_prefix("-i"))
+                        .unwrap_or_default();
+                    cli.in_place = true;
+                    cli.backup_suffix = Some(suffix.to_string());
+                }
                 arg if arg.starts_with('-') => {
                     return Err(format!("Unknown flag: {}. Use --help for usage.", arg).into());
                 }
@@ -60,6 +60,12 @@ pub struct EditorApp {
     pub message: Option<String>,
     /// Active diff session
     pub diff_state: Option<crate::sed::diff::DiffState>,
+    /// Active multi-file sed preview
+    pub review_state: Option<crate::sed::diff::ReviewState>,
+    /// Active merge conflict resolution
+    pub conflict_state: Option<crate::core::commands::conflict::ConflictState>,
+    /// Active sed debugging session
+    pub sed_debug: Option<crate::core::commands::sed_debug::SedDebugState>,
     /// Terminal host session
     pub terminal_host: Option<TerminalHost>,
     /// Dispatch depth counter for macro recursion prevention
@@ -70,6 +76,8 @@ pub struct EditorApp {
     pub last_yank_len: usize,
     /// Was the last command a yank? (for yank-pop chaining)
     pub last_command_was_yank: bool,
+    /// Recovery files for modified buffers
+    pub autosave: crate::core::autosave::Autosave,
 }
 
 impl EditorApp {
@@ -118,11 +126,15 @@ impl EditorApp {
             universal_argument: None,
             message: None,
             diff_state: None,
+            review_state: None,
+            conflict_state: None,
+            sed_debug: None,
             terminal_host: None,
             dispatch_depth: 0,
             last_yank_pos: None,
             last_yank_len: 0,
             last_command_was_yank: false,
+            autosave: crate::core::autosave::Autosave::new(),
         }
     }
 
@@ -147,6 +159,11 @@ impl EditorApp {
             }
         }
 
+        // Autosave interval in seconds, 0 turns it off
+        if let Some(ConfigValue::Int(seconds)) = config.settings.get("autosave_interval") {
+            app.autosave.interval = std::time::Duration::from_secs((*seconds).max(0) as u64);
+        }
+
         // Register all commands
         crate::core::commands::register_all(&mut app);
 
@@ -156,6 +173,11 @@ impl EditorApp {
                 if let Some(window) = app.windows.get_mut(&WindowId(0)) {
                     window.buffer_id = buffer_id;
                 }
+                app.message = app
+                    .buffers
+                    .get(&buffer_id)
+                    .and_then(crate::core::commands::file::file_notice);
+                crate::core::commands::file::offer_recovery(&mut app);
             }
         }
 
@@ -0,0 +1,286 @@
+//! Crash recovery
+//!
+//! Every so often the text of each modified file buffer is written to a
+//! recovery file named after the file's absolute path. A recovery file is
+//! removed once its buffer is saved, killed, or the editor exits normally, so
+//! one found when a file is opened was left behind by a crash.
+
+use crate::core::buffer::{Buffer, BufferKind};
+use crate::core::id::BufferId;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often recovery files are written unless configured otherwise
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The recovery files written for open buffers
+#[derive(Debug)]
+pub struct Autosave {
+    /// Directory recovery files go in; `None` turns autosaving off
+    pub dir: Option<PathBuf>,
+    pub interval: Duration,
+    last_run: Instant,
+    /// Recovery file and buffer version written for each buffer
+    written: HashMap<BufferId, (PathBuf, u64)>,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Autosave {
+    /// Autosave into `$XDG_STATE_HOME/erax/recovery`, falling back to
+    /// `~/.local/state/erax/recovery`
+    pub fn new() -> Self {
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            });
+        Self {
+            dir: state_home.map(|dir| dir.join("erax").join("recovery")),
+            interval: DEFAULT_INTERVAL,
+            last_run: Instant::now(),
+            written: HashMap::new(),
+        }
+    }
+
+    /// When the next run is due, if autosaving is on
+    pub fn deadline(&self) -> Option<Instant> {
+        (self.dir.is_some() && !self.interval.is_zero()).then(|| self.last_run + self.interval)
+    }
+
+    /// Write recovery files if the interval has passed since the last run
+    pub fn tick(&mut self, buffers: &HashMap<BufferId, Buffer>) -> Result<(), String> {
+        match self.deadline() {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.last_run = Instant::now();
+                self.run(buffers)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write a recovery file for each modified file buffer that changed since
+    /// its last one, and remove those of buffers that were saved or killed
+    pub fn run(&mut self, buffers: &HashMap<BufferId, Buffer>) -> Result<(), String> {
+        let mut result = Ok(());
+        let stale: Vec<BufferId> = self
+            .written
+            .iter()
+            .filter(|&(id, (path, _))| {
+                buffers
+                    .get(id)
+                    .and_then(|buffer| self.recovery_path_for(buffer))
+                    .as_ref()
+                    != Some(path)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for id in stale {
+            if let Some((path, _)) = self.written.remove(&id) {
+                let _ = fs::remove_file(path);
+            }
+        }
+
+        for (&id, buffer) in buffers {
+            let Some(path) = self.recovery_path_for(buffer) else {
+                continue;
+            };
+            if self.written.get(&id).map(|(_, version)| *version) == Some(buffer.version) {
+                continue;
+            }
+            match write_recovery(&path, &buffer.to_string()) {
+                Ok(()) => {
+                    self.written.insert(id, (path, buffer.version));
+                }
+                Err(e) => result = Err(format!("{}: {}", path.display(), e)),
+            }
+        }
+        result
+    }
+
+    /// Remove the recovery files written by this session, as on a normal exit
+    pub fn discard(&mut self) {
+        for (_, (path, _)) in self.written.drain() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Recovery file for a modified file buffer
+    fn recovery_path_for(&self, buffer: &Buffer) -> Option<PathBuf> {
+        if buffer.buffer_kind() != BufferKind::Normal || !buffer.modified {
+            return None;
+        }
+        self.recovery_path(buffer.filename.as_ref()?)
+    }
+
+    /// Recovery file for the file at `file`
+    pub fn recovery_path(&self, file: &Path) -> Option<PathBuf> {
+        let absolute = std::path::absolute(file).ok()?;
+        Some(self.dir.as_ref()?.join(recovery_name(&absolute)))
+    }
+
+    /// Text left in a recovery file for `file` if it differs from `current`.
+    /// A recovery file holding `current` is of no use and is removed.
+    pub fn recovered_text(&self, file: &Path, current: &str) -> Option<String> {
+        let path = self.recovery_path(file)?;
+        let text = fs::read_to_string(&path).ok()?;
+        if text == current {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Some(text)
+    }
+
+    /// Remove the recovery file for `file`
+    pub fn remove(&mut self, file: &Path) -> Result<(), String> {
+        let path = self.recovery_path(file).ok_or("Autosave is turned off")?;
+        self.written.retain(|_, (written, _)| *written != path);
+        fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Recovery file name for the absolute path `file`: the path with `%` and
+/// separators percent-encoded, so different files never share a name
+fn recovery_name(file: &Path) -> String {
+    let mut name = String::new();
+    for ch in file.to_string_lossy().chars() {
+        match ch {
+            '%' => name.push_str("%25"),
+            '/' => name.push_str("%2F"),
+            '\\' => name.push_str("%5C"),
+            ':' => name.push_str("%3A"),
+            ch => name.push(ch),
+        }
+    }
+    name.push_str(".recover");
+    name
+}
+
+/// Replace the recovery file at `path` with `text`. The directory is only
+/// readable by the user, since recovery files hold their files' contents.
+fn write_recovery(path: &Path, text: &str) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    temp.write_all(text.as_bytes())?;
+    temp.as_file().sync_all()?;
+    temp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn autosave_in(dir: &Path) -> Autosave {
+        Autosave {
+            dir: Some(dir.join("recovery")),
+            ..Autosave::new()
+        }
+    }
+
+    #[test]
+    fn test_recovery_name() {
+        assert_eq!(
+            recovery_name(Path::new("/home/a%b/c.txt")),
+            "%2Fhome%2Fa%25b%2Fc.txt.recover"
+        );
+        // Names that would collide with a plain separator substitution
+        assert_ne!(
+            recovery_name(Path::new("/a/%2Fb")),
+            recovery_name(Path::new("/a//b"))
+        );
+    }
+
+    #[test]
+    fn test_run_writes_and_removes() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let file = dir.path().join("f.txt");
+        if let Err(e) = fs::write(&file, "one\n") {
+            panic!("Failed to write: {}", e);
+        }
+        let mut autosave = autosave_in(dir.path());
+        let mut buffer = match Buffer::from_file(&file) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Failed to load: {}", e),
+        };
+        buffer.insert(0, "zero\n");
+        let mut buffers = HashMap::from([(BufferId(1), buffer)]);
+
+        if let Err(e) = autosave.run(&buffers) {
+            panic!("Failed to autosave: {}", e);
+        }
+        assert_eq!(
+            autosave.recovered_text(&file, "one\n").as_deref(),
+            Some("zero\none\n")
+        );
+
+        // Saving the buffer makes its recovery file stale
+        if let Some(buffer) = buffers.get_mut(&BufferId(1))
+            && let Err(e) = buffer.save()
+        {
+            panic!("Failed to save: {}", e);
+        }
+        if let Err(e) = autosave.run(&buffers) {
+            panic!("Failed to autosave: {}", e);
+        }
+        assert_eq!(autosave.recovered_text(&file, ""), None);
+
+        // A normal exit removes what is left
+        if let Some(buffer) = buffers.get_mut(&BufferId(1)) {
+            buffer.insert(0, "again\n");
+        }
+        if let Err(e) = autosave.run(&buffers) {
+            panic!("Failed to autosave: {}", e);
+        }
+        autosave.discard();
+        assert_eq!(autosave.recovered_text(&file, ""), None);
+    }
+
+    #[test]
+    fn test_tick_waits_for_interval() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let file = dir.path().join("f.txt");
+        let mut buffer = Buffer::from_string("text\n".to_string());
+        buffer.filename = Some(file.clone());
+        buffer.modified = true;
+        let buffers = HashMap::from([(BufferId(1), buffer)]);
+
+        let mut autosave = autosave_in(dir.path());
+        if let Err(e) = autosave.tick(&buffers) {
+            panic!("Failed to autosave: {}", e);
+        }
+        assert_eq!(autosave.recovered_text(&file, ""), None);
+
+        autosave.interval = Duration::ZERO;
+        assert_eq!(autosave.deadline(), None);
+        autosave.interval = Duration::from_nanos(1);
+        std::thread::sleep(Duration::from_millis(1));
+        if let Err(e) = autosave.tick(&buffers) {
+            panic!("Failed to autosave: {}", e);
+        }
+        assert_eq!(
+            autosave.recovered_text(&file, "").as_deref(),
+            Some("text\n")
+        );
+    }
+}
@@ -13,14 +13,20 @@ use std::time::SystemTime;
 use tempfile::NamedTempFile;
 
 use crate::core::diagnostics::Diagnostic;
+use crate::core::encoding::FileEncoding;
 use crate::core::lexer::LexerState;
+use crate::core::line_ending::{self, LineEnding, LineEndingCounts};
 use crate::core::syntax::SyntaxLexerState;
 use crate::core::undo_group::{UndoGroup, UndoGrouper};
+use crate::core::vcs::VcsState;
 
 /// Maximum undo stack depth to prevent OOM from unbounded undo history
 /// Increased to 10000 to handle stress testing and rapid editing sessions
 const MAX_UNDO_DEPTH: usize = 10_000;
 
+/// Symbolic links followed before giving up, as in Linux
+const MAX_SYMLINK_DEPTH: usize = 40;
+
 /// Represents the type of buffer
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BufferKind {
@@ -28,6 +34,10 @@ pub enum BufferKind {
     Diagnostics,
     DiffOriginal,
     DiffModified,
+    /// Unified diff of a multi-file sed preview
+    DiffReview,
+    /// Script, state or output view of the sed debugger
+    SedDebug,
     Terminal,
     ShellOutput,
 }
@@ -54,6 +64,10 @@ pub struct Buffer {
     pub buffer_kind: BufferKind,
     /// Dirty flag (true if buffer has unsaved changes)
     pub modified: bool,
+    /// Line break inserted for new lines
+    pub line_ending: LineEnding,
+    /// Encoding the file was decoded from and is saved in
+    pub encoding: FileEncoding,
     /// Last modification time of the file on disk
     pub last_modified_time: Option<SystemTime>,
     /// Version counter for tracking buffer changes
@@ -68,6 +82,8 @@ pub struct Buffer {
     pub diagnostics: Vec<Diagnostic>,
     /// Syntax highlighting state cache for each line
     pub syntax_state_cache: Vec<SyntaxLexerState>,
+    /// How the text differs from the file in git `HEAD`
+    pub vcs: VcsState,
 }
 
 impl Buffer {
@@ -78,6 +94,8 @@ impl Buffer {
             filename: None,
             buffer_kind: BufferKind::Normal,
             modified: false,
+            line_ending: LineEnding::default(),
+            encoding: FileEncoding::default(),
             last_modified_time: None,
             version: 0,
             undo_stack: VecDeque::new(),
@@ -85,6 +103,7 @@ impl Buffer {
             undo_grouper: UndoGrouper::new(),
             diagnostics: Vec::new(),
             syntax_state_cache: Vec::new(),
+            vcs: VcsState::default(),
         }
     }
 
@@ -95,6 +114,8 @@ impl Buffer {
             filename: None,
             buffer_kind: BufferKind::Normal,
             modified: false,
+            line_ending: LineEndingCounts::count(content.as_ref()).dominant(),
+            encoding: FileEncoding::default(),
             last_modified_time: None,
             version: 0,
             undo_stack: VecDeque::new(),
@@ -102,6 +123,7 @@ impl Buffer {
             undo_grouper: UndoGrouper::new(),
             diagnostics: Vec::new(),
             syntax_state_cache: Vec::new(),
+            vcs: VcsState::default(),
         }
     }
 
@@ -124,30 +146,20 @@ impl Buffer {
             );
         }
 
-        // Load file content using streaming reader for reduced memory usage.
-        // Rope::from_reader streams the file directly into the rope structure,
-        // avoiding the need to hold the entire file in RAM as an intermediate String.
-        let file =
-            fs::File::open(&validated_path).map_err(|e| format!("Failed to open file: {}", e))?;
-        let reader = std::io::BufReader::new(file);
-
-        // Try to load as valid UTF-8 first, fall back to lossy conversion if invalid
-        let rope = match Rope::from_reader(reader) {
-            Ok(r) => r,
-            Err(_) => {
-                // File contains invalid UTF-8 - use lossy conversion
-                let bytes =
-                    fs::read(&validated_path).map_err(|e| format!("Failed to read file: {}", e))?;
-                let content = String::from_utf8_lossy(&bytes);
-                Rope::from_str(&content)
-            }
-        };
+        // Bytes the detected encoding can't decode become escape markers, so
+        // saving writes them back unchanged
+        let bytes = fs::read(&validated_path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let encoding = FileEncoding::detect(&bytes);
+        let rope = Rope::from_str(&encoding.decode(&bytes));
 
+        let line_ending = Self::count_line_endings(&rope).dominant();
         Ok(Self {
             rope,
             filename: Some(validated_path),
             buffer_kind: BufferKind::Normal,
             modified: false,
+            line_ending,
+            encoding,
             last_modified_time: modified_time,
             version: 0,
             undo_stack: VecDeque::new(),
@@ -155,6 +167,7 @@ impl Buffer {
             undo_grouper: UndoGrouper::new(),
             diagnostics: Vec::new(),
             syntax_state_cache: Vec::new(),
+            vcs: VcsState::default(),
         })
     }
 
@@ -297,10 +310,8 @@ impl Buffer {
         if line_idx >= self.rope.len_lines() {
             return None;
         }
-        let line = self.rope.line(line_idx);
-        // Remove trailing newline if present
-        let s = line.to_string();
-        Some(s.trim_end_matches('\n').to_string())
+        let line = self.rope.line(line_idx).to_string();
+        Some(line_ending::trim_line_break(&line).to_string())
     }
 
     /// Get content of a line with newline preserved
@@ -311,6 +322,28 @@ impl Buffer {
         Some(self.rope.line(line_idx).to_string())
     }
 
+    /// Length in bytes of the line break ending a line; 0 for the last line
+    pub fn line_break_len(&self, line_idx: usize) -> usize {
+        self.line_with_newline(line_idx).map_or(0, |line| {
+            line.len() - line_ending::trim_line_break(&line).len()
+        })
+    }
+
+    /// How many line breaks of each style the buffer has
+    pub fn line_ending_counts(&self) -> LineEndingCounts {
+        Self::count_line_endings(&self.rope)
+    }
+
+    /// Ropey never splits a CRLF across chunks, so they can be counted
+    /// one at a time
+    fn count_line_endings(rope: &Rope) -> LineEndingCounts {
+        let mut counts = LineEndingCounts::default();
+        for chunk in rope.chunks() {
+            counts.add(LineEndingCounts::count(chunk));
+        }
+        counts
+    }
+
     /// Get length of a specific line in bytes (including newline)
     pub fn line_len(&self, line_idx: usize) -> Option<usize> {
         if line_idx >= self.rope.len_lines() {
@@ -362,6 +395,39 @@ impl Buffer {
         self.redo_stack.clear();
     }
 
+    /// Replace `len` bytes at `pos` with `text` as a single undo step
+    /// described by `description`
+    pub fn replace(&mut self, pos: usize, len: usize, text: &str, description: &str) {
+        let pos = pos.min(self.rope.len_bytes());
+        let len = len.min(self.rope.len_bytes() - pos);
+        let start_char = self.rope.byte_to_char(pos);
+        let end_char = self.rope.byte_to_char(pos + len);
+        let deleted_text = Rope::from(self.rope.slice(start_char..end_char));
+
+        self.rope.remove(start_char..end_char);
+        self.rope.insert(start_char, text);
+        self.mark_modified();
+
+        // Undo replays the group backwards: remove the insert, then restore
+        let mut group = UndoGroup::with_description(description);
+        if len > 0 {
+            group.add_edit(Edit::Delete {
+                pos,
+                text: deleted_text,
+            });
+        }
+        if !text.is_empty() {
+            group.add_edit(Edit::Insert {
+                pos,
+                text: Rope::from_str(text),
+            });
+        }
+        if !group.is_empty() {
+            self.push_group(group);
+        }
+        self.redo_stack.clear();
+    }
+
     /// Insert a single character (optimized path)
     pub fn insert_char(&mut self, pos: usize, ch: char) {
         let pos = pos.min(self.rope.len_bytes());
@@ -467,19 +533,78 @@ impl Buffer {
     // ==================== File Operations ====================
 
     /// Save buffer to file
+    ///
+    /// The text goes to a temporary file next to the file, which then
+    /// replaces it with the original's permissions and (where allowed)
+    /// ownership. Symbolic links are followed so their target is written. A
+    /// file with other hard links, or one that doesn't exist yet, is written
+    /// in place instead, so the links keep sharing it and a new file gets the
+    /// usual permissions.
     pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let path = self.filename.as_ref().ok_or("No filename set for buffer")?;
+        let target = Self::resolve_symlinks(path)?;
+        let metadata = fs::metadata(&target).ok();
+
+        if let Some(metadata) = &metadata {
+            // Replacing the file would bypass its own permissions, so check
+            // them first; opening without truncating changes nothing
+            let writable = !metadata.permissions().readonly()
+                && fs::OpenOptions::new().write(true).open(&target).is_ok();
+            if !writable {
+                return Err(format!("File is read-only: {}", target.display()).into());
+            }
+        }
+
+        #[cfg(unix)]
+        let hard_linked = {
+            use std::os::unix::fs::MetadataExt;
+            metadata
+                .as_ref()
+                .is_some_and(|metadata| metadata.nlink() > 1)
+        };
+        #[cfg(not(unix))]
+        let hard_linked = false;
+
+        match metadata {
+            Some(metadata) if !hard_linked => self.replace_file(&target, &metadata)?,
+            _ => {
+                // Encode everything first so a failure leaves the file alone
+                let mut contents = self.encoding.header().to_vec();
+                for chunk in self.rope.chunks() {
+                    contents.extend_from_slice(&self.encoding.encode(chunk)?);
+                }
+                let mut file = fs::File::create(&target)?;
+                file.write_all(&contents)?;
+                file.sync_all()?;
+            }
+        }
+
+        // Update state
+        self.modified = false;
+        self.last_modified_time = fs::metadata(&target).ok().and_then(|m| m.modified().ok());
+        // A commit may have been made since HEAD was last read
+        self.vcs.invalidate();
+
+        Ok(())
+    }
 
-        // Write to temp file first for atomic save
-        let parent = match path.parent() {
-            Some(p) => p,
-            None => Path::new("."),
+    /// Atomically replace `target` with the buffer's text, keeping the
+    /// permissions and ownership in `metadata`
+    fn replace_file(
+        &self,
+        target: &Path,
+        metadata: &fs::Metadata,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let parent = match target.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
         };
         let mut temp_file = NamedTempFile::new_in(parent)?;
 
         // Write content chunk by chunk to avoid large allocations
+        temp_file.write_all(self.encoding.header())?;
         for chunk in self.rope.chunks() {
-            temp_file.write_all(chunk.as_bytes())?;
+            temp_file.write_all(&self.encoding.encode(chunk)?)?;
         }
         temp_file.flush()?;
 
@@ -487,16 +612,47 @@ impl Buffer {
         // Without this, a system crash after persist() could result in data loss.
         temp_file.as_file().sync_all()?;
 
-        // Atomic rename
-        temp_file.persist(path)?;
-
-        // Update state
-        self.modified = false;
-        self.last_modified_time = fs::metadata(path).ok().and_then(|m| m.modified().ok());
+        fs::set_permissions(temp_file.path(), metadata.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Changing the owner needs privileges; carry on without
+            let _ = std::os::unix::fs::chown(
+                temp_file.path(),
+                Some(metadata.uid()),
+                Some(metadata.gid()),
+            );
+        }
 
+        // Atomic rename
+        temp_file.persist(target)?;
         Ok(())
     }
 
+    /// The file `path` names after following symbolic links, including a
+    /// last one that points nowhere yet
+    fn resolve_symlinks(path: &Path) -> Result<PathBuf, String> {
+        let mut target = path.to_path_buf();
+        for _ in 0..MAX_SYMLINK_DEPTH {
+            match fs::symlink_metadata(&target) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    let link = fs::read_link(&target)
+                        .map_err(|e| format!("Failed to read link {}: {}", target.display(), e))?;
+                    // A relative link is relative to its directory
+                    target = match target.parent() {
+                        Some(parent) => parent.join(link),
+                        None => link,
+                    };
+                }
+                _ => return Ok(target),
+            }
+        }
+        Err(format!(
+            "Too many levels of symbolic links: {}",
+            path.display()
+        ))
+    }
+
     /// Save buffer to a specific file (save as)
     pub fn save_as(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
         self.filename = Some(path.as_ref().to_path_buf());
@@ -508,7 +664,10 @@ impl Buffer {
     /// Push an edit to the undo stack, with smart grouping
     fn push_edit(&mut self, edit: Edit) {
         let should_group = if let Some(last_group) = self.undo_stack.back() {
-            if let Some(prev_edit) = last_group.edits.last() {
+            // A described group is one whole operation and is never extended
+            if last_group.description.is_some() {
+                false
+            } else if let Some(prev_edit) = last_group.edits.last() {
                 // For now, we use LexerState::Normal as we don't have full lexer integration here yet
                 self.undo_grouper
                     .should_group(prev_edit, &edit, LexerState::Normal)
@@ -526,13 +685,18 @@ impl Buffer {
         } else {
             let mut group = UndoGroup::new();
             group.add_edit(edit);
-            if self.undo_stack.len() >= MAX_UNDO_DEPTH {
-                self.undo_stack.pop_front(); // O(1) discard oldest
-            }
-            self.undo_stack.push_back(group);
+            self.push_group(group);
         }
     }
 
+    /// Push a new group to the undo stack, discarding the oldest when full
+    fn push_group(&mut self, group: UndoGroup) {
+        if self.undo_stack.len() >= MAX_UNDO_DEPTH {
+            self.undo_stack.pop_front(); // O(1) discard oldest
+        }
+        self.undo_stack.push_back(group);
+    }
+
     /// Mark buffer as modified
     fn mark_modified(&mut self) {
         self.modified = true;
@@ -571,10 +735,9 @@ impl Buffer {
             .ok_or("No filename set for buffer")?
             .clone();
 
-        use std::io::BufReader;
-        let file = std::fs::File::open(&path)?;
-        let reader = BufReader::new(file);
-        self.rope = Rope::from_reader(reader)?;
+        let bytes = fs::read(&path)?;
+        self.rope = Rope::from_str(&self.encoding.decode(&bytes));
+        self.line_ending = self.line_ending_counts().dominant();
 
         self.modified = false;
         self.last_modified_time = fs::metadata(&path).ok().and_then(|m| m.modified().ok());
@@ -585,6 +748,15 @@ impl Buffer {
         Ok(())
     }
 
+    /// Reload buffer from disk, decoding it as `encoding`
+    pub fn revisit_with_encoding(
+        &mut self,
+        encoding: FileEncoding,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let previous = std::mem::replace(&mut self.encoding, encoding);
+        self.reload().inspect_err(|_| self.encoding = previous)
+    }
+
     // ==================== Diagnostics ====================
 
     /// Add a diagnostic to this buffer
@@ -910,6 +1082,25 @@ mod tests {
         assert_eq!(buf.to_string(), "");
     }
 
+    #[test]
+    fn test_replace_is_one_undo_step() {
+        let mut buf = Buffer::new();
+        buf.insert(0, "fn a() {}\nfn b() {}\n");
+        let before = buf.undo_stack.len();
+
+        buf.replace(10, 9, "fn c() { 1 }", "sed region");
+        assert_eq!(buf.to_string(), "fn a() {}\nfn c() { 1 }\n");
+        assert_eq!(buf.undo_stack.len(), before + 1);
+
+        // Typing afterwards starts a new group
+        buf.insert_char(0, 'x');
+        buf.undo();
+        buf.undo();
+        assert_eq!(buf.to_string(), "fn a() {}\nfn b() {}\n");
+        buf.redo();
+        assert_eq!(buf.to_string(), "fn a() {}\nfn c() { 1 }\n");
+    }
+
     #[test]
     fn test_find() {
         let mut buf = Buffer::new();
@@ -961,4 +1152,72 @@ mod tests {
             );
         }
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_mode_and_links() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let script = dir.path().join("script.sh");
+        let symlink = dir.path().join("link.sh");
+        let hard_link = dir.path().join("hard.sh");
+        let created = fs::write(&script, "echo old\n")
+            .and_then(|_| fs::set_permissions(&script, fs::Permissions::from_mode(0o750)))
+            .and_then(|_| std::os::unix::fs::symlink("script.sh", &symlink))
+            .and_then(|_| fs::hard_link(&script, &hard_link));
+        if let Err(e) = created {
+            panic!("Failed to set up files: {}", e);
+        }
+
+        let save = |path: &Path, text: &str| {
+            let mut buffer = match Buffer::from_file(path) {
+                Ok(buffer) => buffer,
+                Err(e) => panic!("Failed to load: {}", e),
+            };
+            buffer.replace(0, buffer.len(), text, "edit");
+            buffer.save()
+        };
+
+        // Through the symlink, into the file shared by both names
+        if let Err(e) = save(&symlink, "echo new\n") {
+            panic!("Failed to save: {}", e);
+        }
+        assert!(fs::symlink_metadata(&symlink).is_ok_and(|m| m.file_type().is_symlink()));
+        assert_eq!(
+            fs::read_to_string(&hard_link).ok().as_deref(),
+            Some("echo new\n")
+        );
+        match fs::metadata(&script) {
+            Ok(metadata) => assert_eq!(metadata.permissions().mode() & 0o777, 0o750),
+            Err(e) => panic!("Failed to stat file: {}", e),
+        }
+
+        // Without the hard link the file is replaced, keeping its mode
+        if let Err(e) = fs::remove_file(&hard_link) {
+            panic!("Failed to remove link: {}", e);
+        }
+        if let Err(e) = save(&script, "echo newer\n") {
+            panic!("Failed to save: {}", e);
+        }
+        match fs::metadata(&script) {
+            Ok(metadata) => assert_eq!(metadata.permissions().mode() & 0o777, 0o750),
+            Err(e) => panic!("Failed to stat file: {}", e),
+        }
+
+        if let Err(e) = fs::set_permissions(&script, fs::Permissions::from_mode(0o444)) {
+            panic!("Failed to set permissions: {}", e);
+        }
+        match save(&script, "echo lost\n") {
+            Ok(()) => panic!("Saved a read-only file"),
+            Err(e) => assert!(e.to_string().starts_with("File is read-only")),
+        }
+        assert_eq!(
+            fs::read_to_string(&script).ok().as_deref(),
+            Some("echo newer\n")
+        );
+    }
 }
@@ -0,0 +1,391 @@
+//! Merge conflict resolution
+//!
+//! Shows a file with conflict markers as three panes: our version and their
+//! version of the whole file side by side on top, and the file being merged
+//! below. Each conflict is resolved with a single key in either top pane.
+
+use crate::core::app::EditorApp;
+use crate::core::buffer::{Buffer, BufferKind};
+use crate::core::command::Command;
+use crate::core::conflict::{self, Resolution};
+use crate::core::dispatcher::DispatchResult;
+use crate::core::id::BufferId;
+use crate::sed::diff::{DiffView, Hunk};
+use std::path::PathBuf;
+
+/// An active conflict resolution session
+pub struct ConflictState {
+    /// The file being merged
+    pub buffer_id: BufferId,
+    /// The `DiffOriginal` (ours) and `DiffModified` (theirs) views
+    pub view_buffer_ids: (BufferId, BufferId),
+    /// Differences between the two views, for highlighting
+    pub hunks: Vec<Hunk>,
+    /// First line of each conflict in the ours and theirs views
+    pub ours_starts: Vec<usize>,
+    pub theirs_starts: Vec<usize>,
+    /// Indexed like `ours_starts`
+    pub resolved: Vec<bool>,
+    /// Index of the current conflict among those still in the buffer
+    pub current: usize,
+}
+
+/// A note for the message line when `buffer` has conflict markers
+pub fn conflict_notice(buffer: &Buffer) -> Option<String> {
+    match conflict::find_conflicts(&buffer.to_string()).len() {
+        0 => None,
+        1 => Some("1 merge conflict (M-x resolve-conflicts)".to_string()),
+        count => Some(format!("{} merge conflicts (M-x resolve-conflicts)", count)),
+    }
+}
+
+#[derive(Clone)]
+pub struct ResolveConflictsCommand;
+
+impl Command for ResolveConflictsCommand {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.active_buffer().is_none() {
+            return DispatchResult::NotHandled;
+        }
+        if let Err(e) = start_conflict_mode(app) {
+            app.message = Some(format!("Error: {}", e));
+        }
+        DispatchResult::Success
+    }
+}
+
+/// Open the three-pane view over the conflicts of the active buffer
+pub fn start_conflict_mode(app: &mut EditorApp) -> Result<(), String> {
+    let buffer_id = app
+        .windows
+        .get(&app.active_window)
+        .map(|window| window.buffer_id)
+        .ok_or("No active window")?;
+    let buffer = app.buffers.get(&buffer_id).ok_or("No active buffer")?;
+    if buffer.buffer_kind() != BufferKind::Normal {
+        return Err("Conflicts can only be resolved in a file buffer".to_string());
+    }
+    let text = buffer.to_string();
+    let name = buffer.display_name();
+    let conflicts = conflict::find_conflicts(&text);
+    if conflicts.is_empty() {
+        return Err("No merge conflicts".to_string());
+    }
+
+    let (ours, ours_starts) = conflict::resolve_all(&text, &conflicts, Resolution::Ours);
+    let (theirs, theirs_starts) = conflict::resolve_all(&text, &conflicts, Resolution::Theirs);
+    let hunks = DiffView::new(ours.clone(), theirs.clone()).change_hunks();
+
+    let mut add_view = |title: String, text: String, kind: BufferKind| {
+        let mut view = Buffer::from_string(text);
+        view.filename = Some(PathBuf::from(title));
+        view.buffer_kind = kind;
+        app.add_buffer(view)
+    };
+    let ours_id = add_view(format!("*Ours {}*", name), ours, BufferKind::DiffOriginal);
+    let theirs_id = add_view(
+        format!("*Theirs {}*", name),
+        theirs,
+        BufferKind::DiffModified,
+    );
+
+    // Ours and theirs side by side above the file being merged
+    app.delete_other_windows();
+    let ours_window = app.active_window;
+    app.split_window_horizontally();
+    if let Some(window) = app.windows.get_mut(&app.active_window) {
+        window.buffer_id = buffer_id;
+    }
+    app.active_window = ours_window;
+    if let Some(window) = app.windows.get_mut(&ours_window) {
+        window.buffer_id = ours_id;
+    }
+    app.split_window_vertically();
+    if let Some(window) = app.windows.get_mut(&app.active_window) {
+        window.buffer_id = theirs_id;
+    }
+    app.active_window = ours_window;
+
+    app.conflict_state = Some(ConflictState {
+        buffer_id,
+        view_buffer_ids: (ours_id, theirs_id),
+        hunks,
+        resolved: vec![false; ours_starts.len()],
+        ours_starts,
+        theirs_starts,
+        current: 0,
+    });
+    jump_to_conflict(app);
+    app.message = Some(
+        "Conflicts (o: ours, t: theirs, b: base, a: both, n/j: next, p/k: previous, q: quit)"
+            .to_string(),
+    );
+    Ok(())
+}
+
+/// The conflicts still in the merged buffer
+fn remaining_conflicts(app: &EditorApp) -> Vec<conflict::Conflict> {
+    app.conflict_state
+        .as_ref()
+        .and_then(|state| app.buffers.get(&state.buffer_id))
+        .map(|buffer| conflict::find_conflicts(&buffer.to_string()))
+        .unwrap_or_default()
+}
+
+/// Move every pane to the current conflict
+fn jump_to_conflict(app: &mut EditorApp) {
+    let remaining = remaining_conflicts(app);
+    let Some(state) = app.conflict_state.as_mut() else {
+        return;
+    };
+    state.current = state.current.min(remaining.len().saturating_sub(1));
+    let Some(current) = remaining.get(state.current) else {
+        return;
+    };
+
+    // The n-th conflict left is the n-th one not yet resolved here, unless
+    // markers were edited by hand
+    let unresolved: Vec<usize> = (0..state.resolved.len())
+        .filter(|&index| !state.resolved[index])
+        .collect();
+    let original = (unresolved.len() == remaining.len()).then(|| unresolved[state.current]);
+
+    let targets = [
+        (state.buffer_id, Some(current.start_line)),
+        (
+            state.view_buffer_ids.0,
+            original.map(|index| state.ours_starts[index]),
+        ),
+        (
+            state.view_buffer_ids.1,
+            original.map(|index| state.theirs_starts[index]),
+        ),
+    ];
+    for window in app.windows.values_mut() {
+        let Some(line) = targets
+            .iter()
+            .find(|(buffer_id, _)| *buffer_id == window.buffer_id)
+            .and_then(|(_, line)| *line)
+        else {
+            continue;
+        };
+        if let Some(buffer) = app.buffers.get(&window.buffer_id) {
+            window.cursor_y = line;
+            window.cursor_x = 0;
+            window.scroll_offset = line.saturating_sub(5);
+            window.update_visual_cursor(buffer);
+            window.ensure_cursor_visible(buffer);
+        }
+    }
+}
+
+/// Replace the current conflict with the side picked by `resolution`
+fn resolve_current(app: &mut EditorApp, resolution: Resolution) -> Result<(), String> {
+    let remaining = remaining_conflicts(app);
+    let state = app
+        .conflict_state
+        .as_mut()
+        .ok_or("No conflicts in progress")?;
+    let current = remaining
+        .get(state.current)
+        .ok_or("No conflicts remain; save the file to finish")?;
+    let text = current
+        .resolve(resolution)
+        .ok_or("This conflict has no base section")?;
+
+    let unresolved: Vec<usize> = (0..state.resolved.len())
+        .filter(|&index| !state.resolved[index])
+        .collect();
+    if unresolved.len() == remaining.len() {
+        state.resolved[unresolved[state.current]] = true;
+    }
+
+    let buffer = app
+        .buffers
+        .get_mut(&state.buffer_id)
+        .ok_or("The buffer was closed")?;
+    let start = buffer
+        .line_to_byte(current.start_line)
+        .unwrap_or(buffer.len());
+    let end = buffer
+        .line_to_byte(current.end_line + 1)
+        .unwrap_or(buffer.len());
+    buffer.replace(start, end - start, &text, "resolve conflict");
+
+    app.message = Some(match remaining.len() - 1 {
+        0 => "All conflicts resolved; save the file to finish".to_string(),
+        1 => "1 conflict left".to_string(),
+        left => format!("{} conflicts left", left),
+    });
+    jump_to_conflict(app);
+    Ok(())
+}
+
+/// Run `resolution` as a command
+fn resolve_command(app: &mut EditorApp, resolution: Resolution) -> DispatchResult {
+    if app.conflict_state.is_none() {
+        return DispatchResult::NotHandled;
+    }
+    if let Err(e) = resolve_current(app, resolution) {
+        app.message = Some(format!("Error: {}", e));
+    }
+    DispatchResult::Success
+}
+
+#[derive(Clone)]
+pub struct ConflictKeepOurs;
+
+impl Command for ConflictKeepOurs {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        resolve_command(app, Resolution::Ours)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConflictKeepTheirs;
+
+impl Command for ConflictKeepTheirs {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        resolve_command(app, Resolution::Theirs)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConflictKeepBase;
+
+impl Command for ConflictKeepBase {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        resolve_command(app, Resolution::Base)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConflictKeepBoth;
+
+impl Command for ConflictKeepBoth {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        resolve_command(app, Resolution::Both)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConflictNext;
+
+impl Command for ConflictNext {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.conflict_state.as_mut() else {
+            return DispatchResult::NotHandled;
+        };
+        state.current += 1;
+        jump_to_conflict(app);
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct ConflictPrevious;
+
+impl Command for ConflictPrevious {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.conflict_state.as_mut() else {
+            return DispatchResult::NotHandled;
+        };
+        state.current = state.current.saturating_sub(1);
+        jump_to_conflict(app);
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct ConflictQuit;
+
+impl Command for ConflictQuit {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.conflict_state.take() else {
+            return DispatchResult::NotHandled;
+        };
+        app.delete_other_windows();
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = state.buffer_id;
+        }
+        app.buffers.remove(&state.view_buffer_ids.0);
+        app.buffers.remove(&state.view_buffer_ids.1);
+        DispatchResult::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERGE: &str = "\
+fn main() {
+<<<<<<< HEAD
+    println!(\"ours\");
+=======
+    println!(\"theirs\");
+>>>>>>> feature
+    let x = 1;
+<<<<<<< HEAD
+    a();
+||||||| base
+    base();
+=======
+    b();
+>>>>>>> feature
+}
+";
+
+    #[test]
+    fn test_resolve_conflicts() {
+        let mut app = EditorApp::new();
+        if let Some(buffer) = app.active_buffer_mut() {
+            buffer.insert(0, MERGE);
+        }
+        let buffer_id = app.windows[&app.active_window].buffer_id;
+        if let Some(buffer) = app.buffers.get(&buffer_id) {
+            assert_eq!(
+                conflict_notice(buffer).as_deref(),
+                Some("2 merge conflicts (M-x resolve-conflicts)")
+            );
+        }
+
+        if let Err(e) = start_conflict_mode(&mut app) {
+            panic!("Failed to start: {}", e);
+        }
+        assert_eq!(app.windows.len(), 3);
+
+        ConflictKeepBase.execute(&mut app, 1);
+        assert!(
+            app.message
+                .as_deref()
+                .is_some_and(|m| m.contains("no base"))
+        );
+        ConflictKeepTheirs.execute(&mut app, 1);
+        ConflictKeepBase.execute(&mut app, 1);
+        let text = |app: &EditorApp| match app.buffers.get(&buffer_id) {
+            Some(buffer) => buffer.to_string(),
+            None => panic!("Buffer disappeared"),
+        };
+        assert_eq!(
+            text(&app),
+            "fn main() {\n    println!(\"theirs\");\n    let x = 1;\n    base();\n}\n"
+        );
+        assert_eq!(
+            app.message.as_deref(),
+            Some("All conflicts resolved; save the file to finish")
+        );
+
+        // Each resolution is one undo step
+        if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+            buffer.undo();
+        }
+        assert_eq!(conflict::find_conflicts(&text(&app)).len(), 1);
+        ConflictKeepBoth.execute(&mut app, 1);
+        assert!(text(&app).contains("    a();\n    b();\n"));
+
+        ConflictQuit.execute(&mut app, 1);
+        assert_eq!(app.windows.len(), 1);
+        assert_eq!(app.buffers.len(), 1);
+    }
+}
@@ -1,9 +1,14 @@
 use crate::core::app::EditorApp;
+use crate::core::buffer::{Buffer, BufferKind};
 use crate::core::command::Command;
-use crate::core::dispatcher::DispatchResult;
+use crate::core::dispatcher::{DispatchResult, InputAction};
+use crate::core::id::BufferId;
 use crate::sed::SedConfig;
-use crate::sed::diff::{DiffState, DiffView};
+use crate::sed::diff::{DiffRegion, DiffState, DiffView, Hunk, HunkStatus};
+use std::fs;
 use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone)]
 pub struct SedPreviewCommand;
@@ -17,24 +22,77 @@ impl Command for SedPreviewCommand {
         // Prompt for sed expression
         DispatchResult::NeedsInput {
             prompt: "Sed preview expression: ".to_string(),
-            action: crate::core::dispatcher::InputAction::SedPreview,
+            action: InputAction::SedPreview,
         }
     }
 }
 
+#[derive(Clone)]
+pub struct SedPreviewRegionCommand;
+
+impl Command for SedPreviewRegionCommand {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.active_buffer().is_none() {
+            return DispatchResult::NotHandled;
+        }
+        if region_bounds(app).is_none() {
+            return DispatchResult::Info("No mark set".to_string());
+        }
+
+        DispatchResult::NeedsInput {
+            prompt: "Sed preview region expression: ".to_string(),
+            action: InputAction::SedPreviewRegion,
+        }
+    }
+}
+
+/// Byte range between mark and point in the active window
+fn region_bounds(app: &EditorApp) -> Option<Range<usize>> {
+    let window = app.windows.get(&app.active_window)?;
+    let buffer = app.buffers.get(&window.buffer_id)?;
+    let mark = window.mark?;
+
+    let cursor_byte = window.get_byte_offset(buffer).unwrap_or(0);
+    let mut mark_window = window.clone();
+    mark_window.cursor_x = mark.0;
+    mark_window.cursor_y = mark.1;
+    let mark_byte = mark_window.get_byte_offset(buffer).unwrap_or(0);
+
+    Some(cursor_byte.min(mark_byte)..cursor_byte.max(mark_byte))
+}
+
 pub fn start_sed_diff(
     app: &mut EditorApp,
     expression: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let (original_content, original_buffer_id) = {
+    start_diff_preview(app, expression, None)
+}
+
+/// Preview `expression` run over the region only; accepting replaces the
+/// region as a single undo step
+pub fn start_sed_region_diff(
+    app: &mut EditorApp,
+    expression: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let region = region_bounds(app).ok_or("No mark set")?;
+    if region.is_empty() {
+        return Err("The region is empty".into());
+    }
+    start_diff_preview(app, expression, Some(region))
+}
+
+fn start_diff_preview(
+    app: &mut EditorApp,
+    expression: &str,
+    region: Option<Range<usize>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let original_content = {
         let buffer = app.active_buffer().ok_or("No active buffer")?;
-        let window = app
-            .windows
-            .get(&app.active_window)
-            .ok_or("No active window")?;
-        (buffer.to_string(), window.buffer_id)
+        match &region {
+            Some(range) => buffer.get_range_as_string(range.start, range.len()),
+            None => buffer.to_string(),
+        }
     };
-    let original_window_id = app.active_window;
 
     let mut config = SedConfig::new();
     config.add_script(expression).map_err(|e| e.to_string())?;
@@ -44,22 +102,59 @@ pub fn start_sed_diff(
     let modified_content = String::from_utf8(modified_output)?;
 
     let diff_view = DiffView::new(original_content.clone(), modified_content.clone());
-    let hunks = diff_view.compute_hunks();
+    let hunks = diff_view.change_hunks();
 
     if hunks.is_empty() {
         app.message = Some("No changes".to_string());
         return Ok(());
     }
+    let region = region.map(|range| DiffRegion {
+        start: range.start,
+        original: original_content.clone(),
+        modified: modified_content.clone(),
+    });
+    app.message = Some(if region.is_some() {
+        "Region preview (y: apply to region, n/j: next, k: previous, q: quit)".to_string()
+    } else {
+        "Sed preview (y: accept, r: reject, a: accept all, u: undo accept, s: split at cursor, w: write patch, n/k: next/previous, q: quit)"
+            .to_string()
+    });
+
+    open_diff_view(
+        app,
+        ("*Diff Original*", original_content),
+        ("*Diff Modified*", modified_content),
+        hunks,
+    );
+    if let Some(state) = app.diff_state.as_mut() {
+        state.region = region;
+    }
+    Ok(())
+}
+
+/// Show two texts side by side in `DiffOriginal`/`DiffModified` buffers and
+/// start a diff session over `hunks`; the active buffer is the original
+fn open_diff_view(
+    app: &mut EditorApp,
+    (original_name, original_content): (&str, String),
+    (modified_name, modified_content): (&str, String),
+    hunks: Vec<Hunk>,
+) {
+    let original_window_id = app.active_window;
+    let original_buffer_id = match app.windows.get(&original_window_id) {
+        Some(window) => window.buffer_id,
+        None => return,
+    };
 
     // Create buffers
-    let mut original_diff_buffer = crate::core::buffer::Buffer::from_string(original_content);
-    original_diff_buffer.filename = Some(std::path::PathBuf::from("*Diff Original*"));
-    original_diff_buffer.buffer_kind = crate::core::buffer::BufferKind::DiffOriginal;
+    let mut original_diff_buffer = Buffer::from_string(original_content);
+    original_diff_buffer.filename = Some(PathBuf::from(original_name));
+    original_diff_buffer.buffer_kind = BufferKind::DiffOriginal;
     let obid = app.add_buffer(original_diff_buffer);
 
-    let mut modified_diff_buffer = crate::core::buffer::Buffer::from_string(modified_content);
-    modified_diff_buffer.filename = Some(std::path::PathBuf::from("*Diff Modified*"));
-    modified_diff_buffer.buffer_kind = crate::core::buffer::BufferKind::DiffModified;
+    let mut modified_diff_buffer = Buffer::from_string(modified_content);
+    modified_diff_buffer.filename = Some(PathBuf::from(modified_name));
+    modified_diff_buffer.buffer_kind = BufferKind::DiffModified;
     let mbid = app.add_buffer(modified_diff_buffer);
 
     // Split window vertically
@@ -79,18 +174,323 @@ pub fn start_sed_diff(
     }
 
     app.diff_state = Some(DiffState {
+        statuses: vec![HunkStatus::Pending; hunks.len()],
+        accept_history: Vec::new(),
         hunks,
         current_hunk: 0,
         original_buffer_id,
         original_window_id,
+        region: None,
+        modified_buffer_id: None,
+        view_buffer_ids: (obid, mbid),
     });
 
     // Jump to first hunk
     jump_to_hunk(app, 0);
+}
+
+#[derive(Clone)]
+pub struct DiffBuffersCommand;
+
+impl Command for DiffBuffersCommand {
+    fn execute(&self, _app: &mut EditorApp, _count: usize) -> DispatchResult {
+        DispatchResult::NeedsInput {
+            prompt: "Diff buffer or file: ".to_string(),
+            action: InputAction::DiffBuffers,
+        }
+    }
+}
+
+/// Compare two buffers side by side. Each of `left` and `right` names an
+/// open buffer or a file, which is opened if needed.
+pub fn start_buffer_diff(
+    app: &mut EditorApp,
+    left: &str,
+    right: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let left_id = resolve_buffer(app, left)?;
+    let right_id = resolve_buffer(app, right)?;
+    if left_id == right_id {
+        return Err("Can't diff a buffer with itself".into());
+    }
+
+    let side = |id: BufferId| {
+        app.buffers
+            .get(&id)
+            .map(|buffer| {
+                (
+                    format!("*Diff {}*", buffer.display_name()),
+                    buffer.to_string(),
+                )
+            })
+            .ok_or("Buffer disappeared")
+    };
+    let (left_name, left_text) = side(left_id)?;
+    let (right_name, right_text) = side(right_id)?;
+
+    let hunks = DiffView::new(left_text.clone(), right_text.clone()).change_hunks();
+    if hunks.is_empty() {
+        app.message = Some("No differences".to_string());
+        return Ok(());
+    }
+
+    // The left buffer is the one restored when the diff is closed
+    if let Some(window) = app.windows.get_mut(&app.active_window) {
+        window.buffer_id = left_id;
+    }
+    open_diff_view(
+        app,
+        (&left_name, left_text),
+        (&right_name, right_text),
+        hunks,
+    );
+    if let Some(state) = app.diff_state.as_mut() {
+        state.modified_buffer_id = Some(right_id);
+    }
+    app.message = Some(
+        "Diff (<: copy hunk to the left, >: copy hunk to the right, n/j: next, k: previous, q: quit)"
+            .to_string(),
+    );
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct DiffBufferWithFile;
+
+impl Command for DiffBufferWithFile {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.active_buffer().is_none() {
+            return DispatchResult::NotHandled;
+        }
+        if let Err(e) = start_file_diff(app) {
+            app.message = Some(format!("Diff Error: {}", e));
+        }
+        DispatchResult::Success
+    }
+}
 
+/// Compare the active buffer with its file on disk. The disk side is a
+/// snapshot that hunks can be reverted to but not copied into.
+pub fn start_file_diff(app: &mut EditorApp) -> Result<(), Box<dyn std::error::Error>> {
+    let buffer = app.active_buffer().ok_or("No active buffer")?;
+    let path = buffer.filename.clone().ok_or("The buffer has no file")?;
+    let name = buffer.display_name();
+    let buffer_text = buffer.to_string();
+    let disk_text = Buffer::from_file(&path)?.to_string();
+
+    let hunks = DiffView::new(buffer_text.clone(), disk_text.clone()).change_hunks();
+    if hunks.is_empty() {
+        app.message = Some(format!("No differences from {}", name));
+        return Ok(());
+    }
+
+    open_diff_view(
+        app,
+        (&format!("*Buffer {}*", name), buffer_text),
+        (&format!("*Disk {}*", name), disk_text),
+        hunks,
+    );
+    if let Some(state) = app.diff_state.as_mut() {
+        state.modified_buffer_id = Some(state.view_buffer_ids.1);
+    }
+    app.message = Some(
+        "Buffer vs. disk (<: revert hunk to the disk version, n/j: next, k: previous, q: quit)"
+            .to_string(),
+    );
+    Ok(())
+}
+
+/// Compare the active buffer with the text a crash left in its recovery
+/// file. Accepting a hunk takes the recovered version into the buffer.
+pub fn start_recovery_diff(
+    app: &mut EditorApp,
+    recovered: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let buffer = app.active_buffer().ok_or("No active buffer")?;
+    let name = buffer.display_name();
+    let buffer_text = buffer.to_string();
+
+    let hunks = DiffView::new(buffer_text.clone(), recovered.clone()).change_hunks();
+    if hunks.is_empty() {
+        app.message = Some(format!("No differences from the recovery data of {}", name));
+        return Ok(());
+    }
+
+    open_diff_view(
+        app,
+        (&format!("*Buffer {}*", name), buffer_text),
+        (&format!("*Recovered {}*", name), recovered),
+        hunks,
+    );
+    app.message = Some(
+        "Buffer vs. recovered (y: take hunk, r: reject, a: take all, u: undo, n/k: next/previous, q: quit)"
+            .to_string(),
+    );
     Ok(())
 }
 
+/// Find the open buffer for the file at path `name`, opening the file if
+/// needed, or when there is no such file, the open buffer called `name`
+fn resolve_buffer(app: &mut EditorApp, name: &str) -> Result<BufferId, String> {
+    let name = name.trim();
+    let mut candidates: Vec<(BufferId, &Buffer)> = app
+        .buffers
+        .iter()
+        .filter(|(_, buffer)| buffer.buffer_kind() == BufferKind::Normal)
+        .map(|(&id, buffer)| (id, buffer))
+        .collect();
+    candidates.sort_by_key(|&(id, _)| id);
+
+    let path = Path::new(name);
+    if path.is_file() {
+        // The file may already be open under another name
+        let canonical = fs::canonicalize(path).map_err(|e| e.to_string())?;
+        let open = candidates.iter().find(|(_, buffer)| {
+            buffer
+                .filename
+                .as_ref()
+                .and_then(|filename| fs::canonicalize(filename).ok())
+                .is_some_and(|filename| filename == canonical)
+        });
+        return match open {
+            Some(&(id, _)) => Ok(id),
+            None => app.load_file(path).map_err(|e| e.to_string()),
+        };
+    }
+
+    if let Some(&(id, _)) = candidates
+        .iter()
+        .find(|(_, buffer)| buffer.display_name() == name)
+    {
+        return Ok(id);
+    }
+    // A bare file name, if only one buffer has it
+    let by_file_name: Vec<BufferId> = candidates
+        .iter()
+        .filter(|(_, buffer)| {
+            buffer
+                .filename
+                .as_ref()
+                .and_then(|path| path.file_name())
+                .is_some_and(|file_name| file_name == name)
+        })
+        .map(|&(id, _)| id)
+        .collect();
+    match by_file_name[..] {
+        [id] => Ok(id),
+        [] => Err(format!("No buffer or file named {}", name)),
+        _ => Err(format!("Several buffers are named {}", name)),
+    }
+}
+
+/// Recompute a two-buffer diff after one side changed and redraw both views
+fn refresh_buffer_diff(app: &mut EditorApp) {
+    let Some(state) = app.diff_state.as_mut() else {
+        return;
+    };
+    let Some(modified_buffer_id) = state.modified_buffer_id else {
+        return;
+    };
+    let text = |id: &BufferId| app.buffers.get(id).map(|buffer| buffer.to_string());
+    let (Some(left), Some(right)) = (text(&state.original_buffer_id), text(&modified_buffer_id))
+    else {
+        return;
+    };
+
+    state.set_hunks(DiffView::new(left.clone(), right.clone()).change_hunks());
+    state.current_hunk = state.current_hunk.min(state.hunks.len().saturating_sub(1));
+    let current_hunk = state.current_hunk;
+    if state.hunks.is_empty() {
+        app.message = Some("No differences remain".to_string());
+    }
+
+    let (original_view_id, modified_view_id) = state.view_buffer_ids;
+    for (id, text) in [(original_view_id, left), (modified_view_id, right)] {
+        if let Some(buffer) = app.buffers.get_mut(&id) {
+            let len = buffer.len();
+            buffer.delete(0, len);
+            buffer.insert(0, &text);
+            buffer.modified = false;
+        }
+    }
+    jump_to_hunk(app, current_hunk);
+}
+
+/// Copy the current hunk of a two-buffer diff to the left or right buffer
+fn copy_hunk(app: &mut EditorApp, to_left: bool) -> Result<(), String> {
+    let state = app.diff_state.as_ref().ok_or("No diff in progress")?;
+    let modified_buffer_id = state
+        .modified_buffer_id
+        .ok_or("Only a diff between two buffers can copy hunks")?;
+    if !to_left && modified_buffer_id == state.view_buffer_ids.1 {
+        return Err("The disk version can't be edited; save the buffer instead".to_string());
+    }
+    let hunk = state
+        .hunks
+        .get(state.current_hunk)
+        .ok_or("No differences")?;
+    let (target, line, old_text, new_text) = if to_left {
+        let (old, new) = (&hunk.old_lines, &hunk.new_lines);
+        (
+            state.original_buffer_id,
+            hunk.start_line,
+            old.concat(),
+            new.concat(),
+        )
+    } else {
+        let (old, new) = (&hunk.new_lines, &hunk.old_lines);
+        (
+            modified_buffer_id,
+            hunk.new_start,
+            old.concat(),
+            new.concat(),
+        )
+    };
+
+    let buffer = app
+        .buffers
+        .get_mut(&target)
+        .ok_or("The buffer was closed")?;
+    let start = buffer.line_to_byte(line).unwrap_or(buffer.len());
+    if buffer.get_range_as_string(start, old_text.len()) != old_text {
+        refresh_buffer_diff(app);
+        return Err("The buffer changed; the diff has been refreshed".to_string());
+    }
+    buffer.replace(start, old_text.len(), &new_text, "copy diff hunk");
+    refresh_buffer_diff(app);
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct DiffCopyHunkLeft;
+
+impl Command for DiffCopyHunkLeft {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.diff_state.is_none() {
+            return DispatchResult::NotHandled;
+        }
+        if let Err(e) = copy_hunk(app, true) {
+            app.message = Some(format!("Error: {}", e));
+        }
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct DiffCopyHunkRight;
+
+impl Command for DiffCopyHunkRight {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.diff_state.is_none() {
+            return DispatchResult::NotHandled;
+        }
+        if let Err(e) = copy_hunk(app, false) {
+            app.message = Some(format!("Error: {}", e));
+        }
+        DispatchResult::Success
+    }
+}
+
 fn jump_to_hunk(app: &mut EditorApp, index: usize) {
     let state = match &app.diff_state {
         Some(s) => s,
@@ -101,22 +501,22 @@ fn jump_to_hunk(app: &mut EditorApp, index: usize) {
     }
 
     let hunk = &state.hunks[index];
-    let line = hunk.start_line;
 
     // Update both windows
     let window_ids: Vec<crate::core::id::WindowId> = app.windows.keys().cloned().collect();
     for wid in window_ids {
         if let Some(window) = app.windows.get_mut(&wid) {
             if let Some(buffer) = app.buffers.get(&window.buffer_id) {
-                use crate::core::buffer::BufferKind;
-                let kind = buffer.buffer_kind();
-                if kind == BufferKind::DiffOriginal || kind == BufferKind::DiffModified {
-                    window.cursor_y = line;
-                    window.cursor_x = 0;
-                    window.scroll_offset = line.saturating_sub(5);
-                    window.update_visual_cursor(buffer);
-                    window.ensure_cursor_visible(buffer);
-                }
+                let line = match buffer.buffer_kind() {
+                    BufferKind::DiffOriginal => hunk.start_line,
+                    BufferKind::DiffModified => hunk.new_start,
+                    _ => continue,
+                };
+                window.cursor_y = line;
+                window.cursor_x = 0;
+                window.scroll_offset = line.saturating_sub(5);
+                window.update_visual_cursor(buffer);
+                window.ensure_cursor_visible(buffer);
             }
         }
     }
@@ -174,39 +574,298 @@ impl Command for DiffAcceptHunk {
             None => return DispatchResult::NotHandled,
         };
 
-        let hunk = state.hunks[state.current_hunk].clone();
-        let original_buffer_id = state.original_buffer_id;
+        // Between two buffers, accepting takes the right side's version
+        if state.modified_buffer_id.is_some() {
+            app.diff_state = Some(state);
+            return DiffCopyHunkLeft.execute(app, 1);
+        }
 
-        if let Some(buffer) = app.buffers.get_mut(&original_buffer_id) {
-            // Apply hunk to original buffer
-            if let Some(start_byte) = buffer.line_to_byte(hunk.start_line) {
-                let end_line_plus_one = hunk.end_line + 1;
-                let end_byte = match buffer.line_to_byte(end_line_plus_one) {
-                    Some(b) => b,
-                    None => buffer.len(),
-                };
+        if let Some(region) = state.region.take() {
+            app.diff_state = Some(state);
+            match apply_region(app, &region) {
+                Ok(()) => {
+                    DiffQuit.execute(app, 1);
+                    app.message = Some("Applied to region".to_string());
+                }
+                Err(e) => {
+                    if let Some(state) = app.diff_state.as_mut() {
+                        state.region = Some(region);
+                    }
+                    app.message = Some(format!("Error: {}", e));
+                }
+            }
+            return DispatchResult::Success;
+        }
+
+        let current_hunk = state.current_hunk;
+        app.diff_state = Some(state);
+        if let Err(e) = accept_hunks(app, vec![current_hunk]) {
+            app.message = Some(format!("Error: {}", e));
+        }
+        DispatchResult::Success
+    }
+}
+
+/// The diff session, if it is a whole-buffer sed preview whose hunks are
+/// accepted and rejected one by one
+fn hunk_preview(app: &mut EditorApp) -> Result<&mut DiffState, String> {
+    let state = app.diff_state.as_mut().ok_or("No diff in progress")?;
+    if state.region.is_some() {
+        return Err("A region preview is applied as a whole".to_string());
+    }
+    if state.modified_buffer_id.is_some() {
+        return Err("Use < and > to copy hunks between buffers".to_string());
+    }
+    Ok(state)
+}
+
+/// Apply hunks `indices` (in order) of a sed preview to the original
+/// buffer, or take them back out when `accept` is false, as one undo step
+fn rewrite_hunks(app: &mut EditorApp, indices: &[usize], accept: bool) -> Result<(), String> {
+    let state = app.diff_state.as_ref().ok_or("No diff in progress")?;
+    let Some(&first) = indices.first() else {
+        return Ok(());
+    };
+    let buffer = app
+        .buffers
+        .get_mut(&state.original_buffer_id)
+        .ok_or("The buffer was closed")?;
+    let byte = |buffer: &Buffer, line: usize| buffer.line_to_byte(line).unwrap_or(buffer.len());
 
-                buffer.delete(start_byte, end_byte - start_byte);
+    // Rebuild the text from the first hunk to the end of the last one
+    let span_start = byte(buffer, state.current_start(first));
+    let mut pos = span_start;
+    let mut text = String::new();
+    for &index in indices {
+        let hunk = &state.hunks[index];
+        let (from, to) = if accept {
+            (hunk.old_lines.concat(), hunk.new_lines.concat())
+        } else {
+            (hunk.new_lines.concat(), hunk.old_lines.concat())
+        };
+        let start = byte(buffer, state.current_start(index));
+        if start < pos || buffer.get_range_as_string(start, from.len()) != from {
+            return Err("The buffer changed since the preview".to_string());
+        }
+        text.push_str(&buffer.get_range_as_string(pos, start - pos));
+        text.push_str(&to);
+        pos = start + from.len();
+    }
+    let description = if accept {
+        "accept diff hunk"
+    } else {
+        "undo diff hunk"
+    };
+    buffer.replace(span_start, pos - span_start, &text, description);
 
-                let new_text = hunk.new_lines.join("");
-                buffer.insert(start_byte, &new_text);
+    let status = if accept {
+        HunkStatus::Accepted
+    } else {
+        HunkStatus::Pending
+    };
+    if let Some(state) = app.diff_state.as_mut() {
+        for &index in indices {
+            state.statuses[index] = status;
+        }
+    }
+    Ok(())
+}
+
+/// Accept the pending hunks among `indices` and move to the next pending one
+fn accept_hunks(app: &mut EditorApp, mut indices: Vec<usize>) -> Result<(), String> {
+    let state = hunk_preview(app)?;
+    indices.retain(|&index| state.statuses.get(index) == Some(&HunkStatus::Pending));
+    indices.sort_unstable();
+    if indices.is_empty() {
+        return Err("No pending hunks to accept".to_string());
+    }
+    rewrite_hunks(app, &indices, true)?;
+    if let Some(state) = app.diff_state.as_mut() {
+        state.accept_history.push(indices);
+    }
+    advance_to_pending(app);
+    Ok(())
+}
+
+/// Move to the next pending hunk, or say that every hunk has been handled
+fn advance_to_pending(app: &mut EditorApp) {
+    let Some(state) = app.diff_state.as_mut() else {
+        return;
+    };
+    match state.next_pending(state.current_hunk) {
+        Some(index) => {
+            state.current_hunk = index;
+            jump_to_hunk(app, index);
+        }
+        None => app.message = Some("All hunks handled (q: quit)".to_string()),
+    }
+}
+
+#[derive(Clone)]
+pub struct DiffRejectHunk;
+
+impl Command for DiffRejectHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.diff_state.is_none() {
+            return DispatchResult::NotHandled;
+        }
+        let result = hunk_preview(app).and_then(|state| {
+            let status = &mut state.statuses[state.current_hunk];
+            match status {
+                HunkStatus::Accepted => Err("Undo the accept first".to_string()),
+                _ => {
+                    *status = HunkStatus::Rejected;
+                    Ok(())
+                }
             }
+        });
+        match result {
+            Ok(()) => advance_to_pending(app),
+            Err(e) => app.message = Some(format!("Error: {}", e)),
         }
+        DispatchResult::Success
+    }
+}
 
-        if state.current_hunk + 1 < state.hunks.len() {
-            state.current_hunk += 1;
-        } else {
-            app.message = Some("Applied last hunk".to_string());
+#[derive(Clone)]
+pub struct DiffAcceptAll;
+
+impl Command for DiffAcceptAll {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.diff_state.as_ref() else {
+            return DispatchResult::NotHandled;
+        };
+        let remaining = (0..state.hunks.len()).collect();
+        if let Err(e) = accept_hunks(app, remaining) {
+            app.message = Some(format!("Error: {}", e));
         }
+        DispatchResult::Success
+    }
+}
 
-        let current_idx = state.current_hunk;
-        app.diff_state = Some(state);
-        jump_to_hunk(app, current_idx);
+#[derive(Clone)]
+pub struct DiffUndoAccept;
+
+impl Command for DiffUndoAccept {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.diff_state.is_none() {
+            return DispatchResult::NotHandled;
+        }
+        let result = hunk_preview(app).and_then(|state| {
+            state
+                .accept_history
+                .pop()
+                .ok_or_else(|| "No accepted hunks to undo".to_string())
+        });
+        let indices = match result {
+            Ok(indices) => indices,
+            Err(e) => {
+                app.message = Some(format!("Error: {}", e));
+                return DispatchResult::Success;
+            }
+        };
 
+        match rewrite_hunks(app, &indices, false) {
+            Ok(()) => {
+                if let Some(state) = app.diff_state.as_mut() {
+                    state.current_hunk = indices[0];
+                }
+                jump_to_hunk(app, indices[0]);
+            }
+            Err(e) => {
+                if let Some(state) = app.diff_state.as_mut() {
+                    state.accept_history.push(indices);
+                }
+                app.message = Some(format!("Error: {}", e));
+            }
+        }
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct DiffSplitHunk;
+
+impl Command for DiffSplitHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.diff_state.is_none() {
+            return DispatchResult::NotHandled;
+        }
+        if let Err(e) = split_hunk(app) {
+            app.message = Some(format!("Error: {}", e));
+        }
         DispatchResult::Success
     }
 }
 
+/// Split the current hunk so that the line at the cursor starts a new hunk
+fn split_hunk(app: &mut EditorApp) -> Result<(), String> {
+    let (kind, cursor_line) = app
+        .windows
+        .get(&app.active_window)
+        .and_then(|window| {
+            let buffer = app.buffers.get(&window.buffer_id)?;
+            Some((buffer.buffer_kind(), window.cursor_y))
+        })
+        .ok_or("No active window")?;
+    let state = hunk_preview(app)?;
+    let index = state.current_hunk;
+    if state.statuses[index] != HunkStatus::Pending {
+        return Err("Only a pending hunk can be split".to_string());
+    }
+
+    let hunk = &state.hunks[index];
+    let (old_len, new_len) = (hunk.old_lines.len(), hunk.new_lines.len());
+    let counts = match kind {
+        BufferKind::DiffOriginal => cursor_line
+            .checked_sub(hunk.start_line)
+            .map(|offset| (offset, offset.min(new_len))),
+        BufferKind::DiffModified => cursor_line
+            .checked_sub(hunk.new_start)
+            .map(|offset| (offset.min(old_len), offset)),
+        _ => None,
+    };
+    let (old_count, new_count) = counts
+        .filter(|&counts| counts != (0, 0) && counts.0 <= old_len && counts.1 <= new_len)
+        .filter(|&counts| counts != (old_len, new_len))
+        .ok_or("Put the cursor on a line inside the hunk to split it there")?;
+
+    let (first, second) = hunk.split_at(old_count, new_count);
+    state.hunks.splice(index..=index, [first, second]);
+    state.statuses.insert(index + 1, HunkStatus::Pending);
+    for accepted in state.accept_history.iter_mut().flatten() {
+        if *accepted > index {
+            *accepted += 1;
+        }
+    }
+    app.message = Some(format!("Split hunk {}", index + 1));
+    jump_to_hunk(app, index);
+    Ok(())
+}
+
+/// Replace the previewed region with its new text as one undo step
+fn apply_region(app: &mut EditorApp, region: &DiffRegion) -> Result<(), String> {
+    let buffer_id = app
+        .diff_state
+        .as_ref()
+        .map(|state| state.original_buffer_id)
+        .ok_or("No diff in progress")?;
+    let buffer = app
+        .buffers
+        .get_mut(&buffer_id)
+        .ok_or("The buffer was closed")?;
+    if buffer.get_range_as_string(region.start, region.original.len()) != region.original {
+        return Err("The region changed since the preview".to_string());
+    }
+    buffer.replace(
+        region.start,
+        region.original.len(),
+        &region.modified,
+        "sed region",
+    );
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct DiffQuit;
 
@@ -218,8 +877,273 @@ impl Command for DiffQuit {
             if let Some(window) = app.windows.get_mut(&app.active_window) {
                 window.buffer_id = original_buffer_id;
             }
+            let (original_view_id, modified_view_id) = state.view_buffer_ids;
+            app.buffers.remove(&original_view_id);
+            app.buffers.remove(&modified_view_id);
             return DispatchResult::Success;
         }
         DispatchResult::NotHandled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_preview_applies_as_one_undo_step() {
+        let mut app = EditorApp::new();
+        if let Some(buffer) = app.active_buffer_mut() {
+            buffer.insert(0, "fn a() { x }\nfn b() { x }\nfn c() { x }\n");
+        }
+        // Mark at the start of line 1, point at the start of line 2
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.mark = Some((0, 1));
+            window.cursor_y = 2;
+            window.cursor_x = 0;
+        }
+        let buffer_id = app.windows[&app.active_window].buffer_id;
+
+        if let Err(e) = start_sed_region_diff(&mut app, "s/x/y/") {
+            panic!("Preview failed: {}", e);
+        }
+        assert!(app.diff_state.is_some());
+        DiffAcceptHunk.execute(&mut app, 1);
+        assert!(app.diff_state.is_none());
+
+        let buffer = match app.buffers.get_mut(&buffer_id) {
+            Some(buffer) => buffer,
+            None => panic!("Buffer disappeared"),
+        };
+        assert_eq!(
+            buffer.to_string(),
+            "fn a() { x }\nfn b() { y }\nfn c() { x }\n"
+        );
+        buffer.undo();
+        assert_eq!(
+            buffer.to_string(),
+            "fn a() { x }\nfn b() { x }\nfn c() { x }\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_buffers_copies_hunks_both_ways() {
+        let mut app = EditorApp::new();
+        let mut add = |name: &str, text: &str| {
+            let mut buffer = Buffer::from_string(text);
+            buffer.filename = Some(PathBuf::from(name));
+            app.add_buffer(buffer)
+        };
+        let left = add("left.txt", "a\nb\nc\nd\n");
+        let right = add("right.txt", "a\nB\nc\nd\ne\n");
+
+        if let Err(e) = start_buffer_diff(&mut app, "left.txt", "right.txt") {
+            panic!("Diff failed: {}", e);
+        }
+        let view_buffer_ids = match &app.diff_state {
+            Some(state) => {
+                assert_eq!(state.hunks.len(), 2);
+                state.view_buffer_ids
+            }
+            None => panic!("No diff started"),
+        };
+        let text = |app: &EditorApp, id: BufferId| match app.buffers.get(&id) {
+            Some(buffer) => buffer.to_string(),
+            None => panic!("Missing buffer {}", id),
+        };
+
+        // Take `B` from the right, then push the left's ending to the right
+        DiffCopyHunkLeft.execute(&mut app, 1);
+        assert_eq!(text(&app, left), "a\nB\nc\nd\n");
+        DiffCopyHunkRight.execute(&mut app, 1);
+        assert_eq!(text(&app, right), "a\nB\nc\nd\n");
+        assert_eq!(text(&app, view_buffer_ids.1), "a\nB\nc\nd\n");
+        assert!(
+            app.diff_state
+                .as_ref()
+                .is_some_and(|state| state.hunks.is_empty())
+        );
+
+        DiffQuit.execute(&mut app, 1);
+        assert!(!app.buffers.contains_key(&view_buffer_ids.0));
+        assert!(!app.buffers.contains_key(&view_buffer_ids.1));
+        assert_eq!(app.windows[&app.active_window].buffer_id, left);
+    }
+
+    #[test]
+    fn test_diff_files_with_the_same_name() {
+        // A file in the working directory, given by its bare name, and an
+        // open file of the same name in another directory
+        let here = match tempfile::Builder::new().suffix(".txt").tempfile_in(".") {
+            Ok(file) => file,
+            Err(e) => panic!("Failed to create temp file: {}", e),
+        };
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let file_name = match here.path().file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => panic!("Temp file has no name"),
+        };
+        let there = dir.path().join(&file_name);
+        for (path, text) in [(here.path(), "a\n"), (there.as_path(), "b\n")] {
+            if let Err(e) = fs::write(path, text) {
+                panic!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+
+        let mut app = EditorApp::new();
+        let there_id = match app.load_file(&there) {
+            Ok(id) => id,
+            Err(e) => panic!("Failed to open {}: {}", there.display(), e),
+        };
+        if let Err(e) = start_buffer_diff(&mut app, &there.to_string_lossy(), &file_name) {
+            panic!("Diff failed: {}", e);
+        }
+        match &app.diff_state {
+            Some(state) => {
+                assert_eq!(state.hunks.len(), 1);
+                assert_eq!(state.original_buffer_id, there_id);
+                assert!(state.modified_buffer_id.is_some_and(|id| id != there_id));
+            }
+            None => panic!("No diff started"),
+        }
+    }
+
+    #[test]
+    fn test_diff_with_file_reverts_hunks() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("notes.txt");
+        if let Err(e) = fs::write(&path, "one\ntwo\nthree\n") {
+            panic!("Failed to write {}: {}", path.display(), e);
+        }
+
+        let mut app = EditorApp::new();
+        let buffer_id = match app.load_file(&path) {
+            Ok(id) => id,
+            Err(e) => panic!("Failed to open {}: {}", path.display(), e),
+        };
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = buffer_id;
+        }
+        if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+            buffer.insert(0, "zero\n");
+            buffer.insert(buffer.len(), "four\n");
+        }
+
+        if let Err(e) = start_file_diff(&mut app) {
+            panic!("Diff failed: {}", e);
+        }
+        assert_eq!(
+            app.diff_state.as_ref().map(|state| state.hunks.len()),
+            Some(2)
+        );
+
+        // The disk side is read-only; reverting the first hunk drops `zero`
+        DiffCopyHunkRight.execute(&mut app, 1);
+        assert!(
+            app.message
+                .as_deref()
+                .is_some_and(|m| m.contains("can't be edited"))
+        );
+        DiffAcceptHunk.execute(&mut app, 1);
+        assert_eq!(
+            app.buffers.get(&buffer_id).map(|buffer| buffer.to_string()),
+            Some("one\ntwo\nthree\nfour\n".to_string())
+        );
+        assert_eq!(
+            app.diff_state.as_ref().map(|state| state.hunks.len()),
+            Some(1)
+        );
+    }
+
+    fn preview(text: &str, expression: &str) -> (EditorApp, BufferId) {
+        let mut app = EditorApp::new();
+        if let Some(buffer) = app.active_buffer_mut() {
+            buffer.insert(0, text);
+        }
+        let buffer_id = app.windows[&app.active_window].buffer_id;
+        if let Err(e) = start_sed_diff(&mut app, expression) {
+            panic!("Preview failed: {}", e);
+        }
+        (app, buffer_id)
+    }
+
+    fn text(app: &EditorApp, buffer_id: BufferId) -> String {
+        match app.buffers.get(&buffer_id) {
+            Some(buffer) => buffer.to_string(),
+            None => panic!("Missing buffer {}", buffer_id),
+        }
+    }
+
+    #[test]
+    fn test_accepting_hunks_tracks_line_offsets() {
+        let (mut app, buffer_id) = preview("a\nb\nc\nd\ne\n", "/b/d;s/e/E/");
+        DiffAcceptHunk.execute(&mut app, 1);
+        DiffAcceptHunk.execute(&mut app, 1);
+        assert_eq!(text(&app, buffer_id), "a\nc\nd\nE\n");
+
+        DiffUndoAccept.execute(&mut app, 1);
+        assert_eq!(text(&app, buffer_id), "a\nc\nd\ne\n");
+        DiffRejectHunk.execute(&mut app, 1);
+        assert_eq!(
+            app.diff_state.as_ref().map(|state| state.statuses.clone()),
+            Some(vec![HunkStatus::Accepted, HunkStatus::Rejected])
+        );
+        // Each accept is its own undo step in the buffer
+        if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+            buffer.undo();
+        }
+        assert_eq!(text(&app, buffer_id), "a\nc\nd\nE\n");
+    }
+
+    #[test]
+    fn test_accept_all_is_one_undo_step() {
+        let (mut app, buffer_id) = preview("x\n1\nx\n2\nx\n", "s/x/y/");
+        DiffRejectHunk.execute(&mut app, 1);
+        DiffAcceptAll.execute(&mut app, 1);
+        assert_eq!(text(&app, buffer_id), "x\n1\ny\n2\ny\n");
+        DiffAcceptAll.execute(&mut app, 1);
+        assert!(
+            app.message
+                .as_deref()
+                .is_some_and(|m| m.contains("No pending"))
+        );
+
+        if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+            buffer.undo();
+        }
+        assert_eq!(text(&app, buffer_id), "x\n1\nx\n2\nx\n");
+    }
+
+    #[test]
+    fn test_split_hunk_at_cursor() {
+        let (mut app, buffer_id) = preview("a\nb\nc\n", "s/.*/X/");
+        assert_eq!(
+            app.diff_state.as_ref().map(|state| state.hunks.len()),
+            Some(1)
+        );
+        DiffSplitHunk.execute(&mut app, 1);
+        assert!(
+            app.message
+                .as_deref()
+                .is_some_and(|m| m.starts_with("Error"))
+        );
+
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.cursor_y = 1;
+        }
+        DiffSplitHunk.execute(&mut app, 1);
+        assert_eq!(
+            app.diff_state.as_ref().map(|state| state.hunks.len()),
+            Some(2)
+        );
+        DiffRejectHunk.execute(&mut app, 1);
+        DiffAcceptHunk.execute(&mut app, 1);
+        assert_eq!(text(&app, buffer_id), "a\nX\nX\n");
+    }
+}
@@ -1,7 +1,28 @@
 use crate::core::app::EditorApp;
+use crate::core::buffer::Buffer;
 /// File and buffer operation commands
 use crate::core::command::Command;
 use crate::core::dispatcher::{DispatchResult, InputAction};
+use crate::core::encoding::{self, FileEncoding};
+use crate::core::line_ending::{self, LineEnding};
+
+/// Warnings for the message line when `buffer` is opened
+pub fn file_notice(buffer: &Buffer) -> Option<String> {
+    let counts = buffer.line_ending_counts();
+    let mixed = counts.is_mixed().then(|| {
+        format!(
+            "Mixed line endings ({}); new lines use {} (M-x set-line-endings)",
+            counts.describe(),
+            buffer.line_ending.name()
+        )
+    });
+    let notices: Vec<String> = encoding_notice(buffer)
+        .into_iter()
+        .chain(crate::core::commands::conflict::conflict_notice(buffer))
+        .chain(mixed)
+        .collect();
+    (!notices.is_empty()).then(|| notices.join("; "))
+}
 
 /// Save active buffer to file
 #[derive(Clone)]
@@ -24,7 +45,7 @@ impl Command for SaveBuffer {
                     return DispatchResult::FileModified;
                 }
                 if let Err(e) = buffer.save() {
-                    eprintln!("Error saving file: {}", e);
+                    return DispatchResult::Info(format!("Error saving file: {}", e));
                 }
             }
         }
@@ -147,3 +168,390 @@ impl Command for PrintBuffer {
         DispatchResult::Success
     }
 }
+
+/// Which encoding the buffer was decoded as, unless UTF-8, and how many
+/// bytes it could not decode
+fn encoding_notice(buffer: &Buffer) -> Option<String> {
+    let escapes = encoding::count_escapes(&buffer.to_string());
+    let decoded = (buffer.encoding != FileEncoding::default())
+        .then(|| format!("Decoded as {}", buffer.encoding.name()));
+    let kept = (escapes > 0).then(|| {
+        format!(
+            "{} undecodable byte{} kept as-is (M-x revisit-with-encoding)",
+            escapes,
+            if escapes == 1 { "" } else { "s" }
+        )
+    });
+    let notices: Vec<String> = decoded.into_iter().chain(kept).collect();
+    (!notices.is_empty()).then(|| notices.join(", "))
+}
+
+/// Convert every line break in the buffer to one style (prompts)
+#[derive(Clone)]
+pub struct SetLineEndings;
+
+impl Command for SetLineEndings {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(buffer) = app.active_buffer() else {
+            return DispatchResult::NotHandled;
+        };
+        DispatchResult::NeedsInput {
+            prompt: format!(
+                "Line endings (lf, crlf, cr) [{}]: ",
+                buffer.line_ending.name()
+            ),
+            action: InputAction::SetLineEndings,
+        }
+    }
+}
+
+/// Convert the active buffer to the line ending called `name`, as one undo
+/// step, and use it for new lines
+pub fn set_line_endings(app: &mut EditorApp, name: &str) -> Result<String, String> {
+    let ending =
+        LineEnding::from_name(name).ok_or_else(|| format!("Unknown line ending: {}", name))?;
+    let buffer = app.active_buffer_mut().ok_or("No active buffer")?;
+    let text = buffer.to_string();
+    let converted = line_ending::convert(&text, ending);
+    if converted != text {
+        buffer.replace(0, text.len(), &converted, "set line endings");
+    }
+    buffer.line_ending = ending;
+    Ok(format!("Line endings set to {}", ending.name()))
+}
+
+/// Reload the file decoded in another encoding (prompts)
+#[derive(Clone)]
+pub struct RevisitWithEncoding;
+
+impl Command for RevisitWithEncoding {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.active_buffer().is_none() {
+            return DispatchResult::NotHandled;
+        }
+        DispatchResult::NeedsInput {
+            prompt: "Revisit with encoding: ".to_string(),
+            action: InputAction::RevisitWithEncoding,
+        }
+    }
+}
+
+/// Choose the encoding the buffer is saved in (prompts)
+#[derive(Clone)]
+pub struct SetBufferEncoding;
+
+impl Command for SetBufferEncoding {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(buffer) = app.active_buffer() else {
+            return DispatchResult::NotHandled;
+        };
+        DispatchResult::NeedsInput {
+            prompt: format!("Save with encoding [{}]: ", buffer.encoding.name()),
+            action: InputAction::SetBufferEncoding,
+        }
+    }
+}
+
+fn parse_encoding(name: &str) -> Result<FileEncoding, String> {
+    FileEncoding::from_name(name).ok_or_else(|| {
+        format!(
+            "Unknown encoding: {} (utf-8, utf-16le, utf-16be, latin-1, windows-1252, with -bom for a byte order mark)",
+            name.trim()
+        )
+    })
+}
+
+/// Read the active buffer's file again, decoding it as `name`
+pub fn revisit_with_encoding(app: &mut EditorApp, name: &str) -> Result<String, String> {
+    let encoding = parse_encoding(name)?;
+    let buffer = app.active_buffer_mut().ok_or("No active buffer")?;
+    if buffer.filename.is_none() {
+        return Err("The buffer has no file".to_string());
+    }
+    if buffer.modified {
+        return Err("The buffer has unsaved changes".to_string());
+    }
+    buffer
+        .revisit_with_encoding(encoding)
+        .map_err(|e| e.to_string())?;
+    let buffer = app.active_buffer().ok_or("No active buffer")?;
+    Ok(match encoding_notice(buffer) {
+        Some(notice) => format!("Revisited: {}", notice),
+        None => format!("Revisited as {}", encoding.name()),
+    })
+}
+
+/// Save the active buffer as `name` from now on, if its text can be
+/// encoded that way
+pub fn set_buffer_encoding(app: &mut EditorApp, name: &str) -> Result<String, String> {
+    let encoding = parse_encoding(name)?;
+    let buffer = app.active_buffer_mut().ok_or("No active buffer")?;
+    encoding.encode(&buffer.to_string())?;
+    if encoding != buffer.encoding {
+        buffer.encoding = encoding;
+        buffer.modified = true;
+    }
+    Ok(format!("Will save as {}", encoding.name()))
+}
+
+/// Text a crash left in the recovery file of the active buffer's file, if it
+/// differs from the buffer
+pub fn recovered_text(app: &EditorApp) -> Option<String> {
+    let buffer = app.active_buffer()?;
+    app.autosave
+        .recovered_text(buffer.filename.as_ref()?, &buffer.to_string())
+}
+
+fn recovery_prompt(buffer: &Buffer) -> String {
+    format!(
+        "Recovery data found for {}: (r)estore, (d)iff or (x) delete? ",
+        buffer.display_name()
+    )
+}
+
+/// Ask what to do with the recovery data of the active buffer's file, if
+/// there is any
+pub fn offer_recovery(app: &mut EditorApp) {
+    if recovered_text(app).is_none() {
+        return;
+    }
+    if let Some(buffer) = app.active_buffer() {
+        let prompt = recovery_prompt(buffer);
+        app.focus_manager.push(
+            crate::core::focus::FocusState::new(
+                crate::core::focus::FocusTarget::Minibuffer,
+                &prompt,
+            )
+            .with_action(InputAction::RecoverFile),
+        );
+    }
+}
+
+/// Restore, diff or delete the recovery data of the active buffer's file
+/// (prompts)
+#[derive(Clone)]
+pub struct RecoverFile;
+
+impl Command for RecoverFile {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(buffer) = app.active_buffer() else {
+            return DispatchResult::NotHandled;
+        };
+        if recovered_text(app).is_none() {
+            return DispatchResult::Info(format!("No recovery data for {}", buffer.display_name()));
+        }
+        DispatchResult::NeedsInput {
+            prompt: recovery_prompt(buffer),
+            action: InputAction::RecoverFile,
+        }
+    }
+}
+
+/// Replace the active buffer's text with its recovery data on `r`, or delete
+/// the recovery file on `x`; anything else keeps it for later
+pub fn resolve_recovery(app: &mut EditorApp, choice: &str) -> Result<String, String> {
+    match choice.trim() {
+        "r" | "restore" => {
+            let text = recovered_text(app).ok_or("No recovery data")?;
+            let buffer = app.active_buffer_mut().ok_or("No active buffer")?;
+            buffer.replace(0, buffer.len(), &text, "recover file");
+            Ok("Restored the recovery data; save to keep it".to_string())
+        }
+        "x" | "delete" => {
+            let buffer = app.active_buffer().ok_or("No active buffer")?;
+            let path = buffer.filename.clone().ok_or("The buffer has no file")?;
+            app.autosave.remove(&path)?;
+            Ok("Deleted the recovery data".to_string())
+        }
+        _ => Ok("Kept the recovery data (M-x recover-file)".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::commands::text::JustifyParagraph;
+
+    #[test]
+    fn test_crlf_file_keeps_its_line_endings() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("dos.txt");
+        if let Err(e) = std::fs::write(&path, "one\r\ntwo\r\nthree\n") {
+            panic!("Failed to write: {}", e);
+        }
+        let mut app = EditorApp::new();
+        let buffer = match Buffer::from_file(&path) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Failed to load: {}", e),
+        };
+        assert_eq!(buffer.line_ending, LineEnding::CrLf);
+        assert_eq!(
+            file_notice(&buffer).as_deref(),
+            Some("Mixed line endings (2 CRLF, 1 LF); new lines use CRLF (M-x set-line-endings)")
+        );
+        let buffer_id = app.add_buffer(buffer);
+        let window_id = app.active_window;
+        if let (Some(window), Some(buffer)) = (
+            app.windows.get_mut(&window_id),
+            app.buffers.get_mut(&buffer_id),
+        ) {
+            window.buffer_id = buffer_id;
+            // Break "one" after "on", then join the lines again
+            assert_eq!(buffer.line(0).as_deref(), Some("one"));
+            window.cursor_x = 2;
+            window.insert_char(buffer, '\n');
+            assert_eq!(buffer.to_string(), "on\r\ne\r\ntwo\r\nthree\n");
+            window.delete_char(buffer, true);
+            assert_eq!(buffer.to_string(), "one\r\ntwo\r\nthree\n");
+        }
+
+        match set_line_endings(&mut app, "lf") {
+            Ok(message) => assert_eq!(message, "Line endings set to LF"),
+            Err(e) => panic!("Failed to convert: {}", e),
+        }
+        let text = app.active_buffer().map(|buffer| buffer.to_string());
+        assert_eq!(text.as_deref(), Some("one\ntwo\nthree\n"));
+        assert!(set_line_endings(&mut app, "ebcdic").is_err());
+
+        // Filling a paragraph keeps CRLF too
+        if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+            let len = buffer.len();
+            buffer.replace(0, len, "one\r\ntwo\r\n\r\nthree\r\n", "reset");
+            buffer.line_ending = LineEnding::CrLf;
+        }
+        if let Some(window) = app.windows.get_mut(&window_id) {
+            window.cursor_y = 0;
+            window.cursor_x = 0;
+        }
+        JustifyParagraph.execute(&mut app, 1);
+        let text = app.active_buffer().map(|buffer| buffer.to_string());
+        assert_eq!(text.as_deref(), Some("one two\r\n\r\nthree\r\n"));
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("legacy.txt");
+        // Windows-1252 quotes around Latin-1 text
+        if let Err(e) = std::fs::write(&path, b"\x93caf\xE9\x94\n") {
+            panic!("Failed to write: {}", e);
+        }
+        let mut app = EditorApp::new();
+        let buffer = match Buffer::from_file(&path) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Failed to load: {}", e),
+        };
+        assert_eq!(buffer.to_string(), "“café”\n");
+        assert_eq!(
+            file_notice(&buffer).as_deref(),
+            Some("Decoded as Windows-1252")
+        );
+        let buffer_id = app.add_buffer(buffer);
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = buffer_id;
+        }
+
+        // As UTF-8 every byte but "caf" is undecodable, and kept
+        match revisit_with_encoding(&mut app, "utf-8") {
+            Ok(message) => assert_eq!(
+                message,
+                "Revisited: 3 undecodable bytes kept as-is (M-x revisit-with-encoding)"
+            ),
+            Err(e) => panic!("Failed to revisit: {}", e),
+        }
+        let save = |app: &mut EditorApp| match app.active_buffer_mut().map(|buffer| buffer.save()) {
+            Some(Ok(())) => std::fs::read(&path).unwrap_or_default(),
+            Some(Err(e)) => panic!("Failed to save: {}", e),
+            None => panic!("No buffer"),
+        };
+        assert_eq!(save(&mut app), b"\x93caf\xE9\x94\n");
+
+        if let Err(e) = revisit_with_encoding(&mut app, "windows-1252") {
+            panic!("Failed to revisit: {}", e);
+        }
+        assert!(set_buffer_encoding(&mut app, "latin-1").is_err());
+        if let Err(e) = set_buffer_encoding(&mut app, "utf-16le-bom") {
+            panic!("Failed to set encoding: {}", e);
+        }
+        assert!(revisit_with_encoding(&mut app, "utf-8").is_err());
+        let saved = save(&mut app);
+        assert!(saved.starts_with(b"\xFF\xFE\x1C\x20c\x00"));
+    }
+
+    #[test]
+    fn test_recover_after_crash() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("f.txt");
+        if let Err(e) = std::fs::write(&path, "one\ntwo\n") {
+            panic!("Failed to write: {}", e);
+        }
+        let open = || {
+            let mut app = EditorApp::new();
+            app.autosave.dir = Some(dir.path().join("recovery"));
+            let buffer = match Buffer::from_file(&path) {
+                Ok(buffer) => buffer,
+                Err(e) => panic!("Failed to load: {}", e),
+            };
+            let buffer_id = app.add_buffer(buffer);
+            if let Some(window) = app.windows.get_mut(&app.active_window) {
+                window.buffer_id = buffer_id;
+            }
+            app
+        };
+
+        // A session that edits the file and never exits
+        let mut crashed = open();
+        if let Some(buffer) = crashed.active_buffer_mut() {
+            buffer.replace(4, 3, "TWO", "edit");
+        }
+        if let Err(e) = crashed.autosave.run(&crashed.buffers) {
+            panic!("Failed to autosave: {}", e);
+        }
+
+        let mut app = open();
+        offer_recovery(&mut app);
+        assert!(matches!(
+            app.focus_manager
+                .current_state()
+                .and_then(|state| state.action.clone()),
+            Some(InputAction::RecoverFile)
+        ));
+        assert_eq!(recovered_text(&app).as_deref(), Some("one\nTWO\n"));
+        assert_eq!(
+            resolve_recovery(&mut app, "r"),
+            Ok("Restored the recovery data; save to keep it".to_string())
+        );
+        let text = app.active_buffer().map(|buffer| buffer.to_string());
+        assert_eq!(text.as_deref(), Some("one\nTWO\n"));
+        assert!(app.active_buffer().is_some_and(|buffer| buffer.modified));
+
+        // Diffing offers the recovered hunks for the buffer
+        let mut app = open();
+        if let Err(e) =
+            crate::core::commands::diff::start_recovery_diff(&mut app, "one\nTWO\n".to_string())
+        {
+            panic!("Failed to diff: {}", e);
+        }
+        assert_eq!(
+            app.diff_state.as_ref().map(|state| state.hunks.len()),
+            Some(1)
+        );
+
+        let mut app = open();
+        assert!(resolve_recovery(&mut app, "x").is_ok());
+        assert_eq!(recovered_text(&app), None);
+        assert!(matches!(
+            RecoverFile.execute(&mut app, 1),
+            DispatchResult::Info(_)
+        ));
+    }
+}
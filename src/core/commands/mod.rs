@@ -23,6 +23,8 @@ pub mod buffer;
 pub mod calculator;
 /// Word completion
 pub mod completion;
+/// Merge conflict resolution
+pub mod conflict;
 /// Application control
 pub mod control;
 /// Diagnostics pane
@@ -43,10 +45,16 @@ pub mod macro_cmd;
 pub mod marks;
 /// Cursor movement commands
 pub mod movement;
+/// Unified patch files
+pub mod patch;
 /// Printing commands
 pub mod print;
+/// Sed preview across many files
+pub mod review;
 /// Search and navigation
 pub mod search;
+/// Sed script debugger
+pub mod sed_debug;
 /// Code snippets
 pub mod snippets;
 /// Spell suggestions
@@ -57,6 +65,8 @@ pub mod terminal;
 pub mod text;
 /// Undo/redo operations
 pub mod undo;
+/// Git change navigation and hunk staging
+pub mod vcs;
 /// Window management
 pub mod window;
 
@@ -69,6 +79,7 @@ pub fn register_all(app: &mut crate::core::app::EditorApp) {
     use self::buffer::*;
     use self::calculator::*;
     use self::completion::*;
+    use self::conflict::*;
     use self::control::*;
     use self::diagnostics::*;
     use self::diff::*;
@@ -79,13 +90,17 @@ pub fn register_all(app: &mut crate::core::app::EditorApp) {
     use self::macro_cmd::*;
     use self::marks::*;
     use self::movement::*;
+    use self::patch::*;
     use self::print::*;
+    use self::review::*;
     use self::search::*;
+    use self::sed_debug::*;
     use self::snippets::*;
     use self::spell_suggest::*;
     use self::terminal::*;
     use self::text::*;
     use self::undo::*;
+    use self::vcs::*;
     use self::window::*;
 
     let mut registry: HashMap<String, Box<dyn Command>> = HashMap::new();
@@ -176,6 +191,16 @@ pub fn register_all(app: &mut crate::core::app::EditorApp) {
     registry.insert("save-buffer".to_string(), Box::new(SaveBuffer));
     registry.insert("find-file".to_string(), Box::new(FindFile));
     registry.insert("write-file".to_string(), Box::new(WriteFile));
+    registry.insert("set-line-endings".to_string(), Box::new(SetLineEndings));
+    registry.insert("recover-file".to_string(), Box::new(RecoverFile));
+    registry.insert(
+        "revisit-with-encoding".to_string(),
+        Box::new(RevisitWithEncoding),
+    );
+    registry.insert(
+        "set-buffer-encoding".to_string(),
+        Box::new(SetBufferEncoding),
+    );
     registry.insert("read-file".to_string(), Box::new(ReadFile));
     registry.insert("delete-buffer".to_string(), Box::new(KillBuffer));
     registry.insert("select-buffer".to_string(), Box::new(SwitchToBuffer));
@@ -280,10 +305,83 @@ pub fn register_all(app: &mut crate::core::app::EditorApp) {
 
     // Diff commands
     registry.insert("sed-preview".to_string(), Box::new(SedPreviewCommand));
+    registry.insert(
+        "sed-preview-region".to_string(),
+        Box::new(SedPreviewRegionCommand),
+    );
     registry.insert("diff-next-hunk".to_string(), Box::new(DiffNextHunk));
     registry.insert("diff-previous-hunk".to_string(), Box::new(DiffPrevHunk));
     registry.insert("diff-accept-hunk".to_string(), Box::new(DiffAcceptHunk));
+    registry.insert("diff-reject-hunk".to_string(), Box::new(DiffRejectHunk));
+    registry.insert("diff-accept-all".to_string(), Box::new(DiffAcceptAll));
+    registry.insert("diff-undo-accept".to_string(), Box::new(DiffUndoAccept));
+    registry.insert("diff-split-hunk".to_string(), Box::new(DiffSplitHunk));
     registry.insert("diff-quit".to_string(), Box::new(DiffQuit));
+    registry.insert("apply-patch".to_string(), Box::new(ApplyPatchCommand));
+    registry.insert("reverse-patch".to_string(), Box::new(ReversePatchCommand));
+    registry.insert("patch-next-hunk".to_string(), Box::new(PatchNextHunk));
+    registry.insert(
+        "patch-previous-hunk".to_string(),
+        Box::new(PatchPreviousHunk),
+    );
+    registry.insert("patch-next-file".to_string(), Box::new(PatchNextFile));
+    registry.insert(
+        "patch-previous-file".to_string(),
+        Box::new(PatchPreviousFile),
+    );
+    registry.insert("diff-export-patch".to_string(), Box::new(DiffExportPatch));
+    registry.insert(
+        "resolve-conflicts".to_string(),
+        Box::new(ResolveConflictsCommand),
+    );
+    registry.insert("conflict-keep-ours".to_string(), Box::new(ConflictKeepOurs));
+    registry.insert(
+        "conflict-keep-theirs".to_string(),
+        Box::new(ConflictKeepTheirs),
+    );
+    registry.insert("conflict-keep-base".to_string(), Box::new(ConflictKeepBase));
+    registry.insert("conflict-keep-both".to_string(), Box::new(ConflictKeepBoth));
+    registry.insert("conflict-next".to_string(), Box::new(ConflictNext));
+    registry.insert("conflict-previous".to_string(), Box::new(ConflictPrevious));
+    registry.insert("conflict-quit".to_string(), Box::new(ConflictQuit));
+    registry.insert("diff-buffers".to_string(), Box::new(DiffBuffersCommand));
+    registry.insert(
+        "diff-buffer-with-file".to_string(),
+        Box::new(DiffBufferWithFile),
+    );
+    registry.insert(
+        "diff-copy-hunk-left".to_string(),
+        Box::new(DiffCopyHunkLeft),
+    );
+    registry.insert(
+        "diff-copy-hunk-right".to_string(),
+        Box::new(DiffCopyHunkRight),
+    );
+    registry.insert(
+        "sed-preview-files".to_string(),
+        Box::new(SedPreviewFilesCommand),
+    );
+    registry.insert("review-next-hunk".to_string(), Box::new(ReviewNextHunk));
+    registry.insert("review-previous-hunk".to_string(), Box::new(ReviewPrevHunk));
+    registry.insert("review-accept-hunk".to_string(), Box::new(ReviewAcceptHunk));
+    registry.insert("review-quit".to_string(), Box::new(ReviewQuit));
+    registry.insert("vcs-next-change".to_string(), Box::new(VcsNextChange));
+    registry.insert(
+        "vcs-previous-change".to_string(),
+        Box::new(VcsPreviousChange),
+    );
+    registry.insert("vcs-revert-hunk".to_string(), Box::new(VcsRevertHunk));
+    registry.insert("vcs-stage-hunk".to_string(), Box::new(VcsStageHunk));
+
+    // Sed debugger
+    registry.insert("sed-debug".to_string(), Box::new(SedDebugCommand));
+    registry.insert("sed-debug-step".to_string(), Box::new(SedDebugStep));
+    registry.insert(
+        "sed-debug-finish-cycle".to_string(),
+        Box::new(SedDebugFinishCycle),
+    );
+    registry.insert("sed-debug-run".to_string(), Box::new(SedDebugRun));
+    registry.insert("sed-debug-quit".to_string(), Box::new(SedDebugQuit));
 
     app.command_registry = registry;
 }
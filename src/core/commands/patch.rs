@@ -0,0 +1,435 @@
+//! Unified patch files
+//!
+//! Moves between the files and hunks of a patch buffer, applies a patch (or
+//! takes it back out) to the buffers of the files it names, and writes the
+//! current diff session out as a patch.
+
+use crate::core::app::EditorApp;
+use crate::core::buffer::{Buffer, BufferKind};
+use crate::core::command::Command;
+use crate::core::dispatcher::{DispatchResult, InputAction};
+use crate::core::id::BufferId;
+use crate::sed::diff::DiffView;
+use crate::sed::patch::{self, FilePatch, HunkResult};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the buffer that reports how each hunk applied
+const REPORT_BUFFER: &str = "*Patch Report*";
+
+#[derive(Clone)]
+pub struct ApplyPatchCommand;
+
+impl Command for ApplyPatchCommand {
+    fn execute(&self, _app: &mut EditorApp, _count: usize) -> DispatchResult {
+        DispatchResult::NeedsInput {
+            prompt: "Apply patch file (empty for this buffer): ".to_string(),
+            action: InputAction::ApplyPatch { reverse: false },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ReversePatchCommand;
+
+impl Command for ReversePatchCommand {
+    fn execute(&self, _app: &mut EditorApp, _count: usize) -> DispatchResult {
+        DispatchResult::NeedsInput {
+            prompt: "Reverse patch file (empty for this buffer): ".to_string(),
+            action: InputAction::ApplyPatch { reverse: true },
+        }
+    }
+}
+
+/// Apply the patch in the file `source`, or in the active buffer when it is
+/// empty, to the buffers of the files it names (opening them as needed).
+/// Each file's changes are one undo step; the outcome of every hunk goes to
+/// the report buffer.
+pub fn apply_patch(app: &mut EditorApp, source: &str, reverse: bool) -> Result<(), String> {
+    let source = source.trim();
+    let (text, base_dir) = if source.is_empty() {
+        let buffer = app.active_buffer().ok_or("No active buffer")?;
+        let dir = buffer
+            .filename
+            .as_ref()
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf);
+        (buffer.to_string(), dir)
+    } else {
+        let text =
+            fs::read_to_string(source).map_err(|e| format!("Can't read {}: {}", source, e))?;
+        (text, Path::new(source).parent().map(Path::to_path_buf))
+    };
+    let files = patch::parse(&text)?;
+    if files.iter().all(|file| file.hunks.is_empty()) {
+        return Err("No hunks found".to_string());
+    }
+
+    let mut report = String::new();
+    let (mut applied, mut failed) = (0, 0);
+    for file in &files {
+        let name = file.target_path().unwrap_or(&file.old_path).to_string();
+        report.push_str(&format!("{}\n", name));
+        let buffer_id = match target_buffer(app, file, base_dir.as_deref(), reverse) {
+            Ok(buffer_id) => buffer_id,
+            Err(e) => {
+                report.push_str(&format!("  {}; {} hunks skipped\n", e, file.hunks.len()));
+                failed += file.hunks.len();
+                continue;
+            }
+        };
+        let Some(buffer) = app.buffers.get_mut(&buffer_id) else {
+            continue;
+        };
+
+        let (patched, results) = patch::apply(
+            &buffer.to_string(),
+            &file.hunks,
+            reverse,
+            patch::DEFAULT_FUZZ,
+        );
+        if results.iter().any(|result| *result != HunkResult::Failed) {
+            let len = buffer.len();
+            let description = if reverse {
+                "reverse patch"
+            } else {
+                "apply patch"
+            };
+            buffer.replace(0, len, &patched, description);
+        }
+        for (hunk, result) in file.hunks.iter().zip(&results) {
+            let outcome = match *result {
+                HunkResult::Applied { offset: 0, fuzz: 0 } => "applied".to_string(),
+                HunkResult::Applied { offset, fuzz: 0 } => {
+                    format!("applied at offset {}", offset)
+                }
+                HunkResult::Applied { offset, fuzz } => {
+                    format!("applied at offset {} with fuzz {}", offset, fuzz)
+                }
+                HunkResult::Failed => "FAILED".to_string(),
+            };
+            if *result == HunkResult::Failed {
+                failed += 1;
+            } else {
+                applied += 1;
+            }
+            report.push_str(&format!("  {}: {}\n", hunk.header, outcome));
+        }
+    }
+
+    show_report(app, &report);
+    let verb = if reverse { "Reversed" } else { "Applied" };
+    app.message = Some(match failed {
+        0 => format!("{} {} hunks", verb, applied),
+        _ => format!(
+            "{} {} hunks, {} failed (see {})",
+            verb, applied, failed, REPORT_BUFFER
+        ),
+    });
+    Ok(())
+}
+
+/// The buffer a file patch applies to: an open buffer of the file, the file
+/// opened from disk, or a new buffer when the patch creates it
+fn target_buffer(
+    app: &mut EditorApp,
+    file: &FilePatch,
+    base_dir: Option<&Path>,
+    reverse: bool,
+) -> Result<BufferId, String> {
+    let name = file.target_path().ok_or("Deleting files isn't supported")?;
+    let candidates = [
+        base_dir.map(|dir| dir.join(name)),
+        Some(PathBuf::from(name)),
+    ];
+    let existing = candidates.iter().flatten().find(|path| path.is_file());
+
+    if let Some(path) = existing {
+        let canonical = fs::canonicalize(path).ok();
+        let open = app.buffers.iter().find(|(_, buffer)| {
+            buffer.buffer_kind() == BufferKind::Normal
+                && buffer
+                    .filename
+                    .as_ref()
+                    .and_then(|filename| fs::canonicalize(filename).ok())
+                    .is_some_and(|filename| Some(filename) == canonical)
+        });
+        if let Some((&buffer_id, _)) = open {
+            return Ok(buffer_id);
+        }
+        return app.load_file(path).map_err(|e| e.to_string());
+    }
+
+    if file.creates_file() && !reverse {
+        let path = candidates.into_iter().flatten().next().unwrap_or_default();
+        let mut buffer = Buffer::new();
+        buffer.filename = Some(path);
+        return Ok(app.add_buffer(buffer));
+    }
+    Err(format!("{} not found", name))
+}
+
+/// Show `report` in the report buffer, in another window
+fn show_report(app: &mut EditorApp, report: &str) {
+    let existing = app
+        .buffers
+        .iter()
+        .find(|(_, buffer)| buffer.filename.as_deref() == Some(Path::new(REPORT_BUFFER)));
+    let buffer_id = match existing {
+        Some((&buffer_id, _)) => buffer_id,
+        None => {
+            let mut buffer = Buffer::new();
+            buffer.filename = Some(PathBuf::from(REPORT_BUFFER));
+            app.add_buffer(buffer)
+        }
+    };
+    if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+        let len = buffer.len();
+        buffer.delete(0, len);
+        buffer.insert(0, report);
+        buffer.modified = false;
+    }
+
+    if app.windows.len() == 1 {
+        let patch_window = app.active_window;
+        app.split_window_horizontally();
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = buffer_id;
+        }
+        app.active_window = patch_window;
+    }
+}
+
+/// Move the cursor to the next (or previous) file or hunk header of the
+/// patch in the active buffer
+fn jump_to_header(app: &mut EditorApp, files: bool, forward: bool) -> DispatchResult {
+    let Some(window) = app.windows.get(&app.active_window) else {
+        return DispatchResult::NotHandled;
+    };
+    let Some(buffer) = app.buffers.get(&window.buffer_id) else {
+        return DispatchResult::NotHandled;
+    };
+    let parsed = match patch::parse(&buffer.to_string()) {
+        Ok(parsed) => parsed,
+        Err(e) => return DispatchResult::Info(format!("Not a patch: {}", e)),
+    };
+    let headers: Vec<usize> = if files {
+        parsed.iter().map(|file| file.header_line).collect()
+    } else {
+        parsed
+            .iter()
+            .flat_map(|file| file.hunks.iter().map(|hunk| hunk.header_line))
+            .collect()
+    };
+
+    let cursor = window.cursor_y;
+    let target = if forward {
+        headers.iter().copied().find(|&line| line > cursor)
+    } else {
+        headers.iter().rev().copied().find(|&line| line < cursor)
+    };
+    let Some(line) = target else {
+        let what = if files { "file" } else { "hunk" };
+        let place = if forward { "next" } else { "previous" };
+        return DispatchResult::Info(format!("No {} {}", place, what));
+    };
+
+    let buffer_id = window.buffer_id;
+    if let (Some(window), Some(buffer)) = (
+        app.windows.get_mut(&app.active_window),
+        app.buffers.get(&buffer_id),
+    ) {
+        window.cursor_y = line;
+        window.cursor_x = 0;
+        window.update_visual_cursor(buffer);
+        window.ensure_cursor_visible(buffer);
+    }
+    DispatchResult::Success
+}
+
+#[derive(Clone)]
+pub struct PatchNextHunk;
+
+impl Command for PatchNextHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        jump_to_header(app, false, true)
+    }
+}
+
+#[derive(Clone)]
+pub struct PatchPreviousHunk;
+
+impl Command for PatchPreviousHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        jump_to_header(app, false, false)
+    }
+}
+
+#[derive(Clone)]
+pub struct PatchNextFile;
+
+impl Command for PatchNextFile {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        jump_to_header(app, true, true)
+    }
+}
+
+#[derive(Clone)]
+pub struct PatchPreviousFile;
+
+impl Command for PatchPreviousFile {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        jump_to_header(app, true, false)
+    }
+}
+
+#[derive(Clone)]
+pub struct DiffExportPatch;
+
+impl Command for DiffExportPatch {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.diff_state.is_none() {
+            return DispatchResult::Info("No diff in progress".to_string());
+        }
+        DispatchResult::NeedsInput {
+            prompt: "Write patch to: ".to_string(),
+            action: InputAction::ExportPatch,
+        }
+    }
+}
+
+/// Write the diff shown by the current diff session to `path` as a unified
+/// patch, named after the buffers on each side
+pub fn export_diff_patch(app: &mut EditorApp, path: &str) -> Result<(), String> {
+    let state = app.diff_state.as_ref().ok_or("No diff in progress")?;
+    let text = |buffer_id: &BufferId| {
+        app.buffers
+            .get(buffer_id)
+            .map(|buffer| buffer.to_string())
+            .ok_or("A diff view was closed")
+    };
+    let original = text(&state.view_buffer_ids.0)?;
+    let modified = text(&state.view_buffer_ids.1)?;
+
+    // A sed preview changes the original buffer's own file
+    let name = |buffer_id: BufferId| {
+        app.buffers
+            .get(&buffer_id)
+            .map(|buffer| match &buffer.filename {
+                Some(filename) if buffer.buffer_kind() == BufferKind::Normal => {
+                    let cwd = std::env::current_dir().unwrap_or_default();
+                    let relative = filename.strip_prefix(&cwd).unwrap_or(filename);
+                    relative.display().to_string()
+                }
+                _ => buffer.display_name(),
+            })
+            .unwrap_or_default()
+    };
+    let old_name = name(state.original_buffer_id);
+    let new_name = match state.modified_buffer_id {
+        Some(buffer_id) if buffer_id != state.view_buffer_ids.1 => name(buffer_id),
+        _ => old_name.clone(),
+    };
+
+    let diff = DiffView::new(original, modified)
+        .unified_diff(&format!("a/{}", old_name), &format!("b/{}", new_name));
+    if diff.is_empty() {
+        return Err("No differences to export".to_string());
+    }
+    fs::write(path, diff).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    app.message = Some(format!("Wrote {}", path));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(app: &EditorApp, buffer_id: BufferId) -> String {
+        match app.buffers.get(&buffer_id) {
+            Some(buffer) => buffer.to_string(),
+            None => panic!("Missing buffer {}", buffer_id),
+        }
+    }
+
+    #[test]
+    fn test_export_and_apply_patch() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let file_path = dir.path().join("list.txt");
+        let patch_path = dir.path().join("change.patch");
+        if let Err(e) = fs::write(&file_path, "a\nb\nc\n") {
+            panic!("Failed to write {}: {}", file_path.display(), e);
+        }
+
+        // Preview a change to the file and export it
+        let mut app = EditorApp::new();
+        let buffer_id = match app.load_file(&file_path) {
+            Ok(buffer_id) => buffer_id,
+            Err(e) => panic!("Failed to open {}: {}", file_path.display(), e),
+        };
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = buffer_id;
+        }
+        if let Err(e) = crate::core::commands::diff::start_sed_diff(&mut app, "s/b/B/") {
+            panic!("Preview failed: {}", e);
+        }
+        if let Err(e) = export_diff_patch(&mut app, &patch_path.to_string_lossy()) {
+            panic!("Export failed: {}", e);
+        }
+        crate::core::commands::diff::DiffQuit.execute(&mut app, 1);
+        let exported = match fs::read_to_string(&patch_path) {
+            Ok(exported) => exported,
+            Err(e) => panic!("Failed to read the patch: {}", e),
+        };
+        assert!(exported.contains("-b\n+B\n"));
+
+        // The patch names the file by its absolute path here, so it finds
+        // the open buffer
+        if let Err(e) = apply_patch(&mut app, &patch_path.to_string_lossy(), false) {
+            panic!("Apply failed: {}", e);
+        }
+        assert_eq!(text(&app, buffer_id), "a\nB\nc\n");
+        assert_eq!(app.message.as_deref(), Some("Applied 1 hunks"));
+
+        // Applying again fails and says so; reversing restores the file
+        if let Err(e) = apply_patch(&mut app, &patch_path.to_string_lossy(), false) {
+            panic!("Apply failed: {}", e);
+        }
+        assert!(
+            app.message
+                .as_deref()
+                .is_some_and(|m| m.contains("1 failed"))
+        );
+        if let Err(e) = apply_patch(&mut app, &patch_path.to_string_lossy(), true) {
+            panic!("Reverse failed: {}", e);
+        }
+        assert_eq!(text(&app, buffer_id), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn test_navigate_patch_buffer() {
+        let mut app = EditorApp::new();
+        if let Some(buffer) = app.active_buffer_mut() {
+            buffer.insert(
+                0,
+                "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n--- a/y\n+++ b/y\n@@ -1 +1 @@\n-c\n+d\n",
+            );
+        }
+        let cursor = |app: &EditorApp| app.windows[&app.active_window].cursor_y;
+        PatchNextHunk.execute(&mut app, 1);
+        assert_eq!(cursor(&app), 2);
+        PatchNextFile.execute(&mut app, 1);
+        assert_eq!(cursor(&app), 5);
+        PatchNextHunk.execute(&mut app, 1);
+        assert_eq!(cursor(&app), 7);
+        PatchPreviousFile.execute(&mut app, 1);
+        assert_eq!(cursor(&app), 5);
+        assert!(matches!(
+            PatchNextFile.execute(&mut app, 1),
+            DispatchResult::Info(_)
+        ));
+    }
+}
@@ -0,0 +1,487 @@
+//! Sed preview across many files
+//!
+//! Runs one sed expression over a file set (a glob, a directory, or all open
+//! buffers) and shows every resulting hunk in a single `*Sed Preview*`
+//! buffer. Accepted hunks go to the open buffer of their file, or straight to
+//! the file on disk when it is not open.
+
+use crate::core::app::EditorApp;
+use crate::core::buffer::{Buffer, BufferKind};
+use crate::core::command::Command;
+use crate::core::dispatcher::{DispatchResult, InputAction};
+use crate::core::id::BufferId;
+use crate::sed::SedConfig;
+use crate::sed::diff::{DiffView, Hunk, ReviewFile, ReviewHunk, ReviewState, ReviewTarget};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// File set naming all open file buffers
+const ALL_BUFFERS: &str = ":buffers";
+
+#[derive(Clone)]
+pub struct SedPreviewFilesCommand;
+
+impl Command for SedPreviewFilesCommand {
+    fn execute(&self, _app: &mut EditorApp, _count: usize) -> DispatchResult {
+        DispatchResult::NeedsInput {
+            prompt: format!("Sed preview files (glob, directory or {}): ", ALL_BUFFERS),
+            action: InputAction::SedPreviewFiles,
+        }
+    }
+}
+
+/// Run `expression` over the files named by `files` and open the review
+/// buffer with the resulting hunks
+pub fn start_sed_review(
+    app: &mut EditorApp,
+    files: &str,
+    expression: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = SedConfig::new();
+    config.add_script(expression)?;
+
+    let sources = collect_sources(app, files.trim())?;
+    if sources.is_empty() {
+        return Err(format!("No files match {}", files).into());
+    }
+
+    let mut review_text = String::new();
+    let mut review_files = Vec::new();
+    let mut hunks = Vec::new();
+    for (file, original) in sources {
+        let mut output = Vec::new();
+        config.execute(Cursor::new(&original), &mut output)?;
+        // Files the script turns into invalid UTF-8 can't be shown as text
+        let Ok(modified) = String::from_utf8(output) else {
+            continue;
+        };
+
+        let view = DiffView::new(original, modified);
+        let file_hunks = view.context_hunks();
+        if file_hunks.is_empty() {
+            continue;
+        }
+
+        review_text.push_str(&format!("--- {}\n+++ {}\n", file.name, file.name));
+        for (hunk, text) in file_hunks.into_iter().zip(view.unified_hunks()) {
+            hunks.push(ReviewHunk {
+                file: review_files.len(),
+                hunk,
+                review_line: review_text.matches('\n').count(),
+                accepted: false,
+            });
+            review_text.push_str(&text);
+        }
+        review_files.push(file);
+    }
+
+    if hunks.is_empty() {
+        app.message = Some("No changes".to_string());
+        return Ok(());
+    }
+
+    let original_buffer_id = app
+        .windows
+        .get(&app.active_window)
+        .ok_or("No active window")?
+        .buffer_id;
+    let mut review_buffer = Buffer::from_string(review_text);
+    review_buffer.filename = Some(PathBuf::from("*Sed Preview*"));
+    review_buffer.buffer_kind = BufferKind::DiffReview;
+    let review_buffer_id = app.add_buffer(review_buffer);
+    if let Some(window) = app.windows.get_mut(&app.active_window) {
+        window.buffer_id = review_buffer_id;
+    }
+
+    app.message = Some(format!(
+        "{} hunks in {} files (y: accept, n/j: next, k: previous, q: quit)",
+        hunks.len(),
+        review_files.len()
+    ));
+    app.review_state = Some(ReviewState {
+        files: review_files,
+        hunks,
+        current_hunk: 0,
+        review_buffer_id,
+        original_buffer_id,
+    });
+    jump_to_review_hunk(app, 0);
+
+    Ok(())
+}
+
+/// Resolve a file set to the files it names and their current text.
+///
+/// Files open in a buffer are taken from the buffer, so unsaved edits are
+/// included and accepted hunks go to the buffer.
+fn collect_sources(app: &EditorApp, spec: &str) -> Result<Vec<(ReviewFile, String)>, String> {
+    let mut open_buffers: Vec<(BufferId, &Path)> = app
+        .buffers
+        .iter()
+        .filter(|(_, buffer)| buffer.buffer_kind() == BufferKind::Normal)
+        .filter_map(|(&id, buffer)| Some((id, buffer.filename.as_deref()?)))
+        .collect();
+    open_buffers.sort_by_key(|&(id, _)| id);
+
+    let buffer_source = |id: BufferId, path: &Path| {
+        let file = ReviewFile {
+            name: path.display().to_string(),
+            target: ReviewTarget::Buffer(id),
+        };
+        app.buffers
+            .get(&id)
+            .map(|buffer| (file, buffer.to_string()))
+    };
+
+    if spec == ALL_BUFFERS {
+        return Ok(open_buffers
+            .iter()
+            .filter_map(|&(id, path)| buffer_source(id, path))
+            .collect());
+    }
+
+    let paths = if Path::new(spec).is_dir() {
+        let mut paths = Vec::new();
+        walk_directory(Path::new(spec), &mut paths);
+        paths
+    } else if spec.contains(['*', '?']) {
+        glob_files(spec)
+    } else if Path::new(spec).is_file() {
+        vec![PathBuf::from(spec)]
+    } else {
+        return Err(format!("No such file or directory: {}", spec));
+    };
+
+    let open_paths: HashMap<PathBuf, BufferId> = open_buffers
+        .iter()
+        .filter_map(|&(id, path)| Some((fs::canonicalize(path).ok()?, id)))
+        .collect();
+
+    let mut sources = Vec::new();
+    for path in paths {
+        let open_id = fs::canonicalize(&path)
+            .ok()
+            .and_then(|canonical| open_paths.get(&canonical).copied());
+        if let Some(id) = open_id {
+            sources.extend(buffer_source(id, &path));
+            continue;
+        }
+        // Binary and unreadable files are skipped
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let file = ReviewFile {
+            name: path.display().to_string(),
+            target: ReviewTarget::File(path),
+        };
+        sources.push((file, content));
+    }
+    Ok(sources)
+}
+
+/// Collect the files below `dir` in sorted order, skipping hidden entries and
+/// symbolic links
+fn walk_directory(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let read_from = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(read_from) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => walk_directory(&path, paths),
+            Ok(kind) if kind.is_file() => paths.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Files matching a glob pattern with `*`, `?` and `**`
+fn glob_files(pattern: &str) -> Vec<PathBuf> {
+    // Walk from the directory part before the first wildcard
+    let wildcard = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    let base = match pattern[..wildcard].rfind('/') {
+        Some(0) => "/",
+        Some(slash) => &pattern[..slash],
+        None => "",
+    };
+
+    let mut paths = Vec::new();
+    walk_directory(Path::new(base), &mut paths);
+    let pattern: Vec<char> = pattern.chars().collect();
+    paths.retain(|path| {
+        let text: Vec<char> = path.to_string_lossy().chars().collect();
+        glob_match(&pattern, &text)
+    });
+    paths
+}
+
+/// Match `text` against a glob: `*` and `?` stay within one path component,
+/// `**` spans any number of directories
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) if rest.first() == Some(&'*') => {
+            let rest = &rest[1..];
+            // `**/` also matches no directory at all
+            let after_slash = rest.strip_prefix(&['/'][..]).unwrap_or(rest);
+            glob_match(after_slash, text) || (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some(('*', rest)) => {
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(('?', rest)) => {
+            matches!(text.first(), Some(&c) if c != '/') && glob_match(rest, &text[1..])
+        }
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Move the review buffer's cursor to hunk `index`
+fn jump_to_review_hunk(app: &mut EditorApp, index: usize) {
+    let (line, review_buffer_id) = match &app.review_state {
+        Some(state) if index < state.hunks.len() => {
+            (state.hunks[index].review_line, state.review_buffer_id)
+        }
+        _ => return,
+    };
+
+    let Some(window) = app.windows.get_mut(&app.active_window) else {
+        return;
+    };
+    if window.buffer_id != review_buffer_id {
+        return;
+    }
+    if let Some(buffer) = app.buffers.get(&review_buffer_id) {
+        window.cursor_y = line;
+        window.cursor_x = 0;
+        window.scroll_offset = line.saturating_sub(5);
+        window.update_visual_cursor(buffer);
+        window.ensure_cursor_visible(buffer);
+    }
+}
+
+/// Replace the lines of `hunk`, now starting at line `start`, with its new
+/// lines; fails if the text there no longer matches the preview
+fn replace_hunk_lines(buffer: &mut Buffer, start: usize, hunk: &Hunk) -> Result<(), String> {
+    let old_text = hunk.old_lines.concat();
+    let start_byte = buffer.line_to_byte(start);
+    match start_byte {
+        Some(pos) if buffer.get_range_as_string(pos, old_text.len()) == old_text => {
+            buffer.delete(pos, old_text.len());
+            buffer.insert(pos, &hunk.new_lines.concat());
+            Ok(())
+        }
+        _ => Err("changed since the preview".to_string()),
+    }
+}
+
+/// Apply hunk `index` of the review to its buffer or file
+fn apply_review_hunk(
+    app: &mut EditorApp,
+    state: &ReviewState,
+    index: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let review_hunk = &state.hunks[index];
+    let file = &state.files[review_hunk.file];
+    let start = state.current_start(index);
+    let in_file = |e: String| format!("{}: {}", file.name, e);
+
+    match &file.target {
+        ReviewTarget::Buffer(id) => {
+            let buffer = app
+                .buffers
+                .get_mut(id)
+                .ok_or_else(|| in_file("buffer was closed".to_string()))?;
+            replace_hunk_lines(buffer, start, &review_hunk.hunk).map_err(in_file)?;
+            buffer.modified = true;
+        }
+        ReviewTarget::File(path) => {
+            let mut buffer = Buffer::from_file(path)?;
+            replace_hunk_lines(&mut buffer, start, &review_hunk.hunk).map_err(in_file)?;
+            buffer.save()?;
+        }
+    }
+    Ok(())
+}
+
+/// Mark hunk `index` as applied in the review buffer
+fn mark_hunk_applied(app: &mut EditorApp, state: &ReviewState, index: usize) {
+    let line = state.hunks[index].review_line;
+    if let Some(buffer) = app.buffers.get_mut(&state.review_buffer_id)
+        && let (Some(start), Some(line_text)) = (buffer.line_to_byte(line), buffer.line(line))
+    {
+        buffer.insert(start + line_text.len(), " (applied)");
+        buffer.modified = false;
+    }
+}
+
+#[derive(Clone)]
+pub struct ReviewNextHunk;
+
+impl Command for ReviewNextHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.review_state.as_mut() else {
+            return DispatchResult::NotHandled;
+        };
+        if state.current_hunk + 1 < state.hunks.len() {
+            state.current_hunk += 1;
+        }
+        let index = state.current_hunk;
+        jump_to_review_hunk(app, index);
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct ReviewPrevHunk;
+
+impl Command for ReviewPrevHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.review_state.as_mut() else {
+            return DispatchResult::NotHandled;
+        };
+        state.current_hunk = state.current_hunk.saturating_sub(1);
+        let index = state.current_hunk;
+        jump_to_review_hunk(app, index);
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct ReviewAcceptHunk;
+
+impl Command for ReviewAcceptHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(mut state) = app.review_state.take() else {
+            return DispatchResult::NotHandled;
+        };
+
+        let index = state.current_hunk;
+        if state.hunks[index].accepted {
+            app.message = Some("Hunk already applied".to_string());
+        } else {
+            match apply_review_hunk(app, &state, index) {
+                Ok(()) => {
+                    state.hunks[index].accepted = true;
+                    mark_hunk_applied(app, &state, index);
+                    let name = &state.files[state.hunks[index].file].name;
+                    app.message = Some(format!("Applied hunk to {}", name));
+                    if index + 1 < state.hunks.len() {
+                        state.current_hunk += 1;
+                    }
+                }
+                Err(e) => app.message = Some(format!("Error: {}", e)),
+            }
+        }
+
+        let index = state.current_hunk;
+        app.review_state = Some(state);
+        jump_to_review_hunk(app, index);
+        DispatchResult::Success
+    }
+}
+
+#[derive(Clone)]
+pub struct ReviewQuit;
+
+impl Command for ReviewQuit {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(state) = app.review_state.take() else {
+            return DispatchResult::NotHandled;
+        };
+        for window in app.windows.values_mut() {
+            if window.buffer_id == state.review_buffer_id {
+                window.buffer_id = state.original_buffer_id;
+            }
+        }
+        app.buffers.remove(&state.review_buffer_id);
+        DispatchResult::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, path: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let path: Vec<char> = path.chars().collect();
+        glob_match(&pattern, &path)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob("src/*.rs", "src/main.rs"));
+        assert!(!glob("src/*.rs", "src/core/app.rs"));
+        assert!(glob("src/**/*.rs", "src/main.rs"));
+        assert!(glob("src/**/*.rs", "src/core/commands/diff.rs"));
+        assert!(glob("**/mod.rs", "src/sed/mod.rs"));
+        assert!(glob("a?c.txt", "abc.txt"));
+        assert!(!glob("a?c.txt", "a/c.txt"));
+    }
+
+    #[test]
+    fn test_review_applies_to_files_and_buffers() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let on_disk = dir.path().join("a.txt");
+        let opened = dir.path().join("b.txt");
+        for path in [&on_disk, &opened] {
+            if let Err(e) = fs::write(path, "foo\nbar\nfoo\n") {
+                panic!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+
+        let mut app = EditorApp::new();
+        let opened_id = match Buffer::from_file(&opened) {
+            Ok(buffer) => app.add_buffer(buffer),
+            Err(e) => panic!("Failed to open {}: {}", opened.display(), e),
+        };
+
+        let spec = dir.path().to_string_lossy().to_string();
+        if let Err(e) = start_sed_review(&mut app, &spec, "s/foo/baz/") {
+            panic!("Review failed: {}", e);
+        }
+        match &app.review_state {
+            Some(state) => assert_eq!(state.hunks.len(), 2),
+            None => panic!("No review started"),
+        }
+
+        ReviewAcceptHunk.execute(&mut app, 1);
+        ReviewAcceptHunk.execute(&mut app, 1);
+        match fs::read_to_string(&on_disk) {
+            Ok(content) => assert_eq!(content, "baz\nbar\nbaz\n"),
+            Err(e) => panic!("Failed to read {}: {}", on_disk.display(), e),
+        }
+        match app.buffers.get(&opened_id) {
+            Some(buffer) => assert_eq!(buffer.to_string(), "baz\nbar\nbaz\n"),
+            None => panic!("Buffer disappeared"),
+        }
+
+        ReviewQuit.execute(&mut app, 1);
+        assert!(app.review_state.is_none());
+    }
+}
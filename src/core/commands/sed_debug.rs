@@ -0,0 +1,238 @@
+//! Sed script debugger
+//!
+//! Steps a sed script over the active buffer, showing the script with the
+//! next command marked, the pattern and hold spaces, and the output so far
+//! in split windows.
+
+use crate::core::app::EditorApp;
+use crate::core::buffer::{Buffer, BufferKind};
+use crate::core::command::Command;
+use crate::core::dispatcher::{DispatchResult, InputAction};
+use crate::core::id::BufferId;
+use crate::sed::debug::Debugger;
+use crate::sed::{SedConfig, Step};
+use std::path::PathBuf;
+
+/// An active sed debugging session
+pub struct SedDebugState {
+    pub debugger: Debugger,
+    pub script_buffer_id: BufferId,
+    pub status_buffer_id: BufferId,
+    pub output_buffer_id: BufferId,
+    /// Buffer shown before the session started
+    pub original_buffer_id: BufferId,
+}
+
+#[derive(Clone)]
+pub struct SedDebugCommand;
+
+impl Command for SedDebugCommand {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        if app.active_buffer().is_none() {
+            return DispatchResult::NotHandled;
+        }
+
+        DispatchResult::NeedsInput {
+            prompt: "Sed debug expression: ".to_string(),
+            action: InputAction::SedDebug,
+        }
+    }
+}
+
+/// Start debugging `config` over the active buffer
+pub fn start_sed_debug(
+    app: &mut EditorApp,
+    config: SedConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (input, original_buffer_id) = {
+        let window = app
+            .windows
+            .get(&app.active_window)
+            .ok_or("No active window")?;
+        let buffer = app
+            .buffers
+            .get(&window.buffer_id)
+            .ok_or("No active buffer")?;
+        (buffer.to_string().into_bytes(), window.buffer_id)
+    };
+    let debugger = Debugger::new(config, input)?;
+
+    let mut add_view = |name: &str| {
+        let mut buffer = Buffer::new();
+        buffer.filename = Some(PathBuf::from(name));
+        buffer.buffer_kind = BufferKind::SedDebug;
+        app.add_buffer(buffer)
+    };
+    let script_buffer_id = add_view("*Sed Script*");
+    let status_buffer_id = add_view("*Sed State*");
+    let output_buffer_id = add_view("*Sed Output*");
+
+    // Script on the left; state above the output on the right
+    app.delete_other_windows();
+    let script_window = app.active_window;
+    app.split_window_vertically();
+    if let Some(window) = app.windows.get_mut(&app.active_window) {
+        window.buffer_id = status_buffer_id;
+    }
+    app.split_window_horizontally();
+    if let Some(window) = app.windows.get_mut(&app.active_window) {
+        window.buffer_id = output_buffer_id;
+    }
+    app.active_window = script_window;
+    if let Some(window) = app.windows.get_mut(&script_window) {
+        window.buffer_id = script_buffer_id;
+    }
+
+    app.sed_debug = Some(SedDebugState {
+        debugger,
+        script_buffer_id,
+        status_buffer_id,
+        output_buffer_id,
+        original_buffer_id,
+    });
+    refresh_views(app);
+    app.message =
+        Some("Sed debugger (s: step, c: finish cycle, g: run to end, q: quit)".to_string());
+    Ok(())
+}
+
+/// Rewrite the script, state and output buffers from the debugger
+fn refresh_views(app: &mut EditorApp) {
+    let Some(session) = &app.sed_debug else {
+        return;
+    };
+    let views = [
+        (session.script_buffer_id, session.debugger.script_listing()),
+        (session.status_buffer_id, session.debugger.status()),
+        (
+            session.output_buffer_id,
+            String::from_utf8_lossy(session.debugger.output()).into_owned(),
+        ),
+    ];
+
+    for (buffer_id, text) in views {
+        if let Some(buffer) = app.buffers.get_mut(&buffer_id) {
+            let len = buffer.len();
+            buffer.delete(0, len);
+            buffer.insert(0, &text);
+            buffer.modified = false;
+        }
+    }
+}
+
+/// Step the session until `stop` accepts a step, then refresh the views
+fn advance(app: &mut EditorApp, stop: impl Fn(Step) -> bool) -> DispatchResult {
+    let Some(session) = app.sed_debug.as_mut() else {
+        return DispatchResult::NotHandled;
+    };
+    if session.debugger.is_finished() {
+        app.message = Some("Script finished".to_string());
+        return DispatchResult::Success;
+    }
+
+    match session.debugger.run_until(stop) {
+        Ok(true) => {}
+        Ok(false) => app.message = Some("Stopped: the script may be looping".to_string()),
+        Err(e) => app.message = Some(format!("Sed Error: {}", e)),
+    }
+    refresh_views(app);
+    DispatchResult::Success
+}
+
+#[derive(Clone)]
+pub struct SedDebugStep;
+
+impl Command for SedDebugStep {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        advance(app, |_| true)
+    }
+}
+
+#[derive(Clone)]
+pub struct SedDebugFinishCycle;
+
+impl Command for SedDebugFinishCycle {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        advance(app, |step| step == Step::EndCycle)
+    }
+}
+
+#[derive(Clone)]
+pub struct SedDebugRun;
+
+impl Command for SedDebugRun {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        advance(app, |_| false)
+    }
+}
+
+#[derive(Clone)]
+pub struct SedDebugQuit;
+
+impl Command for SedDebugQuit {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let Some(session) = app.sed_debug.take() else {
+            return DispatchResult::NotHandled;
+        };
+        app.delete_other_windows();
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = session.original_buffer_id;
+        }
+        for buffer_id in [
+            session.script_buffer_id,
+            session.status_buffer_id,
+            session.output_buffer_id,
+        ] {
+            app.buffers.remove(&buffer_id);
+        }
+        DispatchResult::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_text(app: &EditorApp, buffer_id: BufferId) -> String {
+        match app.buffers.get(&buffer_id) {
+            Some(buffer) => buffer.to_string(),
+            None => panic!("Missing buffer {}", buffer_id),
+        }
+    }
+
+    #[test]
+    fn test_debug_session() {
+        let mut app = EditorApp::new();
+        if let Some(buffer) = app.active_buffer_mut() {
+            buffer.insert(0, "one\ntwo\n");
+        }
+        let mut config = SedConfig::new();
+        if let Err(e) = config.add_script("s/o/0/g") {
+            panic!("Failed to parse script: {}", e);
+        }
+        if let Err(e) = start_sed_debug(&mut app, config) {
+            panic!("Failed to start debugger: {}", e);
+        }
+        assert_eq!(app.windows.len(), 3);
+
+        let (status_id, output_id) = match &app.sed_debug {
+            Some(session) => (session.status_buffer_id, session.output_buffer_id),
+            None => panic!("No debug session"),
+        };
+        SedDebugStep.execute(&mut app, 1);
+        SedDebugStep.execute(&mut app, 1);
+        assert!(buffer_text(&app, status_id).contains("Pattern space:\n0ne\n"));
+        SedDebugFinishCycle.execute(&mut app, 1);
+        assert_eq!(buffer_text(&app, output_id), "0ne\n");
+        SedDebugRun.execute(&mut app, 1);
+        assert_eq!(buffer_text(&app, output_id), "0ne\ntw0\n");
+
+        SedDebugQuit.execute(&mut app, 1);
+        assert!(app.sed_debug.is_none());
+        assert_eq!(app.windows.len(), 1);
+        assert_eq!(
+            app.active_buffer().map(|b| b.to_string()),
+            Some("one\ntwo\n".to_string())
+        );
+    }
+}
@@ -151,9 +151,10 @@ impl Command for JustifyParagraph {
                 buffer.delete(start_byte, delete_len);
 
                 // Insert new paragraph
-                let mut new_paragraph = formatted_lines.join("\n");
+                let line_ending = buffer.line_ending.as_str();
+                let mut new_paragraph = formatted_lines.join(line_ending);
                 if end_line < total_lines || delete_len > 0 {
-                    new_paragraph.push('\n');
+                    new_paragraph.push_str(line_ending);
                 }
                 buffer.insert(start_byte, &new_paragraph);
 
@@ -226,7 +227,7 @@ impl Command for DeleteBlankLines {
                     buffer.delete(start_byte, end_byte - start_byte);
 
                     if is_current_blank {
-                        buffer.insert(start_byte, "\n");
+                        buffer.insert(start_byte, buffer.line_ending.as_str());
                     }
                 }
             }
@@ -711,17 +712,18 @@ impl Command for WrapWord {
                         Some(b) => b,
                         None => 0,
                     };
-                    buffer.insert(insert_pos, "\n");
+                    buffer.insert(insert_pos, buffer.line_ending.as_str());
                     window.move_down(buffer);
                     window.beginning_of_line(buffer);
                 } else {
                     // Found space at break_pos - use if let to safely unwrap
                     if let Some(pos) = break_pos {
+                        let newline = buffer.line_ending.as_str();
                         buffer.delete(pos, 1); // Delete 1 char (space/tab)
-                        buffer.insert(pos, "\n");
+                        buffer.insert(pos, newline);
 
                         // Move cursor to end of wrap
-                        let new_cursor_byte = pos + 1 + word_len;
+                        let new_cursor_byte = pos + newline.len() + word_len;
                         let (line, col) = byte_to_cursor_position(buffer, new_cursor_byte);
                         window.cursor_y = line;
                         window.cursor_x = col;
@@ -0,0 +1,204 @@
+//! Git change navigation, and reverting or staging single hunks
+
+use crate::core::app::EditorApp;
+use crate::core::command::Command;
+use crate::core::dispatcher::DispatchResult;
+use crate::core::vcs;
+
+/// Refresh the changes of the active buffer against `HEAD`, returning the
+/// cursor line
+fn refreshed_cursor(app: &mut EditorApp) -> Result<usize, String> {
+    let window = app
+        .windows
+        .get(&app.active_window)
+        .ok_or("No active window")?;
+    let cursor = window.cursor_y;
+    let buffer = app
+        .buffers
+        .get_mut(&window.buffer_id)
+        .ok_or("No active buffer")?;
+    vcs::refresh(buffer);
+    if !buffer.vcs.is_tracked() {
+        return Err("The file is not tracked by git".to_string());
+    }
+    Ok(cursor)
+}
+
+/// Move to the start of the next or previous change
+fn jump_to_change(app: &mut EditorApp, forward: bool) -> DispatchResult {
+    let cursor = match refreshed_cursor(app) {
+        Ok(cursor) => cursor,
+        Err(e) => return DispatchResult::Info(e),
+    };
+    let Some(buffer) = app.active_buffer() else {
+        return DispatchResult::NotHandled;
+    };
+    let mut starts = buffer.vcs.hunks().iter().map(|hunk| hunk.new_start);
+    let target = if forward {
+        starts.find(|&line| line > cursor)
+    } else {
+        starts.rev().find(|&line| line < cursor)
+    };
+    let Some(line) = target else {
+        let place = if forward { "next" } else { "previous" };
+        return DispatchResult::Info(format!("No {} change", place));
+    };
+
+    let buffer_id = app.windows[&app.active_window].buffer_id;
+    if let (Some(window), Some(buffer)) = (
+        app.windows.get_mut(&app.active_window),
+        app.buffers.get(&buffer_id),
+    ) {
+        window.cursor_y = line.min(buffer.line_count().saturating_sub(1));
+        window.cursor_x = 0;
+        window.update_visual_cursor(buffer);
+        window.ensure_cursor_visible(buffer);
+    }
+    DispatchResult::Success
+}
+
+/// Index of the hunk at the cursor
+fn hunk_at_cursor(app: &mut EditorApp) -> Result<usize, String> {
+    let cursor = refreshed_cursor(app)?;
+    app.active_buffer()
+        .and_then(|buffer| buffer.vcs.hunk_at(cursor))
+        .ok_or_else(|| "No change at point".to_string())
+}
+
+#[derive(Clone)]
+pub struct VcsNextChange;
+
+impl Command for VcsNextChange {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        jump_to_change(app, true)
+    }
+}
+
+#[derive(Clone)]
+pub struct VcsPreviousChange;
+
+impl Command for VcsPreviousChange {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        jump_to_change(app, false)
+    }
+}
+
+#[derive(Clone)]
+pub struct VcsRevertHunk;
+
+impl Command for VcsRevertHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let index = match hunk_at_cursor(app) {
+            Ok(index) => index,
+            Err(e) => return DispatchResult::Info(e),
+        };
+        let Some(buffer) = app.active_buffer_mut() else {
+            return DispatchResult::NotHandled;
+        };
+        let hunk = buffer.vcs.hunks()[index].clone();
+        let start = buffer.line_to_byte(hunk.new_start).unwrap_or(buffer.len());
+        let end = buffer
+            .line_to_byte(hunk.new_start + hunk.new_lines.len())
+            .unwrap_or(buffer.len());
+        buffer.replace(start, end - start, &hunk.old_lines.concat(), "revert hunk");
+
+        let line = hunk.new_start.min(buffer.line_count().saturating_sub(1));
+        let buffer_id = app.windows[&app.active_window].buffer_id;
+        if let (Some(window), Some(buffer)) = (
+            app.windows.get_mut(&app.active_window),
+            app.buffers.get(&buffer_id),
+        ) {
+            window.cursor_y = line;
+            window.cursor_x = 0;
+            window.update_visual_cursor(buffer);
+        }
+        DispatchResult::Info("Reverted hunk to HEAD".to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct VcsStageHunk;
+
+impl Command for VcsStageHunk {
+    fn execute(&self, app: &mut EditorApp, _count: usize) -> DispatchResult {
+        let staged = hunk_at_cursor(app).and_then(|index| match app.active_buffer() {
+            Some(buffer) => vcs::stage_hunk(buffer, index),
+            None => Err("No active buffer".to_string()),
+        });
+        match staged {
+            Ok(()) => DispatchResult::Info("Staged hunk".to_string()),
+            Err(e) => DispatchResult::Info(format!("Failed to stage hunk: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::Buffer;
+
+    #[test]
+    fn test_navigate_and_revert() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(args)
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        let path = dir.path().join("f.txt");
+        if let Err(e) = std::fs::write(&path, "one\ntwo\nthree\nfour\n") {
+            panic!("Failed to write: {}", e);
+        }
+        let committed = git(&["init", "-q"])
+            && git(&["add", "f.txt"])
+            && git(&[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "-m",
+                "init",
+            ]);
+        if !committed {
+            return; // No git available
+        }
+
+        let mut app = EditorApp::new();
+        let buffer = match Buffer::from_file(&path) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Failed to load: {}", e),
+        };
+        let buffer_id = app.add_buffer(buffer);
+        if let Some(window) = app.windows.get_mut(&app.active_window) {
+            window.buffer_id = buffer_id;
+        }
+        if let Some(buffer) = app.active_buffer_mut() {
+            buffer.replace(4, 3, "TWO", "edit");
+            let end = buffer.len();
+            buffer.insert(end, "five\n");
+        }
+
+        VcsNextChange.execute(&mut app, 1);
+        assert_eq!(app.windows[&app.active_window].cursor_y, 1);
+        VcsNextChange.execute(&mut app, 1);
+        assert_eq!(app.windows[&app.active_window].cursor_y, 4);
+        assert!(matches!(
+            VcsNextChange.execute(&mut app, 1),
+            DispatchResult::Info(_)
+        ));
+        VcsPreviousChange.execute(&mut app, 1);
+        assert_eq!(app.windows[&app.active_window].cursor_y, 1);
+
+        VcsRevertHunk.execute(&mut app, 1);
+        let text = app.active_buffer().map(|buffer| buffer.to_string());
+        assert_eq!(text.as_deref(), Some("one\ntwo\nthree\nfour\nfive\n"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Merge conflict markers
+//!
+//! Finds the conflicts git (or `diff3 -m`) leaves in a file:
+//!
+//! ```text
+//! <<<<<<< ours
+//! our lines
+//! ||||||| base        (diff3 style only)
+//! the common ancestor
+//! =======
+//! their lines
+//! >>>>>>> theirs
+//! ```
+
+/// Length of a conflict marker
+const MARKER_LEN: usize = 7;
+
+/// One conflict region
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    /// Line of the `<<<<<<<` marker
+    pub start_line: usize,
+    /// Line of the `>>>>>>>` marker
+    pub end_line: usize,
+    pub ours: Vec<String>,
+    /// The common ancestor, in diff3-style conflicts
+    pub base: Option<Vec<String>>,
+    pub theirs: Vec<String>,
+}
+
+/// Which side of a conflict to keep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Base,
+    /// Ours followed by theirs
+    Both,
+}
+
+impl Conflict {
+    /// The text that replaces the conflict, markers included; `None` when
+    /// keeping the base of a conflict that has none
+    pub fn resolve(&self, resolution: Resolution) -> Option<String> {
+        match resolution {
+            Resolution::Ours => Some(self.ours.concat()),
+            Resolution::Theirs => Some(self.theirs.concat()),
+            Resolution::Base => self.base.as_ref().map(|base| base.concat()),
+            Resolution::Both => Some(self.ours.concat() + &self.theirs.concat()),
+        }
+    }
+}
+
+/// Whether `line` is a conflict marker made of `marker`. Only `=======`
+/// stands alone; the others may be followed by a label.
+fn is_marker(line: &str, marker: char) -> bool {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let Some(rest) = line.strip_prefix(&marker.to_string().repeat(MARKER_LEN)) else {
+        return false;
+    };
+    match marker {
+        '=' => rest.is_empty(),
+        _ => rest.is_empty() || rest.starts_with(' '),
+    }
+}
+
+/// Where the lines of an open conflict go
+enum Section {
+    Ours,
+    Base,
+    Theirs,
+}
+
+/// Every well-formed conflict in `text`, in order
+pub fn find_conflicts(text: &str) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let mut open: Option<(Conflict, Section)> = None;
+
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        // A new start marker abandons an unfinished conflict
+        if is_marker(line, '<') {
+            let conflict = Conflict {
+                start_line: index,
+                end_line: index,
+                ours: Vec::new(),
+                base: None,
+                theirs: Vec::new(),
+            };
+            open = Some((conflict, Section::Ours));
+            continue;
+        }
+        let Some((conflict, section)) = open.as_mut() else {
+            continue;
+        };
+
+        match section {
+            Section::Ours if is_marker(line, '|') => {
+                conflict.base = Some(Vec::new());
+                *section = Section::Base;
+            }
+            Section::Ours | Section::Base if is_marker(line, '=') => *section = Section::Theirs,
+            Section::Theirs if is_marker(line, '>') => {
+                if let Some((mut conflict, _)) = open.take() {
+                    conflict.end_line = index;
+                    conflicts.push(conflict);
+                }
+            }
+            Section::Ours => conflict.ours.push(line.to_string()),
+            Section::Base => {
+                if let Some(base) = conflict.base.as_mut() {
+                    base.push(line.to_string());
+                }
+            }
+            Section::Theirs => conflict.theirs.push(line.to_string()),
+        }
+    }
+    conflicts
+}
+
+/// `text` with every conflict resolved the same way, and the first line of
+/// each conflict's text in the result. Conflicts that can't be resolved that
+/// way keep their markers.
+pub fn resolve_all(
+    text: &str,
+    conflicts: &[Conflict],
+    resolution: Resolution,
+) -> (String, Vec<usize>) {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut result = String::with_capacity(text.len());
+    let mut starts = Vec::with_capacity(conflicts.len());
+    let mut result_lines = 0;
+    let mut line = 0;
+
+    for conflict in conflicts {
+        for kept in &lines[line..conflict.start_line] {
+            result.push_str(kept);
+        }
+        result_lines += conflict.start_line - line;
+        starts.push(result_lines);
+
+        let replacement = match conflict.resolve(resolution) {
+            Some(replacement) => replacement,
+            None => lines[conflict.start_line..=conflict.end_line].concat(),
+        };
+        result_lines += replacement.split_inclusive('\n').count();
+        result.push_str(&replacement);
+        line = conflict.end_line + 1;
+    }
+    for kept in &lines[line.min(lines.len())..] {
+        result.push_str(kept);
+    }
+    (result, starts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERGE: &str = "\
+top
+<<<<<<< HEAD
+ours
+=======
+theirs 1
+theirs 2
+>>>>>>> branch
+middle
+<<<<<<< HEAD
+a
+||||||| base
+b
+=======
+c
+>>>>>>> branch
+";
+
+    #[test]
+    fn test_find_conflicts() {
+        let conflicts = find_conflicts(MERGE);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!((conflicts[0].start_line, conflicts[0].end_line), (1, 6));
+        assert_eq!(conflicts[0].ours, ["ours\n"]);
+        assert_eq!(conflicts[0].theirs, ["theirs 1\n", "theirs 2\n"]);
+        assert_eq!(conflicts[0].base, None);
+        assert_eq!(conflicts[1].base, Some(vec!["b\n".to_string()]));
+        assert_eq!(
+            conflicts[1].resolve(Resolution::Both),
+            Some("a\nc\n".to_string())
+        );
+        assert_eq!(conflicts[0].resolve(Resolution::Base), None);
+    }
+
+    #[test]
+    fn test_markers_need_their_exact_form() {
+        assert!(find_conflicts("<<<<<<<< x\na\n=======\nb\n>>>>>>>\n").is_empty());
+        assert!(find_conflicts("<<<<<<<\na\n======= x\nb\n>>>>>>>\n").is_empty());
+        // An unfinished conflict is dropped, the next one still found
+        assert_eq!(
+            find_conflicts("<<<<<<<\na\n<<<<<<<\nb\n=======\nc\n>>>>>>>\n").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_resolve_all() {
+        let conflicts = find_conflicts(MERGE);
+        let (ours, starts) = resolve_all(MERGE, &conflicts, Resolution::Ours);
+        assert_eq!(ours, "top\nours\nmiddle\na\n");
+        assert_eq!(starts, [1, 3]);
+        let (theirs, starts) = resolve_all(MERGE, &conflicts, Resolution::Theirs);
+        assert_eq!(theirs, "top\ntheirs 1\ntheirs 2\nmiddle\nc\n");
+        assert_eq!(starts, [1, 4]);
+    }
+}
@@ -24,6 +24,36 @@ pub enum InputAction {
     GotoLine,
     Calculator,
     SedPreview,
+    /// Expression of a sed preview over the region
+    SedPreviewRegion,
+    /// File set of a multi-file sed preview
+    SedPreviewFiles,
+    /// Expression of a multi-file sed preview over the given file set
+    SedPreviewFilesExpression(String),
+    /// Script for the sed debugger
+    SedDebug,
+    /// First side of a two-buffer diff
+    DiffBuffers,
+    /// Second side of a two-buffer diff, against the given first side
+    DiffBuffersWith(String),
+    /// What to do about a file that changed on disk: reload, overwrite or
+    /// diff
+    FileChanged,
+    /// Patch file to apply, or to take back out when `reverse` is set
+    ApplyPatch {
+        reverse: bool,
+    },
+    /// File to write the current diff to as a patch
+    ExportPatch,
+    /// Line ending to convert the buffer to
+    SetLineEndings,
+    /// Encoding to decode the buffer's file again with
+    RevisitWithEncoding,
+    /// Encoding to save the buffer in
+    SetBufferEncoding,
+    /// What to do about recovery data left by a crash: restore, diff or
+    /// delete it
+    RecoverFile,
     ExecuteNamedCommand,
     DescribeKey,
 }
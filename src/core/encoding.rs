@@ -0,0 +1,442 @@
+//! File encodings
+//!
+//! Files are decoded into the rope when loaded and encoded again when saved.
+//! Bytes that are not valid in the file's encoding are kept as escape
+//! markers: characters `U+10FF00` to `U+10FFFF` of the last private use
+//! plane, one per byte, which are written back as the byte they stand for.
+//! That way saving a file the editor could not fully decode leaves the
+//! undecodable parts untouched. A file that really has one of those
+//! characters gets each of its bytes escaped instead, so it is kept too.
+
+use std::borrow::Cow;
+
+/// First escape marker; marker `ESCAPE_BASE + b` stands for byte `b`
+const ESCAPE_BASE: u32 = 0x10FF00;
+
+/// Windows-1252 characters for bytes 0x80 to 0x9F; `None` where the code
+/// page leaves the byte undefined
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('€'),
+    None,
+    Some('‚'),
+    Some('ƒ'),
+    Some('„'),
+    Some('…'),
+    Some('†'),
+    Some('‡'),
+    Some('ˆ'),
+    Some('‰'),
+    Some('Š'),
+    Some('‹'),
+    Some('Œ'),
+    None,
+    Some('Ž'),
+    None,
+    None,
+    Some('‘'),
+    Some('’'),
+    Some('“'),
+    Some('”'),
+    Some('•'),
+    Some('–'),
+    Some('—'),
+    Some('˜'),
+    Some('™'),
+    Some('š'),
+    Some('›'),
+    Some('œ'),
+    None,
+    Some('ž'),
+    Some('Ÿ'),
+];
+
+/// A character encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+/// An encoding and whether the file starts with a byte order mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileEncoding {
+    pub encoding: Encoding,
+    pub bom: bool,
+}
+
+/// The marker standing for `byte`
+pub fn escape_byte(byte: u8) -> char {
+    char::from_u32(ESCAPE_BASE + u32::from(byte)).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The byte `ch` stands for, if it is an escape marker
+pub fn escaped_byte(ch: char) -> Option<u8> {
+    u32::from(ch)
+        .checked_sub(ESCAPE_BASE)
+        .and_then(|byte| u8::try_from(byte).ok())
+}
+
+/// How many escape markers `text` has
+pub fn count_escapes(text: &str) -> usize {
+    text.chars()
+        .filter(|&ch| escaped_byte(ch).is_some())
+        .count()
+}
+
+impl Encoding {
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            Encoding::Latin1 | Encoding::Windows1252 => &[],
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+            Encoding::Windows1252 => "Windows-1252",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" | "utf-16" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Decode `bytes`, escaping the ones that can't be decoded
+    fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => decode_utf8(bytes),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+            Encoding::Latin1 => bytes.iter().map(|&byte| char::from(byte)).collect(),
+            Encoding::Windows1252 => bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x80..=0x9F => {
+                        WINDOWS_1252_HIGH[usize::from(byte - 0x80)].unwrap_or(escape_byte(byte))
+                    }
+                    byte => char::from(byte),
+                })
+                .collect(),
+        }
+    }
+
+    /// Append `ch` encoded to `out`, if the encoding has it
+    fn encode_char(self, ch: char, out: &mut Vec<u8>) -> bool {
+        if let Some(byte) = escaped_byte(ch) {
+            out.push(byte);
+            return true;
+        }
+        match self {
+            Encoding::Utf8 => out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    out.extend_from_slice(&if self == Encoding::Utf16Le {
+                        unit.to_le_bytes()
+                    } else {
+                        unit.to_be_bytes()
+                    });
+                }
+            }
+            Encoding::Latin1 => match u8::try_from(u32::from(ch)) {
+                Ok(byte) => out.push(byte),
+                Err(_) => return false,
+            },
+            Encoding::Windows1252 => {
+                let high = WINDOWS_1252_HIGH
+                    .iter()
+                    .position(|&high| high == Some(ch))
+                    .and_then(|index| u8::try_from(0x80 + index).ok());
+                match (high, u8::try_from(u32::from(ch))) {
+                    (Some(byte), _) => out.push(byte),
+                    (None, Ok(byte)) if !(0x80..=0x9F).contains(&byte) => out.push(byte),
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+}
+
+impl FileEncoding {
+    /// Name for the status line and prompts, such as `UTF-8` or
+    /// `UTF-16LE-BOM`
+    pub fn name(&self) -> String {
+        if self.bom {
+            format!("{}-BOM", self.encoding.name())
+        } else {
+            self.encoding.name().to_string()
+        }
+    }
+
+    /// Parse a name as shown by `name`, in any case. A `-bom` suffix asks
+    /// for a byte order mark, which only the Unicode encodings have.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        let (base, bom) = match name.strip_suffix("-bom") {
+            Some(base) => (base, true),
+            None => (name.as_str(), false),
+        };
+        let encoding = Encoding::from_name(base)?;
+        if bom && encoding.bom().is_empty() {
+            return None;
+        }
+        Some(Self { encoding, bom })
+    }
+
+    /// Guess the encoding of `bytes`: a byte order mark decides it,
+    /// otherwise NUL bytes in every other position suggest UTF-16, and text
+    /// that is not UTF-8 is taken as Windows-1252, or Latin-1 if it uses
+    /// bytes Windows-1252 leaves undefined. UTF-8 with a few bad bytes among
+    /// valid multibyte characters stays UTF-8.
+    pub fn detect(bytes: &[u8]) -> Self {
+        for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+            if bytes.starts_with(encoding.bom()) {
+                return Self {
+                    encoding,
+                    bom: true,
+                };
+            }
+        }
+        let encoding = if let Some(encoding) = detect_utf16(bytes) {
+            encoding
+        } else if looks_like_utf8(bytes) {
+            Encoding::Utf8
+        } else if bytes.iter().any(|&byte| {
+            (0x80..=0x9F).contains(&byte) && WINDOWS_1252_HIGH[usize::from(byte - 0x80)].is_none()
+        }) {
+            Encoding::Latin1
+        } else {
+            Encoding::Windows1252
+        };
+        Self {
+            encoding,
+            bom: false,
+        }
+    }
+
+    /// Decode a whole file, skipping its byte order mark
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let bom = self.encoding.bom();
+        let body = match bytes.strip_prefix(bom) {
+            Some(body) if self.bom => body,
+            _ => bytes,
+        };
+        self.encoding.decode(body)
+    }
+
+    /// The byte order mark to write at the start of the file
+    pub fn header(&self) -> &'static [u8] {
+        if self.bom { self.encoding.bom() } else { &[] }
+    }
+
+    /// Encode `text`, which may be one piece of a larger text
+    pub fn encode<'a>(&self, text: &'a str) -> Result<Cow<'a, [u8]>, String> {
+        if self.encoding == Encoding::Utf8 && count_escapes(text) == 0 {
+            return Ok(Cow::Borrowed(text.as_bytes()));
+        }
+        let mut out = Vec::with_capacity(text.len());
+        for ch in text.chars() {
+            if !self.encoding.encode_char(ch, &mut out) {
+                return Err(format!("'{}' cannot be encoded in {}", ch, self.name()));
+            }
+        }
+        Ok(Cow::Owned(out))
+    }
+}
+
+/// Append `ch`, or escape `bytes`, its encoding, if `ch` would be mistaken
+/// for an escape marker
+fn push_decoded(text: &mut String, ch: char, bytes: &[u8]) {
+    if escaped_byte(ch).is_some() {
+        text.extend(bytes.iter().map(|&byte| escape_byte(byte)));
+    } else {
+        text.push(ch);
+    }
+}
+
+/// UTF-8, ignoring whether the last character is cut off
+fn decode_utf8(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid();
+        if count_escapes(valid) == 0 {
+            text.push_str(valid);
+        } else {
+            for (start, ch) in valid.char_indices() {
+                let end = start + ch.len_utf8();
+                push_decoded(&mut text, ch, &valid.as_bytes()[start..end]);
+            }
+        }
+        text.extend(chunk.invalid().iter().map(|&byte| escape_byte(byte)));
+    }
+    text
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let pairs = bytes.chunks_exact(2);
+    let odd_byte = pairs.remainder().first().copied();
+    let units: Vec<(u16, [u8; 2])> = pairs
+        .map(|pair| (unit([pair[0], pair[1]]), [pair[0], pair[1]]))
+        .collect();
+
+    let mut text = String::with_capacity(bytes.len() / 2);
+    let mut index = 0;
+    while index < units.len() {
+        let (first, first_bytes) = units[index];
+        let second = units.get(index + 1).map(|&(second, _)| second);
+        match char::decode_utf16([first].into_iter().chain(second)).next() {
+            Some(Ok(ch)) => {
+                let bytes: Vec<u8> = units[index..index + ch.len_utf16()]
+                    .iter()
+                    .flat_map(|&(_, bytes)| bytes)
+                    .collect();
+                push_decoded(&mut text, ch, &bytes);
+                index += ch.len_utf16();
+            }
+            _ => {
+                // Unpaired surrogate
+                text.extend(first_bytes.iter().map(|&byte| escape_byte(byte)));
+                index += 1;
+            }
+        }
+    }
+    text.extend(odd_byte.map(escape_byte));
+    text
+}
+
+/// UTF-16 without a byte order mark: mostly-ASCII text has a NUL in every
+/// other byte
+fn detect_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 4 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let zeros_at = |parity: usize| {
+        bytes
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&byte| byte == 0)
+            .count()
+    };
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd * 10 >= pairs * 4 && even * 10 < pairs {
+        Some(Encoding::Utf16Le)
+    } else if even * 10 >= pairs * 4 && odd * 10 < pairs {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Whether `bytes` is UTF-8, allowing for fewer bad bytes than valid
+/// multibyte characters
+fn looks_like_utf8(bytes: &[u8]) -> bool {
+    let mut multibyte = 0;
+    let mut invalid = 0;
+    for chunk in bytes.utf8_chunks() {
+        multibyte += chunk.valid().chars().filter(|ch| !ch.is_ascii()).count();
+        invalid += chunk.invalid().len();
+    }
+    invalid == 0 || invalid < multibyte
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> (FileEncoding, String) {
+        let encoding = FileEncoding::detect(bytes);
+        let text = encoding.decode(bytes);
+        let mut saved = encoding.header().to_vec();
+        match encoding.encode(&text) {
+            Ok(encoded) => saved.extend_from_slice(&encoded),
+            Err(e) => panic!("Failed to encode: {}", e),
+        }
+        assert_eq!(saved, bytes);
+        (encoding, text)
+    }
+
+    #[test]
+    fn test_detect_and_round_trip() {
+        let (encoding, text) = round_trip("héllo\n".as_bytes());
+        assert_eq!(encoding, FileEncoding::default());
+        assert_eq!(text, "héllo\n");
+
+        let (encoding, text) = round_trip(b"\xEF\xBB\xBFbom\n");
+        assert_eq!(encoding.name(), "UTF-8-BOM");
+        assert_eq!(text, "bom\n");
+
+        let (encoding, text) = round_trip(b"\xFF\xFEh\x00i\x00");
+        assert_eq!(encoding.name(), "UTF-16LE-BOM");
+        assert_eq!(text, "hi");
+
+        let (encoding, text) = round_trip(b"\x00h\x00i\x00\n");
+        assert_eq!(encoding.name(), "UTF-16BE");
+        assert_eq!(text, "hi\n");
+
+        let (encoding, text) = round_trip(b"caf\xE9 \x93quoted\x94\n");
+        assert_eq!(encoding.name(), "Windows-1252");
+        assert_eq!(text, "café “quoted”\n");
+
+        let (encoding, text) = round_trip(b"caf\xE9 \x81\n");
+        assert_eq!(encoding.name(), "Latin-1");
+        assert_eq!(text, "café \u{81}\n");
+    }
+
+    #[test]
+    fn test_invalid_bytes_are_escaped() {
+        // One bad byte among valid UTF-8 is escaped and written back
+        let (encoding, text) = round_trip(b"\xC3\xA9t\xC3\xA9 \xFF\n");
+        assert_eq!(encoding.encoding, Encoding::Utf8);
+        assert_eq!(count_escapes(&text), 1);
+        assert!(text.starts_with("été "));
+
+        // So is an unpaired surrogate in UTF-16
+        let (_, text) = round_trip(b"\xFF\xFEa\x00\x00\xD8b\x00");
+        assert_eq!(count_escapes(&text), 2);
+
+        // A real U+10FF41 has its bytes escaped rather than being saved as
+        // the "A" its marker would stand for
+        let (_, text) = round_trip("x\u{10FF41}y\n".as_bytes());
+        assert_eq!(count_escapes(&text), 4);
+        let (_, text) = round_trip(b"\xFF\xFEx\x00\xFF\xDB\x41\xDF");
+        assert_eq!(count_escapes(&text), 4);
+    }
+
+    #[test]
+    fn test_names_and_unencodable_text() {
+        assert_eq!(
+            FileEncoding::from_name("UTF-16LE-bom"),
+            Some(FileEncoding {
+                encoding: Encoding::Utf16Le,
+                bom: true
+            })
+        );
+        assert_eq!(FileEncoding::from_name("latin-1-bom"), None);
+        assert_eq!(FileEncoding::from_name("ebcdic"), None);
+        let latin1 = FileEncoding {
+            encoding: Encoding::Latin1,
+            bom: false,
+        };
+        assert!(latin1.encode("€").is_err());
+        assert!(latin1.encode("é").is_ok());
+    }
+}
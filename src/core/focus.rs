@@ -6,6 +6,7 @@
 
 // FocusManager is in EditorApp - full input routing TBD
 
+use crate::core::dispatcher::InputAction;
 use std::fmt;
 
 // =============================================================================
@@ -114,6 +115,8 @@ pub struct FocusState {
     pub history: Vec<String>,
     /// History navigation index
     pub history_index: Option<usize>,
+    /// What to do with the confirmed input, if not implied by `target`
+    pub action: Option<InputAction>,
 }
 
 impl FocusState {
@@ -128,6 +131,7 @@ impl FocusState {
             selected: 0,
             history: Vec::new(),
             history_index: None,
+            action: None,
         }
     }
 
@@ -143,6 +147,7 @@ impl FocusState {
             selected: 0,
             history: Vec::new(),
             history_index: None,
+            action: None,
         }
     }
 
@@ -157,9 +162,16 @@ impl FocusState {
             selected: 0,
             history: Vec::new(),
             history_index: None,
+            action: None,
         }
     }
 
+    /// Set the action that handles the confirmed input
+    pub fn with_action(mut self, action: InputAction) -> Self {
+        self.action = Some(action);
+        self
+    }
+
     /// Process a key event in the focused component
     pub fn handle_key(&mut self, event: &crate::core::input::InputEvent) -> FocusResult {
         use crate::core::input::Key;
@@ -0,0 +1,180 @@
+//! Line ending styles
+//!
+//! Buffers keep the line breaks of their file as they are. Lines broken in
+//! the editor get the buffer's style, which is the most common one in the
+//! file when it is loaded.
+
+/// A line break style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// Short name for the status line
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    /// Parse a style name, also accepting the Emacs names `unix`, `dos` and
+    /// `mac`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Some(LineEnding::Lf),
+            "crlf" | "dos" => Some(LineEnding::CrLf),
+            "cr" | "mac" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+}
+
+/// How many line breaks of each style some text has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LineEndingCounts {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+}
+
+impl LineEndingCounts {
+    /// Count the line breaks in `text`, which must not end between the `\r`
+    /// and `\n` of a CRLF
+    pub fn count(text: &str) -> Self {
+        let mut counts = Self::default();
+        let mut bytes = text.bytes().peekable();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'\n' => counts.lf += 1,
+                b'\r' if bytes.next_if_eq(&b'\n').is_some() => counts.crlf += 1,
+                b'\r' => counts.cr += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    pub fn add(&mut self, other: Self) {
+        self.lf += other.lf;
+        self.crlf += other.crlf;
+        self.cr += other.cr;
+    }
+
+    /// The most common style, preferring LF and then CRLF on ties
+    pub fn dominant(&self) -> LineEnding {
+        if self.crlf > self.lf && self.crlf >= self.cr {
+            LineEnding::CrLf
+        } else if self.cr > self.lf && self.cr > self.crlf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    /// Whether more than one style is used
+    pub fn is_mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr]
+            .iter()
+            .filter(|&&count| count > 0)
+            .count()
+            > 1
+    }
+
+    /// Summary such as "3 CRLF, 1 LF", most common first
+    pub fn describe(&self) -> String {
+        let mut styles = [
+            (self.lf, LineEnding::Lf),
+            (self.crlf, LineEnding::CrLf),
+            (self.cr, LineEnding::Cr),
+        ];
+        styles.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+        styles
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, ending)| format!("{} {}", count, ending.name()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `text` with every line break in style `ending`
+pub fn convert(text: &str, ending: LineEnding) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                result.push_str(ending.as_str());
+            }
+            '\n' => result.push_str(ending.as_str()),
+            ch => result.push(ch),
+        }
+    }
+    result
+}
+
+/// `line` without its line break
+pub fn trim_line_break(line: &str) -> &str {
+    match line.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => line.strip_suffix('\r').unwrap_or(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_and_dominant() {
+        let counts = LineEndingCounts::count("a\r\nb\r\nc\nd\re");
+        assert_eq!(
+            counts,
+            LineEndingCounts {
+                lf: 1,
+                crlf: 2,
+                cr: 1
+            }
+        );
+        assert_eq!(counts.dominant(), LineEnding::CrLf);
+        assert!(counts.is_mixed());
+        assert_eq!(counts.describe(), "2 CRLF, 1 LF, 1 CR");
+
+        let counts = LineEndingCounts::count("a\nb\n");
+        assert_eq!(counts.dominant(), LineEnding::Lf);
+        assert!(!counts.is_mixed());
+        assert_eq!(LineEndingCounts::default().dominant(), LineEnding::Lf);
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(convert("a\r\nb\nc\rd", LineEnding::Lf), "a\nb\nc\nd");
+        assert_eq!(convert("a\nb\r\n", LineEnding::CrLf), "a\r\nb\r\n");
+        assert_eq!(convert("a\r\n", LineEnding::Cr), "a\r");
+        assert_eq!(LineEnding::from_name(" DOS "), Some(LineEnding::CrLf));
+        assert_eq!(LineEnding::from_name("x"), None);
+    }
+
+    #[test]
+    fn test_trim_line_break() {
+        assert_eq!(trim_line_break("a\r\n"), "a");
+        assert_eq!(trim_line_break("a\n"), "a");
+        assert_eq!(trim_line_break("a\r"), "a");
+        assert_eq!(trim_line_break("a"), "a");
+    }
+}
@@ -3,13 +3,16 @@
 //! command dispatch, syntax parsing, and search functionality.
 
 pub mod app;
+pub mod autosave;
 pub mod buffer;
 pub mod calculator;
 pub mod command;
 pub mod commands;
 pub mod completion;
+pub mod conflict;
 pub mod diagnostics;
 pub mod dispatcher;
+pub mod encoding;
 pub mod focus;
 pub mod geometry;
 pub mod id;
@@ -18,6 +21,7 @@ pub mod input_router;
 pub mod kill_ring;
 pub mod layout;
 pub mod lexer;
+pub mod line_ending;
 pub mod menu;
 pub mod mouse;
 pub mod print;
@@ -29,4 +33,5 @@ pub mod syntax;
 pub mod terminal_host;
 pub mod undo_group;
 pub mod utf8;
+pub mod vcs;
 pub mod window;
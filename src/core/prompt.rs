@@ -17,6 +17,7 @@ pub fn handle_prompt_action(
                 let path = std::path::PathBuf::from(&input);
                 match Buffer::from_file(&path) {
                     Ok(buffer) => {
+                        let notice = crate::core::commands::file::file_notice(&buffer);
                         let buffer_id = app.alloc_buffer_id();
                         app.buffers.insert(buffer_id, buffer);
                         if let Some(window) = app.windows.get_mut(&app.active_window) {
@@ -25,7 +26,11 @@ pub fn handle_prompt_action(
                             window.cursor_y = 0;
                             window.scroll_offset = 0;
                         }
-                        app.message = Some(format!("Opened {}", input));
+                        app.message = Some(match notice {
+                            Some(notice) => format!("Opened {}: {}", input, notice),
+                            None => format!("Opened {}", input),
+                        });
+                        crate::core::commands::file::offer_recovery(app);
                     }
                     Err(e) => {
                         app.message = Some(format!("Error: {}", e));
@@ -287,6 +292,144 @@ pub fn handle_prompt_action(
                 }
             }
         }
+        InputAction::SedPreviewRegion => {
+            if !input.is_empty()
+                && let Err(e) = crate::core::commands::diff::start_sed_region_diff(app, &input)
+            {
+                app.message = Some(format!("Sed Error: {}", e));
+            }
+        }
+        InputAction::SedPreviewFiles => {
+            if !input.is_empty() {
+                app.focus_manager.push(
+                    crate::core::focus::FocusState::new(
+                        crate::core::focus::FocusTarget::Minibuffer,
+                        "Sed preview expression: ",
+                    )
+                    .with_action(InputAction::SedPreviewFilesExpression(input)),
+                );
+            }
+        }
+        InputAction::SedPreviewFilesExpression(files) => {
+            if !input.is_empty()
+                && let Err(e) = crate::core::commands::review::start_sed_review(app, &files, &input)
+            {
+                app.message = Some(format!("Sed Error: {}", e));
+            }
+        }
+        InputAction::SedDebug => {
+            if !input.is_empty() {
+                let mut config = crate::sed::SedConfig::new();
+                let started = config
+                    .add_script(&input)
+                    .map_err(|e| e.into())
+                    .and_then(|()| crate::core::commands::sed_debug::start_sed_debug(app, config));
+                if let Err(e) = started {
+                    app.message = Some(format!("Sed Error: {}", e));
+                }
+            }
+        }
+        InputAction::DiffBuffers => {
+            if !input.is_empty() {
+                app.focus_manager.push(
+                    crate::core::focus::FocusState::new(
+                        crate::core::focus::FocusTarget::Minibuffer,
+                        "Diff against buffer or file: ",
+                    )
+                    .with_action(InputAction::DiffBuffersWith(input)),
+                );
+            }
+        }
+        InputAction::DiffBuffersWith(left) => {
+            if !input.is_empty()
+                && let Err(e) = crate::core::commands::diff::start_buffer_diff(app, &left, &input)
+            {
+                app.message = Some(format!("Diff Error: {}", e));
+            }
+        }
+        InputAction::ApplyPatch { reverse } => {
+            if let Err(e) = crate::core::commands::patch::apply_patch(app, &input, reverse) {
+                app.message = Some(format!("Patch Error: {}", e));
+            }
+        }
+        InputAction::ExportPatch => {
+            if !input.is_empty()
+                && let Err(e) = crate::core::commands::patch::export_diff_patch(app, &input)
+            {
+                app.message = Some(format!("Patch Error: {}", e));
+            }
+        }
+        InputAction::SetLineEndings => {
+            if !input.is_empty() {
+                app.message = Some(
+                    match crate::core::commands::file::set_line_endings(app, &input) {
+                        Ok(message) => message,
+                        Err(e) => format!("Error: {}", e),
+                    },
+                );
+            }
+        }
+        InputAction::RevisitWithEncoding => {
+            if !input.is_empty() {
+                app.message = Some(
+                    match crate::core::commands::file::revisit_with_encoding(app, &input) {
+                        Ok(message) => message,
+                        Err(e) => format!("Error: {}", e),
+                    },
+                );
+            }
+        }
+        InputAction::SetBufferEncoding => {
+            if !input.is_empty() {
+                app.message = Some(
+                    match crate::core::commands::file::set_buffer_encoding(app, &input) {
+                        Ok(message) => message,
+                        Err(e) => format!("Error: {}", e),
+                    },
+                );
+            }
+        }
+        InputAction::RecoverFile => match input.trim() {
+            "d" | "diff" => {
+                let diffed = match crate::core::commands::file::recovered_text(app) {
+                    Some(text) => crate::core::commands::diff::start_recovery_diff(app, text),
+                    None => Err("No recovery data".into()),
+                };
+                if let Err(e) = diffed {
+                    app.message = Some(format!("Diff Error: {}", e));
+                }
+            }
+            choice => {
+                app.message = Some(
+                    match crate::core::commands::file::resolve_recovery(app, choice) {
+                        Ok(message) => message,
+                        Err(e) => format!("Error: {}", e),
+                    },
+                );
+            }
+        },
+        InputAction::FileChanged => match input.trim() {
+            "d" | "diff" => {
+                if let Err(e) = crate::core::commands::diff::start_file_diff(app) {
+                    app.message = Some(format!("Diff Error: {}", e));
+                }
+            }
+            choice => {
+                let result = match (choice, app.active_buffer_mut()) {
+                    ("r" | "reload", Some(buffer)) => {
+                        buffer.reload().map(|()| "Reloaded from disk")
+                    }
+                    ("o" | "overwrite", Some(buffer)) => {
+                        buffer.save().map(|()| "Saved over the file on disk")
+                    }
+                    _ => Ok("Cancelled"),
+                };
+                app.message = Some(match result {
+                    Ok(message) => message.to_string(),
+                    Err(e) => format!("Error: {}", e),
+                });
+            }
+        },
         InputAction::ExecuteNamedCommand => {
             if !input.is_empty() {
                 let result = dispatch(app, Some(&input), None, 1);
@@ -298,11 +441,14 @@ pub fn handle_prompt_action(
                     DispatchResult::Info(msg) => {
                         app.message = Some(msg);
                     }
-                    DispatchResult::NeedsInput { prompt, action: _ } => {
-                        app.focus_manager.push(crate::core::focus::FocusState::new(
-                            crate::core::focus::FocusTarget::Minibuffer,
-                            &prompt,
-                        ));
+                    DispatchResult::NeedsInput { prompt, action } => {
+                        app.focus_manager.push(
+                            crate::core::focus::FocusState::new(
+                                crate::core::focus::FocusTarget::Minibuffer,
+                                &prompt,
+                            )
+                            .with_action(action),
+                        );
                     }
                     DispatchResult::Exit => {
                         return Ok(true);
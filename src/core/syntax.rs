@@ -37,6 +37,10 @@ pub enum TokenType {
     Operator,
     /// Punctuation (braces, parens, semicolons, etc.)
     Punctuation,
+    /// Added lines of a patch
+    Inserted,
+    /// Removed lines of a patch
+    Deleted,
     /// Normal text / fallback
     Normal,
 }
@@ -190,6 +194,9 @@ impl SyntaxHighlighter {
         line: &str,
         state: LexerState,
     ) -> (Vec<HighlightSpan>, LexerState) {
+        if matches!(ext, "patch" | "diff") {
+            return (highlight_patch_line(line), state);
+        }
         let config = self.registry.get_config(ext);
         let mut lexer = Lexer::with_state(line, config, state);
         let mut spans = Vec::new();
@@ -226,6 +233,26 @@ impl SyntaxHighlighter {
     }
 }
 
+/// Highlight a line of a unified diff as a whole, by its leading marker
+fn highlight_patch_line(line: &str) -> Vec<HighlightSpan> {
+    let file_header = ["diff ", "index ", "--- ", "+++ "];
+    let token_type = if file_header.iter().any(|prefix| line.starts_with(prefix)) {
+        TokenType::Keyword
+    } else if line.starts_with("@@") {
+        TokenType::Type
+    } else if line.starts_with('+') {
+        TokenType::Inserted
+    } else if line.starts_with('-') {
+        TokenType::Deleted
+    } else if line.starts_with('\\') {
+        TokenType::Comment
+    } else {
+        return Vec::new();
+    };
+    let end = line.trim_end_matches(['\n', '\r']).len();
+    vec![HighlightSpan::new(0, end, token_type)]
+}
+
 impl Default for SyntaxHighlighter {
     fn default() -> Self {
         Self::new()
@@ -321,4 +348,19 @@ mod tests {
         assert_eq!(TokenType::from(TokenKind::Comment), TokenType::Comment);
         assert_eq!(TokenType::from(TokenKind::Whitespace), TokenType::Normal);
     }
+
+    #[test]
+    fn test_patch_highlighting() {
+        let highlighter = SyntaxHighlighter::new();
+        let kind = |line: &str| {
+            let (spans, _) =
+                highlighter.highlight_line_with_state("patch", line, LexerState::Normal);
+            spans.first().map(|span| span.token_type)
+        };
+        assert_eq!(kind("+++ b/src/main.rs"), Some(TokenType::Keyword));
+        assert_eq!(kind("@@ -1,3 +1,4 @@"), Some(TokenType::Type));
+        assert_eq!(kind("+let x = 1;"), Some(TokenType::Inserted));
+        assert_eq!(kind("-let x = 2;"), Some(TokenType::Deleted));
+        assert_eq!(kind(" context"), None);
+    }
 }
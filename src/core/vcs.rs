@@ -0,0 +1,414 @@
+//! Git change markers
+//!
+//! Compares a file buffer with the version of its file in `HEAD`, fetched by
+//! running the local `git` binary, so the gutter can mark changed lines and
+//! single hunks can be reverted or staged.
+
+use crate::core::buffer::{Buffer, BufferKind};
+use crate::core::encoding::FileEncoding;
+use crate::sed::diff::{DiffView, Hunk};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Context lines around a staged hunk, as in `git diff`
+const PATCH_CONTEXT: usize = 3;
+
+/// How a buffer line differs from `HEAD`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines were removed just above this one
+    Deleted,
+}
+
+/// The `HEAD` version of a buffer's file and how the buffer differs from it
+#[derive(Debug, Clone, Default)]
+pub struct VcsState {
+    /// File the last lookup was made for
+    checked: Option<PathBuf>,
+    /// Text in `HEAD`; `None` outside a work tree or for untracked files
+    head: Option<String>,
+    /// Buffer version `hunks` was computed for
+    version: Option<u64>,
+    hunks: Vec<Hunk>,
+}
+
+impl VcsState {
+    /// Forget the `HEAD` text so the next refresh asks git again
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Whether the file is in `HEAD`
+    pub fn is_tracked(&self) -> bool {
+        self.head.is_some()
+    }
+
+    /// Columns the change markers take in the gutter
+    pub fn gutter_width(&self) -> usize {
+        usize::from(self.is_tracked())
+    }
+
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// The marker for buffer line `line`
+    pub fn line_change(&self, line: usize) -> Option<LineChange> {
+        let hunk = &self.hunks[self.hunk_at(line)?];
+        if hunk.new_lines.is_empty() {
+            Some(LineChange::Deleted)
+        } else if line - hunk.new_start < hunk.old_lines.len() {
+            Some(LineChange::Modified)
+        } else {
+            Some(LineChange::Added)
+        }
+    }
+
+    /// Index of the hunk covering buffer line `line`; a deletion covers the
+    /// line after it
+    pub fn hunk_at(&self, line: usize) -> Option<usize> {
+        self.hunks
+            .iter()
+            .position(|hunk| buffer_lines(hunk).contains(&line))
+    }
+}
+
+/// Look up the `HEAD` version of the buffer's file unless that was done for
+/// this file already, and re-diff it if the buffer changed since last time
+pub fn refresh(buffer: &mut Buffer) {
+    if buffer.buffer_kind() != BufferKind::Normal {
+        return;
+    }
+    let Some(path) = buffer.filename.clone() else {
+        return;
+    };
+    if buffer.vcs.checked.as_ref() != Some(&path) {
+        buffer.vcs = VcsState {
+            head: head_text(&path, &buffer.encoding),
+            checked: Some(path),
+            ..VcsState::default()
+        };
+    }
+    let Some(head) = buffer.vcs.head.as_ref() else {
+        return;
+    };
+    if buffer.vcs.version != Some(buffer.version) {
+        buffer.vcs.hunks = DiffView::new(head.clone(), buffer.to_string()).change_hunks();
+        buffer.vcs.version = Some(buffer.version);
+    }
+}
+
+/// Directory to run git in for `path`
+fn file_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Run git in `dir`, feeding it `input`, and return its output or its error
+/// message
+fn git(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input)
+            .map_err(|e| format!("Failed to write to git: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// The file at `path` as committed in `HEAD`, decoded like the buffer
+fn head_text(path: &Path, encoding: &FileEncoding) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    let bytes = git(file_dir(path), &["show", &format!("HEAD:./{}", name)], None).ok()?;
+    Some(encoding.decode(&bytes))
+}
+
+/// The file at `path` as it is in the git index, decoded like the buffer
+fn index_text(path: &Path, encoding: &FileEncoding) -> Result<String, String> {
+    let name = path
+        .file_name()
+        .ok_or("The buffer has no file")?
+        .to_string_lossy();
+    let bytes = git(file_dir(path), &["show", &format!(":./{}", name)], None)?;
+    Ok(encoding.decode(&bytes))
+}
+
+/// Buffer lines a hunk covers; a deletion covers the line after it
+fn buffer_lines(hunk: &Hunk) -> std::ops::Range<usize> {
+    hunk.new_start..hunk.new_start + hunk.new_lines.len().max(1)
+}
+
+/// The change between `index` and `text` within the buffer lines of `hunk`,
+/// as one hunk against `index`
+fn index_hunk(index: &str, text: &str, hunk: &Hunk) -> Option<Hunk> {
+    let lines = buffer_lines(hunk);
+    let overlapping: Vec<Hunk> = DiffView::new(index.to_string(), text.to_string())
+        .change_hunks()
+        .into_iter()
+        .filter(|other| {
+            let other = buffer_lines(other);
+            other.start < lines.end && lines.start < other.end
+        })
+        .collect();
+    let (first, last) = (overlapping.first()?, overlapping.last()?);
+    let index_lines: Vec<&str> = index.split_inclusive('\n').collect();
+    let text_lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let old_end = last.start_line + last.old_lines.len();
+    let new_end = last.new_start + last.new_lines.len();
+    Some(Hunk {
+        start_line: first.start_line,
+        end_line: old_end.saturating_sub(1).max(first.start_line),
+        new_start: first.new_start,
+        old_lines: index_lines[first.start_line..old_end]
+            .iter()
+            .map(|line| line.to_string())
+            .collect(),
+        new_lines: text_lines[first.new_start..new_end]
+            .iter()
+            .map(|line| line.to_string())
+            .collect(),
+        inline: Default::default(),
+    })
+}
+
+/// Add hunk `index` of the buffer to the git index
+///
+/// The hunk is taken from the buffer's diff against `HEAD`, but the patch is
+/// built against the index so that hunks near ones staged before still apply.
+pub fn stage_hunk(buffer: &Buffer, index: usize) -> Result<(), String> {
+    let path = buffer.filename.as_ref().ok_or("The buffer has no file")?;
+    let hunk = buffer.vcs.hunks.get(index).ok_or("No change here")?;
+    let name = path
+        .file_name()
+        .ok_or("The buffer has no file")?
+        .to_string_lossy();
+
+    let location = git(
+        file_dir(path),
+        &["rev-parse", "--show-toplevel", "--show-prefix"],
+        None,
+    )?;
+    let location = String::from_utf8_lossy(&location);
+    let mut lines = location.lines();
+    let top = lines.next().ok_or("Not in a git work tree")?;
+    let prefix = lines.next().unwrap_or_default();
+
+    let staged = index_text(path, &buffer.encoding)?;
+    let hunk =
+        index_hunk(&staged, &buffer.to_string(), hunk).ok_or("The hunk is already staged")?;
+    let patch = hunk_patch(&format!("{}{}", prefix, name), &staged, &hunk);
+    let patch = buffer.encoding.encode(&patch)?;
+    git(Path::new(top), &["apply", "--cached", "-"], Some(&patch)).map(|_| ())
+}
+
+/// A patch making the change of `hunk` to `base`, with context for
+/// `git apply`
+fn hunk_patch(target: &str, base: &str, hunk: &Hunk) -> String {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let old_end = (hunk.start_line + hunk.old_lines.len()).min(base_lines.len());
+    let before_start = hunk.start_line.saturating_sub(PATCH_CONTEXT);
+    let before = &base_lines[before_start..hunk.start_line.min(old_end)];
+    let after = &base_lines[old_end..(old_end + PATCH_CONTEXT).min(base_lines.len())];
+
+    // Both sides start at the same line since this is the only hunk
+    let range = |count: usize| match count {
+        0 => format!("{},0", before_start),
+        count => format!("{},{}", before_start + 1, count),
+    };
+    let context = before.len() + after.len();
+    let mut patch = format!(
+        "--- a/{target}\n+++ b/{target}\n@@ -{} +{} @@\n",
+        range(context + hunk.old_lines.len()),
+        range(context + hunk.new_lines.len()),
+    );
+
+    let removed = hunk.old_lines.iter().map(|line| ('-', line.as_str()));
+    let added = hunk.new_lines.iter().map(|line| ('+', line.as_str()));
+    let body = before
+        .iter()
+        .map(|line| (' ', *line))
+        .chain(removed)
+        .chain(added)
+        .chain(after.iter().map(|line| (' ', *line)));
+    for (marker, line) in body {
+        patch.push(marker);
+        patch.push_str(line);
+        if !line.ends_with('\n') {
+            patch.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sed::patch;
+
+    fn hunks(head: &str, text: &str) -> VcsState {
+        VcsState {
+            head: Some(head.to_string()),
+            hunks: DiffView::new(head.to_string(), text.to_string()).change_hunks(),
+            ..VcsState::default()
+        }
+    }
+
+    #[test]
+    fn test_line_changes() {
+        let state = hunks("a\nb\nc\nd\n", "a\nB\nB2\nc\n");
+        assert_eq!(state.line_change(0), None);
+        assert_eq!(state.line_change(1), Some(LineChange::Modified));
+        assert_eq!(state.line_change(2), Some(LineChange::Added));
+        assert_eq!(state.line_change(3), None);
+        // "d" was removed from the end
+        assert_eq!(state.line_change(4), Some(LineChange::Deleted));
+        assert_eq!(state.hunk_at(2), Some(0));
+        assert_eq!(state.gutter_width(), 1);
+        assert_eq!(VcsState::default().gutter_width(), 0);
+    }
+
+    #[test]
+    fn test_hunk_patch_applies_to_head() {
+        let head: String = (1..=10).map(|n| format!("{}\n", n)).collect();
+        let text = head.replace("5\n", "five\n").replace("9\n", "");
+        let state = hunks(&head, &text);
+        assert_eq!(state.hunks().len(), 2);
+
+        // Each hunk on its own turns HEAD into HEAD plus that change
+        let patch_text = hunk_patch("dir/f.txt", &head, &state.hunks()[1]);
+        let files = match patch::parse(&patch_text) {
+            Ok(files) => files,
+            Err(e) => panic!("Failed to parse: {}", e),
+        };
+        assert_eq!(files[0].target_path(), Some("dir/f.txt"));
+        let (patched, _) = patch::apply(&head, &files[0].hunks, false, 0);
+        assert_eq!(patched, head.replace("9\n", ""));
+    }
+
+    #[test]
+    fn test_stage_hunk() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let run = |args: &[&str]| git(dir.path(), args, None);
+        if run(&["init", "-q"]).is_err() {
+            return; // No git available
+        }
+        let path = dir.path().join("f.txt");
+        let head: String = (1..=10).map(|n| format!("{}\n", n)).collect();
+        if let Err(e) = std::fs::write(&path, &head) {
+            panic!("Failed to write: {}", e);
+        }
+        let committed = run(&["add", "f.txt"]).and_then(|_| {
+            run(&[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "-m",
+                "init",
+            ])
+        });
+        if let Err(e) = committed {
+            panic!("Failed to commit: {}", e);
+        }
+
+        let mut buffer = match Buffer::from_file(&path) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Failed to load: {}", e),
+        };
+        buffer.insert(0, "0\n");
+        let end = buffer.len();
+        buffer.insert(end, "11\n");
+        refresh(&mut buffer);
+        assert!(buffer.vcs.is_tracked());
+        assert_eq!(buffer.vcs.hunks().len(), 2);
+        assert_eq!(buffer.vcs.line_change(0), Some(LineChange::Added));
+
+        if let Err(e) = stage_hunk(&buffer, 1) {
+            panic!("Failed to stage: {}", e);
+        }
+        assert_eq!(run(&["show", ":f.txt"]), Ok((head + "11\n").into_bytes()));
+    }
+
+    #[test]
+    fn test_stage_adjacent_hunks() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let run = |args: &[&str]| git(dir.path(), args, None);
+        if run(&["init", "-q"]).is_err() {
+            return; // No git available
+        }
+        let path = dir.path().join("f.txt");
+        let head: String = (1..=10).map(|n| format!("{}\n", n)).collect();
+        if let Err(e) = std::fs::write(&path, &head) {
+            panic!("Failed to write: {}", e);
+        }
+        let committed = run(&["add", "f.txt"]).and_then(|_| {
+            run(&[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "-m",
+                "init",
+            ])
+        });
+        if let Err(e) = committed {
+            panic!("Failed to commit: {}", e);
+        }
+
+        let mut buffer = match Buffer::from_file(&path) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Failed to load: {}", e),
+        };
+        let text = head.replace("5\n", "five\n").replace("8\n", "eight\n");
+        let end = buffer.len();
+        buffer.replace(0, end, &text, "edit");
+        refresh(&mut buffer);
+        assert_eq!(buffer.vcs.hunks().len(), 2);
+
+        // The second hunk's context includes the first one, already staged
+        for index in 0..2 {
+            if let Err(e) = stage_hunk(&buffer, index) {
+                panic!("Failed to stage hunk {}: {}", index, e);
+            }
+        }
+        assert_eq!(run(&["show", ":f.txt"]), Ok(text.into_bytes()));
+        assert_eq!(
+            stage_hunk(&buffer, 0),
+            Err("The hunk is already staged".to_string())
+        );
+    }
+}
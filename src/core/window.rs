@@ -387,9 +387,14 @@ impl Window {
             None => 0,
         };
 
-        // Use stack buffer to avoid heap allocation
+        // Use stack buffer to avoid heap allocation; newlines take the
+        // buffer's line ending
         let mut char_buf = [0u8; 4];
-        let char_str = c.encode_utf8(&mut char_buf);
+        let char_str: &str = if c == '\n' {
+            buffer.line_ending.as_str()
+        } else {
+            c.encode_utf8(&mut char_buf)
+        };
 
         // Use Buffer's insert method which handles undo recording internally
         buffer.insert(absolute_pos, char_str);
@@ -426,7 +431,7 @@ impl Window {
                         Some(pos) => pos,
                         None => 0,
                     };
-                    buffer.delete(absolute_pos, 1);
+                    buffer.delete(absolute_pos, buffer.line_break_len(self.cursor_y));
                 }
                 // Else: at beginning of buffer, nothing to delete
             } else {
@@ -463,11 +468,11 @@ impl Window {
                     buffer.delete(absolute_pos, char_len);
                 } else if self.cursor_y < buffer.line_count().saturating_sub(1) {
                     // At end of line, delete the newline
-                    buffer.delete(absolute_pos, 1);
+                    buffer.delete(absolute_pos, buffer.line_break_len(self.cursor_y));
                 }
             } else if self.cursor_y < buffer.line_count().saturating_sub(1) {
                 // Empty line, delete newline
-                buffer.delete(absolute_pos, 1);
+                buffer.delete(absolute_pos, buffer.line_break_len(self.cursor_y));
             }
             // Cursor position doesn't change for forward delete
         }
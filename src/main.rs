@@ -25,6 +25,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Apply CLI overrides
     cli.apply_to_config(&mut config);
 
+    // The sed debugger runs the script inside the terminal editor
+    if cli.sed_debug {
+        let (sed_config, files) = run::load_sed_script(&cli)?;
+        let display_mode = terminal::capabilities::DisplayMode::detect();
+        run::run_terminal_mode(
+            files,
+            &config,
+            display_mode,
+            Some(run::StartupView::SedDebug(sed_config)),
+        )?;
+        return Ok(());
+    }
+
     // Determine mode
     let mode = if cli.sed || !cli.expression.is_empty() || cli.script_file.is_some() {
         run::EditorMode::Sed
@@ -52,13 +65,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Run in appropriate mode
     match mode {
         run::EditorMode::Sed => {
-            run::run_sed_mode(&cli)?;
+            let exit_code = run::run_sed_mode(&cli)?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
         }
         run::EditorMode::AsciiTerminal
         | run::EditorMode::AnsiTerminal
         | run::EditorMode::Utf8Terminal => {
+            // Outside sed mode, `--diff a b` compares two files side by side
+            let startup = if cli.diff {
+                if cli.files.len() != 2 {
+                    return Err("--diff needs exactly two files, or a sed script".into());
+                }
+                Some(run::StartupView::Diff)
+            } else {
+                None
+            };
             let display_mode = terminal::capabilities::DisplayMode::detect();
-            run::run_terminal_mode(&cli.files, &config, display_mode)?;
+            run::run_terminal_mode(&cli.files, &config, display_mode, startup)?;
         }
         run::EditorMode::Gui => {
             run::run_gui_mode(&cli.files, &config)?;
@@ -214,13 +214,28 @@ impl ApplicationHandler for GuiApp {
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if let Err(e) = self.editor.autosave.tick(&self.editor.buffers) {
+            self.editor.message = Some(format!("Autosave failed: {}", e));
+            self.dirty = true;
+        }
+        // Wake up for the next autosave even without input
+        event_loop.set_control_flow(match self.editor.autosave.deadline() {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        });
+
         if self.dirty {
             if let Some(window) = &self.window {
                 window.request_redraw();
             }
         }
     }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        // Recovery files are only left behind by a crash
+        self.editor.autosave.discard();
+    }
 }
 
 impl Drop for GuiApp {
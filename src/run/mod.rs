@@ -15,8 +15,8 @@ use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 
 // Re-export mode runners
-pub use sed::run_sed_mode;
-pub use tui::run_terminal_mode;
+pub use sed::{load_sed_script, run_sed_mode};
+pub use tui::{StartupView, run_terminal_mode};
 
 #[cfg(feature = "gui")]
 pub use gui::run_gui_mode;
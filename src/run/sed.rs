@@ -1,39 +1,55 @@
 //! Sed (stream editing) mode implementation.
 
 use crate::cli::Cli;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+use crate::sed::SedConfig;
+use crate::sed::diff::DiffView;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
 use super::validate_file_path;
 
-/// Run in sed (stream editing) mode.
-pub fn run_sed_mode(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
-    use crate::sed::SedConfig;
+/// Input stream type shared by stdin and files
+type Input = Box<dyn BufRead>;
+
+/// Exit status when an input file cannot be read, as in GNU sed
+const EXIT_BAD_INPUT: i32 = 2;
+
+/// Build the sed configuration from the command line.
+///
+/// Returns it with the input files, which follow the script when it is given
+/// as the first positional argument.
+pub fn load_sed_script(cli: &Cli) -> Result<(SedConfig, &[PathBuf]), Box<dyn std::error::Error>> {
+    use crate::sed::fields::FieldSeparator;
+    use crate::sed::posix::RegexSyntax;
 
     let mut sed_config = SedConfig::new();
     sed_config.quiet = cli.quiet;
-
-    for script in &cli.expression {
-        sed_config
-            .add_script(script)
-            .map_err(|e| format!("Error parsing script: {}", e))?;
+    sed_config.null_data = cli.null_data;
+    sed_config.posix = cli.posix;
+    if cli.extended_regex {
+        sed_config.syntax = RegexSyntax::Extended;
+    }
+    if let Some(separator) = &cli.field_separator {
+        sed_config.field_separator = Some(FieldSeparator::parse(separator)?);
     }
 
+    // All -e expressions and the -f file form one script, joined by newlines,
+    // so blocks and labels may span them
+    let mut script_parts: Vec<String> = cli.expression.clone();
     if let Some(script_path) = &cli.script_file {
         let validated_path = validate_file_path(script_path)?;
-        let file = File::open(&validated_path)?;
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            let line = line?;
-            if !line.trim().is_empty() && !line.starts_with('#') {
-                sed_config
-                    .add_script(&line)
-                    .map_err(|e| format!("Error parsing script from file: {}", e))?;
-            }
-        }
+        let content = std::fs::read_to_string(&validated_path)?;
+        script_parts.push(content.trim_end_matches('\n').to_string());
+    }
+
+    if !script_parts.is_empty() {
+        sed_config
+            .add_script(&script_parts.join("\n"))
+            .map_err(|e| format!("Error parsing script: {}", e))?;
     }
 
-    let input_files = if sed_config.commands.is_empty() {
+    let input_files = if script_parts.is_empty() {
         if let Some(first_arg) = cli.files.first() {
             let script = first_arg.to_string_lossy();
             sed_config
@@ -48,42 +64,383 @@ pub fn run_sed_mode(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
     } else {
         &cli.files[..]
     };
+    Ok((sed_config, input_files))
+}
+
+/// Run in sed (stream editing) mode.
+///
+/// Returns the exit status: the code given to `q` or `Q`, otherwise 2 if an
+/// input file could not be read.
+pub fn run_sed_mode(cli: &Cli) -> Result<i32, Box<dyn std::error::Error>> {
+    let (sed_config, input_files) = load_sed_script(cli)?;
+    let mut run = sed_config.start::<Input>()?;
+    let mut status = 0;
+    let mut exit_code = None;
 
     if input_files.is_empty() {
-        let stdin = io::stdin();
-        let stdout = io::stdout();
-        let stdin_lock = stdin.lock();
-        let mut stdout_lock = stdout.lock();
-        sed_config.execute(stdin_lock, &mut stdout_lock)?;
-    } else {
+        if cli.diff || cli.interactive {
+            return Err("--diff and --interactive need input files".into());
+        }
+        let stdin: Input = Box::new(io::stdin().lock());
+        exit_code = run.process(vec![stdin], io::stdout().lock())?;
+    } else if cli.diff || cli.interactive {
+        // Answers to the interactive prompts come from stdin, which is free
+        // since the input comes from files
+        let mut answers = io::stdin().lock().lines();
+        for file_path in input_files {
+            let mut original = Vec::new();
+            match open_input(file_path) {
+                Some(mut input) => input.read_to_end(&mut original)?,
+                None => {
+                    status = EXIT_BAD_INPUT;
+                    continue;
+                }
+            };
+            let mut modified = Vec::new();
+            let input: Input = Box::new(io::Cursor::new(original.clone()));
+            exit_code = run.process(vec![input], &mut modified)?;
+            let keep_going = preview_changes(file_path, original, modified, cli, &mut answers)?;
+            if exit_code.is_some() || !keep_going {
+                break;
+            }
+        }
+    } else if cli.in_place || cli.separate {
         for file_path in input_files {
-            let validated_path = validate_file_path(file_path)?;
-            let file = File::open(&validated_path)?;
-            let reader = BufReader::new(file);
-
-            if cli.in_place {
-                use std::io::BufWriter;
-                use tempfile::NamedTempFile;
-
-                let parent = validated_path
-                    .parent()
-                    .ok_or_else(|| -> Box<dyn std::error::Error> { "Invalid file path".into() })?;
-
-                let temp_file = NamedTempFile::new_in(parent)?;
-                {
-                    let mut writer = BufWriter::new(&temp_file);
-                    sed_config.execute(reader, &mut writer)?;
-                    writer.flush()?;
+            let input = match open_input(file_path) {
+                Some(input) => input,
+                None => {
+                    status = EXIT_BAD_INPUT;
+                    continue;
                 }
-                temp_file.as_file().sync_all()?;
-                temp_file.persist(validated_path)?;
+            };
+            exit_code = if cli.in_place {
+                replace_file(file_path, cli, |writer| run.process(vec![input], writer))?
             } else {
-                let stdout = io::stdout();
-                let mut stdout_lock = stdout.lock();
-                sed_config.execute(reader, &mut stdout_lock)?;
+                run.process(vec![input], io::stdout().lock())?
+            };
+            // `q` and `Q` stop all processing, including later files
+            if exit_code.is_some() {
+                break;
             }
         }
+    } else {
+        // By default all files form one continuous stream
+        let mut inputs = Vec::new();
+        for file_path in input_files {
+            match open_input(file_path) {
+                Some(input) => inputs.push(input),
+                None => status = EXIT_BAD_INPUT,
+            }
+        }
+        exit_code = run.process(inputs, io::stdout().lock())?;
+    }
+
+    run.finish()?;
+    Ok(exit_code.filter(|&code| code != 0).unwrap_or(status))
+}
+
+/// Open an input file, reporting failures on stderr like GNU sed
+fn open_input(path: &Path) -> Option<Input> {
+    // Check existence first so a missing file reports the I/O error, and
+    // validate before opening so FIFOs and devices are never opened
+    let opened = fs::metadata(path)
+        .map_err(Into::into)
+        .and_then(|_| validate_file_path(path))
+        .and_then(|validated| Ok(File::open(validated)?));
+    match opened {
+        Ok(file) => Some(Box::new(BufReader::new(file))),
+        Err(e) => {
+            eprintln!("erax: can't read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Show the changes the script made to one file as a unified diff.
+///
+/// With `--interactive`, ask about each hunk instead and write the accepted
+/// ones to the file. Returns `false` if the user chose to quit.
+fn preview_changes<A>(
+    path: &Path,
+    original: Vec<u8>,
+    modified: Vec<u8>,
+    cli: &Cli,
+    answers: &mut A,
+) -> Result<bool, Box<dyn std::error::Error>>
+where
+    A: Iterator<Item = io::Result<String>>,
+{
+    let name = path.display().to_string();
+    let mut stdout = io::stdout().lock();
+
+    if !cli.interactive {
+        let view = DiffView::new(
+            String::from_utf8_lossy(&original).into_owned(),
+            String::from_utf8_lossy(&modified).into_owned(),
+        );
+        stdout.write_all(view.unified_diff(&name, &name).as_bytes())?;
+        return Ok(true);
+    }
+
+    // Applying hunks rebuilds the file from text, so it must be valid UTF-8
+    let (Ok(original), Ok(modified)) = (String::from_utf8(original), String::from_utf8(modified))
+    else {
+        return Err(format!("{}: --interactive needs UTF-8 text", name).into());
+    };
+    let view = DiffView::new(original, modified);
+    let hunks = view.unified_hunks();
+    if hunks.is_empty() {
+        return Ok(true);
+    }
+
+    writeln!(stdout, "--- {}\n+++ {}", name, name)?;
+    let mut selected = Vec::new();
+    let mut keep_going = true;
+    'hunks: for (index, hunk) in hunks.iter().enumerate() {
+        stdout.write_all(hunk.as_bytes())?;
+        loop {
+            write!(
+                stdout,
+                "Apply this hunk ({}/{}) [y,n,q]? ",
+                index + 1,
+                hunks.len()
+            )?;
+            stdout.flush()?;
+            let answer = match answers.next() {
+                Some(line) => line?,
+                None => "q".to_string(),
+            };
+            match answer.trim() {
+                "y" | "Y" => selected.push(true),
+                "n" | "N" => selected.push(false),
+                "q" | "Q" => {
+                    keep_going = false;
+                    break 'hunks;
+                }
+                _ => continue,
+            }
+            break;
+        }
+    }
+
+    if selected.contains(&true) {
+        let content = view.apply_hunks(&selected);
+        replace_file(path, cli, |writer| writer.write_all(content.as_bytes()))?;
+    }
+    Ok(keep_going)
+}
+
+/// Replace a file with the output of `write`.
+///
+/// The output goes to a temporary file next to the file being edited, which
+/// then replaces it with the original's permissions and (where allowed)
+/// ownership. A symbolic link is replaced by the edited file unless
+/// `--follow-symlinks` is given, in which case its target is edited.
+fn replace_file<T>(
+    path: &Path,
+    cli: &Cli,
+    write: impl FnOnce(&mut BufWriter<&File>) -> io::Result<T>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let target = if cli.follow_symlinks {
+        fs::canonicalize(path)?
+    } else {
+        path.to_path_buf()
+    };
+    let metadata = fs::metadata(&target)?;
+    let parent = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let temp_file = tempfile::NamedTempFile::new_in(parent)?;
+    let result = {
+        let mut writer = BufWriter::new(temp_file.as_file());
+        let result = write(&mut writer)?;
+        writer.flush()?;
+        result
+    };
+    temp_file.as_file().sync_all()?;
+
+    fs::set_permissions(temp_file.path(), metadata.permissions())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Changing the owner needs privileges; like GNU sed, carry on without
+        let _ =
+            std::os::unix::fs::chown(temp_file.path(), Some(metadata.uid()), Some(metadata.gid()));
+    }
+
+    if let Some(suffix) = cli.backup_suffix.as_deref().filter(|s| !s.is_empty()) {
+        fs::rename(&target, backup_path(&target, suffix))?;
+    }
+    temp_file.persist(&target)?;
+    Ok(result)
+}
+
+/// Backup file name for `path`: `suffix` is appended to the file name, or,
+/// if it contains `*`, each `*` is replaced with the file name (so a suffix
+/// like `bak/*` puts the backup in another directory)
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let backup_name = if suffix.contains('*') {
+        suffix.replace('*', &name)
+    } else {
+        format!("{}{}", name, suffix)
+    };
+    path.with_file_name(backup_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_path() {
+        let path = Path::new("dir/file.txt");
+        assert_eq!(backup_path(path, ".bak"), PathBuf::from("dir/file.txt.bak"));
+        assert_eq!(
+            backup_path(path, "old_*"),
+            PathBuf::from("dir/old_file.txt")
+        );
+        assert_eq!(
+            backup_path(path, "bak/*.orig"),
+            PathBuf::from("dir/bak/file.txt.orig")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_in_place_keeps_permissions_and_makes_backup() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("script.sh");
+        let written = fs::write(&path, "echo old\n")
+            .and_then(|_| fs::set_permissions(&path, fs::Permissions::from_mode(0o750)));
+        if let Err(e) = written {
+            panic!("Failed to set up file: {}", e);
+        }
+
+        let cli = Cli {
+            files: vec![path.clone()],
+            expression: vec!["s/old/new/".to_string()],
+            in_place: true,
+            backup_suffix: Some(".bak".to_string()),
+            ..Default::default()
+        };
+        match run_sed_mode(&cli) {
+            Ok(status) => assert_eq!(status, 0),
+            Err(e) => panic!("sed failed: {}", e),
+        }
+
+        assert_eq!(
+            fs::read_to_string(&path).ok().as_deref(),
+            Some("echo new\n")
+        );
+        let backup = dir.path().join("script.sh.bak");
+        assert_eq!(
+            fs::read_to_string(&backup).ok().as_deref(),
+            Some("echo old\n")
+        );
+        match fs::metadata(&path) {
+            Ok(metadata) => assert_eq!(metadata.permissions().mode() & 0o777, 0o750),
+            Err(e) => panic!("Failed to stat file: {}", e),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_in_place_symlink_handling() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let target = dir.path().join("target.txt");
+        let link = dir.path().join("link.txt");
+        let created =
+            fs::write(&target, "a\n").and_then(|_| std::os::unix::fs::symlink(&target, &link));
+        if let Err(e) = created {
+            panic!("Failed to set up files: {}", e);
+        }
+
+        let mut cli = Cli {
+            files: vec![link.clone()],
+            expression: vec!["s/a/b/".to_string()],
+            in_place: true,
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        if let Err(e) = run_sed_mode(&cli) {
+            panic!("sed failed: {}", e);
+        }
+        assert_eq!(fs::read_to_string(&target).ok().as_deref(), Some("b\n"));
+        assert!(fs::symlink_metadata(&link).is_ok_and(|m| m.file_type().is_symlink()));
+
+        // Without --follow-symlinks the link is replaced by a regular file
+        cli.follow_symlinks = false;
+        if let Err(e) = run_sed_mode(&cli) {
+            panic!("sed failed: {}", e);
+        }
+        assert_eq!(fs::read_to_string(&target).ok().as_deref(), Some("b\n"));
+        assert_eq!(fs::read_to_string(&link).ok().as_deref(), Some("b\n"));
+        assert!(fs::symlink_metadata(&link).is_ok_and(|m| m.file_type().is_file()));
+    }
+
+    #[test]
+    fn test_quit_exit_code() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("input.txt");
+        if let Err(e) = fs::write(&path, "a\nb\n") {
+            panic!("Failed to write input: {}", e);
+        }
+        let cli = Cli {
+            files: vec![path],
+            expression: vec!["/b/Q3".to_string()],
+            in_place: true,
+            ..Default::default()
+        };
+        match run_sed_mode(&cli) {
+            Ok(status) => assert_eq!(status, 3),
+            Err(e) => panic!("sed failed: {}", e),
+        }
     }
 
-    Ok(())
+    #[test]
+    fn test_interactive_applies_accepted_hunks() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let path = dir.path().join("input.txt");
+        let original: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let modified = original.replace("\n2\n", "\ntwo\n").replace("18\n", "");
+        if let Err(e) = fs::write(&path, &original) {
+            panic!("Failed to write input: {}", e);
+        }
+        let cli = Cli {
+            interactive: true,
+            ..Default::default()
+        };
+
+        // Unknown answers repeat the question
+        let mut answers = ["maybe", "n", "y"].map(|a| Ok(a.to_string())).into_iter();
+        let result = preview_changes(&path, original.into(), modified.into(), &cli, &mut answers);
+        assert!(matches!(result, Ok(true)));
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                assert!(content.contains("\n2\n"));
+                assert!(!content.contains("18\n"));
+            }
+            Err(e) => panic!("Failed to read result: {}", e),
+        }
+    }
 }
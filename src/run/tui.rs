@@ -8,11 +8,20 @@ use crate::core;
 use crate::terminal;
 use crate::terminal::events::EditorEvent;
 
+/// A view opened over the files once the editor has started
+pub enum StartupView {
+    /// `--sed-debug`: the sed debugger on the first file
+    SedDebug(crate::sed::SedConfig),
+    /// `--diff a b`: the two files side by side
+    Diff,
+}
+
 /// Run in terminal (TUI) mode.
 pub fn run_terminal_mode(
     files: &[PathBuf],
     config: &Config,
     _display_mode: terminal::capabilities::DisplayMode,
+    startup: Option<StartupView>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut keybind_manager = terminal::keybinds::KeyBindingManager::new();
     for (binding, command) in &config.keybindings {
@@ -21,6 +30,26 @@ pub fn run_terminal_mode(
 
     let mut app = core::app::EditorApp::initialize_with_config(config, files);
 
+    match startup {
+        Some(StartupView::SedDebug(sed_config)) => {
+            if let Err(e) = core::commands::sed_debug::start_sed_debug(&mut app, sed_config) {
+                app.message = Some(format!("Sed Error: {}", e));
+            }
+        }
+        Some(StartupView::Diff) => {
+            if let [left, right] = files
+                && let Err(e) = core::commands::diff::start_buffer_diff(
+                    &mut app,
+                    &left.to_string_lossy(),
+                    &right.to_string_lossy(),
+                )
+            {
+                app.message = Some(format!("Diff Error: {}", e));
+            }
+        }
+        None => {}
+    }
+
     let mut display = terminal::display::Display::new_terminal(config)?;
     {
         let (cols, rows) = display.terminal_size;
@@ -114,6 +143,11 @@ pub fn run_terminal_mode(
             }
         }
 
+        if let Err(e) = app.autosave.tick(&app.buffers) {
+            app.message = Some(format!("Autosave failed: {}", e));
+            display.dirty = true;
+        }
+
         if display.dirty {
             display.render(&mut app)?;
 
@@ -142,5 +176,7 @@ pub fn run_terminal_mode(
             display.swap_buffers();
         }
     }
+    // Recovery files are only left behind by a crash
+    app.autosave.discard();
     Ok(())
 }
@@ -0,0 +1,181 @@
+//! Step-by-step execution of a sed script, for the editor's sed debugger.
+
+use super::{Command, ExecState, Output, SedConfig, Step};
+use std::io::{self, Cursor};
+
+/// Steps `run_until` takes before giving up, so a script that loops forever
+/// (`:a;ba`) can't hang the editor
+const MAX_RUN_STEPS: usize = 1_000_000;
+
+/// A sed run over one input that advances a step at a time
+pub struct Debugger {
+    config: SedConfig,
+    state: ExecState<Cursor<Vec<u8>>>,
+    output: Output<Vec<u8>>,
+    /// The most recent step
+    last_step: Option<Step>,
+}
+
+impl Debugger {
+    /// Prepare to run `config` over `input`; `w` files are created here
+    pub fn new(config: SedConfig, input: Vec<u8>) -> io::Result<Self> {
+        let separator = config.separator();
+        let mut state = ExecState::new(separator, &config.commands);
+        config.open_write_files(&mut state)?;
+        state.inputs.push_back(Cursor::new(input));
+        Ok(Self {
+            config,
+            state,
+            output: Output::new(Vec::new(), separator),
+            last_step: None,
+        })
+    }
+
+    /// Run a single step
+    pub fn step(&mut self) -> io::Result<Step> {
+        let step = self.config.step(&mut self.state, &mut self.output)?;
+        if let Step::Finished(_) = step {
+            for file in self.state.write_files.values_mut() {
+                file.flush()?;
+            }
+        }
+        self.last_step = Some(step);
+        Ok(step)
+    }
+
+    /// Step until `stop` accepts a step or the run finishes. Returns `false`
+    /// if it gave up after too many steps.
+    pub fn run_until(&mut self, stop: impl Fn(Step) -> bool) -> io::Result<bool> {
+        for _ in 0..MAX_RUN_STEPS {
+            let step = self.step()?;
+            if stop(step) || matches!(step, Step::Finished(_)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.last_step, Some(Step::Finished(_)))
+    }
+
+    /// Output written so far
+    pub fn output(&self) -> &[u8] {
+        &self.output.inner
+    }
+
+    /// The script, one command per line, indented by block and with the
+    /// command that runs next marked
+    pub fn script_listing(&self) -> String {
+        let next = match self.state.position {
+            super::Position::Command(pc) => Some(pc),
+            _ => None,
+        };
+        let mut listing = String::new();
+        let mut depth = 0;
+        for (index, sed_cmd) in self.config.commands.iter().enumerate() {
+            if let Command::BlockEnd = sed_cmd.command {
+                depth -= 1;
+            }
+            let marker = if next == Some(index) { "=>" } else { "  " };
+            let indent = "  ".repeat(depth);
+            listing.push_str(&format!(
+                "{} {:>3}  {}{}\n",
+                marker,
+                index + 1,
+                indent,
+                sed_cmd.source
+            ));
+            if let Command::Block(_) = sed_cmd.command {
+                depth += 1;
+            }
+        }
+        if next.is_some_and(|pc| pc >= self.config.commands.len()) {
+            listing.push_str("=>      (end of script)\n");
+        }
+        listing
+    }
+
+    /// Line number, last step, pattern space and hold space
+    pub fn status(&self) -> String {
+        let command = |index: usize| &self.config.commands[index].source;
+        let step = match self.last_step {
+            None => "Not started".to_string(),
+            Some(Step::Cycle) => "Started cycle".to_string(),
+            Some(Step::Ran(index)) => format!("Ran {} (address matched)", command(index)),
+            Some(Step::Skipped(index)) => {
+                format!("Skipped {} (address did not match)", command(index))
+            }
+            Some(Step::EndCycle) => "Ended cycle".to_string(),
+            Some(Step::Finished(None)) => "Finished: end of input".to_string(),
+            Some(Step::Finished(Some(code))) => format!("Finished: quit with exit code {}", code),
+        };
+        format!(
+            "Line: {}\nStep: {}\n\nPattern space:\n{}\n\nHold space:\n{}\n",
+            self.state.line_num,
+            step,
+            String::from_utf8_lossy(&self.state.pattern),
+            String::from_utf8_lossy(&self.state.hold),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(script: &str, input: &str) -> Debugger {
+        let mut config = SedConfig::new();
+        if let Err(e) = config.add_script(script) {
+            panic!("Failed to parse {:?}: {}", script, e);
+        }
+        match Debugger::new(config, input.as_bytes().to_vec()) {
+            Ok(debugger) => debugger,
+            Err(e) => panic!("Failed to start debugger: {}", e),
+        }
+    }
+
+    fn step(debugger: &mut Debugger) -> Step {
+        match debugger.step() {
+            Ok(step) => step,
+            Err(e) => panic!("Step failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_steps_through_commands() {
+        let mut debugger = start("/b/s/b/B/\nh", "a\nb\n");
+        assert_eq!(step(&mut debugger), Step::Cycle);
+        assert!(debugger.script_listing().starts_with("=>   1  /b/s/b/B/\n"));
+        assert_eq!(step(&mut debugger), Step::Skipped(0));
+        assert_eq!(step(&mut debugger), Step::Ran(1));
+        assert_eq!(step(&mut debugger), Step::EndCycle);
+        assert_eq!(debugger.output(), b"a\n");
+
+        assert_eq!(step(&mut debugger), Step::Cycle);
+        assert_eq!(step(&mut debugger), Step::Ran(0));
+        assert!(debugger.status().contains("Pattern space:\nB\n"));
+        assert!(debugger.status().contains("Hold space:\na\n"));
+    }
+
+    #[test]
+    fn test_run_until_end_and_endless_loops() {
+        let mut debugger = start("2q5", "a\nb\nc\n");
+        assert!(matches!(debugger.run_until(|_| false), Ok(true)));
+        assert!(debugger.is_finished());
+        assert_eq!(debugger.output(), b"a\nb\n");
+        assert!(debugger.status().contains("exit code 5"));
+
+        let mut debugger = start(":a\nba", "a\n");
+        assert!(matches!(debugger.run_until(|_| false), Ok(false)));
+    }
+
+    #[test]
+    fn test_listing_indents_blocks() {
+        let debugger = start("/x/{\np\n}", "");
+        assert_eq!(
+            debugger.script_listing(),
+            "     1  /x/{\n     2    p\n     3  }\n"
+        );
+    }
+}
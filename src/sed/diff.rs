@@ -1,11 +1,112 @@
-use similar::{ChangeTag, TextDiff};
+use crate::core::id::BufferId;
+use similar::{ChangeTag, DiffOp, TextDiff};
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Lines of context around changes in unified diffs
+const UNIFIED_CONTEXT: usize = 3;
 
 #[derive(Debug, Clone)]
 pub struct Hunk {
     pub start_line: usize,
     pub end_line: usize,
+    /// First line of the hunk in the modified text
+    pub new_start: usize,
     pub old_lines: Vec<String>,
     pub new_lines: Vec<String>,
+    /// The words that changed within the hunk's lines
+    pub inline: InlineChanges,
+}
+
+/// Byte ranges of the changed words in lines that were edited rather than
+/// replaced outright, keyed by line number in the original and modified text
+#[derive(Debug, Clone, Default)]
+pub struct InlineChanges {
+    pub old: Vec<(usize, Vec<Range<usize>>)>,
+    pub new: Vec<(usize, Vec<Range<usize>>)>,
+}
+
+impl InlineChanges {
+    /// Word-diff the lines paired by `ops`
+    fn from_ops<'a>(diff: &'a TextDiff<'a, 'a, '_, str>, ops: &[DiffOp]) -> Self {
+        let mut inline = Self::default();
+        for op in ops {
+            for change in diff.iter_inline_changes(op) {
+                let mut ranges = Vec::new();
+                let mut pos = 0;
+                for (emphasized, value) in change.values() {
+                    if *emphasized {
+                        ranges.push(pos..pos + value.len());
+                    }
+                    pos += value.len();
+                }
+                if ranges.is_empty() {
+                    continue;
+                }
+                match (change.tag(), change.old_index(), change.new_index()) {
+                    (ChangeTag::Delete, Some(line), _) => inline.old.push((line, ranges)),
+                    (ChangeTag::Insert, _, Some(line)) => inline.new.push((line, ranges)),
+                    _ => {}
+                }
+            }
+        }
+        inline
+    }
+
+    /// Changed ranges of `line` in the original text
+    pub fn old_line(&self, line: usize) -> &[Range<usize>] {
+        Self::find(&self.old, line)
+    }
+
+    /// Changed ranges of `line` in the modified text
+    pub fn new_line(&self, line: usize) -> &[Range<usize>] {
+        Self::find(&self.new, line)
+    }
+
+    fn find(lines: &[(usize, Vec<Range<usize>>)], line: usize) -> &[Range<usize>] {
+        lines
+            .iter()
+            .find(|(index, _)| *index == line)
+            .map_or(&[], |(_, ranges)| ranges.as_slice())
+    }
+}
+
+impl Hunk {
+    /// Split into the first `old_count` original and `new_count` modified
+    /// lines and the rest
+    pub fn split_at(&self, old_count: usize, new_count: usize) -> (Hunk, Hunk) {
+        let part = |old: Range<usize>, new: Range<usize>| {
+            let start_line = self.start_line + old.start;
+            let new_start = self.new_start + new.start;
+            let old_span = start_line..start_line + old.len();
+            let new_span = new_start..new_start + new.len();
+            let within = |lines: &[(usize, Vec<Range<usize>>)], span: &Range<usize>| {
+                lines
+                    .iter()
+                    .filter(|(line, _)| span.contains(line))
+                    .cloned()
+                    .collect()
+            };
+            Hunk {
+                start_line,
+                end_line: old_span.end.saturating_sub(1).max(start_line),
+                new_start,
+                old_lines: self.old_lines[old].to_vec(),
+                new_lines: self.new_lines[new].to_vec(),
+                inline: InlineChanges {
+                    old: within(&self.inline.old, &old_span),
+                    new: within(&self.inline.new, &new_span),
+                },
+            }
+        };
+        (
+            part(0..old_count, 0..new_count),
+            part(
+                old_count..self.old_lines.len(),
+                new_count..self.new_lines.len(),
+            ),
+        )
+    }
 }
 
 pub struct DiffView {
@@ -18,61 +119,319 @@ impl DiffView {
         Self { original, modified }
     }
 
-    pub fn compute_hunks(&self) -> Vec<Hunk> {
+    /// Unified diff of the two texts with `old_name` and `new_name` in the
+    /// `---`/`+++` header; empty if the texts are equal
+    pub fn unified_diff(&self, old_name: &str, new_name: &str) -> String {
+        TextDiff::from_lines(&self.original, &self.modified)
+            .unified_diff()
+            .context_radius(UNIFIED_CONTEXT)
+            .header(old_name, new_name)
+            .to_string()
+    }
+
+    /// The hunks of `unified_diff`, each rendered with its `@@` header
+    pub fn unified_hunks(&self) -> Vec<String> {
         let diff = TextDiff::from_lines(&self.original, &self.modified);
-        let mut hunks = Vec::new();
-
-        for hunk in diff.unified_diff().iter_hunks() {
-            let mut old_lines = Vec::new();
-            let mut new_lines = Vec::new();
-            let mut start_line = 0;
-            let mut end_line = 0;
-            let mut first = true;
-
-            for change in hunk.iter_changes() {
-                if first {
-                    start_line = match change.old_index() {
-                        Some(i) => i,
-                        None => 0,
-                    };
-                    first = false;
-                }
-                end_line = match change.old_index() {
-                    Some(i) => i,
-                    None => end_line,
-                };
-
-                match change.tag() {
-                    ChangeTag::Delete => {
-                        old_lines.push(change.value().to_string());
-                    }
-                    ChangeTag::Insert => {
-                        new_lines.push(change.value().to_string());
-                    }
-                    ChangeTag::Equal => {
-                        // In a hunk, we might want to keep some context or just the changes.
-                        // For a simple side-by-side diff preview, we usually want the changed lines.
-                    }
-                }
-            }
+        let mut unified = diff.unified_diff();
+        unified.context_radius(UNIFIED_CONTEXT);
+        unified.iter_hunks().map(|hunk| hunk.to_string()).collect()
+    }
+
+    /// The original text with only the selected hunks of `unified_hunks`
+    /// applied; `selected` is indexed like `unified_hunks`
+    pub fn apply_hunks(&self, selected: &[bool]) -> String {
+        let diff = TextDiff::from_lines(&self.original, &self.modified);
+        let old = diff.old_slices();
+        let new = diff.new_slices();
+        let mut result = String::with_capacity(self.original.len());
+        let mut old_pos = 0;
 
-            if !old_lines.is_empty() || !new_lines.is_empty() {
-                hunks.push(Hunk {
-                    start_line,
-                    end_line,
-                    old_lines,
-                    new_lines,
-                });
+        for (index, group) in diff.grouped_ops(UNIFIED_CONTEXT).iter().enumerate() {
+            let apply = selected.get(index).copied().unwrap_or(false);
+            for op in group {
+                let old_range = op.old_range();
+                // Lines between hunks are unchanged
+                result.extend(old[old_pos..old_range.start].iter().copied());
+                if apply {
+                    result.extend(new[op.new_range()].iter().copied());
+                } else {
+                    result.extend(old[old_range.clone()].iter().copied());
+                }
+                old_pos = old_range.end;
             }
         }
+        result.extend(old[old_pos..].iter().copied());
+        result
+    }
+
+    /// The hunks of `unified_hunks` with their context lines, so that each
+    /// one replaces a contiguous run of original lines
+    pub fn context_hunks(&self) -> Vec<Hunk> {
+        self.hunks_with_context(UNIFIED_CONTEXT)
+    }
+
+    /// Each run of changed lines as a hunk, without context
+    pub fn change_hunks(&self) -> Vec<Hunk> {
+        self.hunks_with_context(0)
+    }
+
+    fn hunks_with_context(&self, context: usize) -> Vec<Hunk> {
+        let diff = TextDiff::from_lines(&self.original, &self.modified);
+        let old = diff.old_slices();
+        let new = diff.new_slices();
 
-        hunks
+        diff.grouped_ops(context)
+            .iter()
+            .filter_map(|group| {
+                let (first, last) = (group.first()?, group.last()?);
+                let old_range = first.old_range().start..last.old_range().end;
+                let new_range = first.new_range().start..last.new_range().end;
+                Some(Hunk {
+                    start_line: old_range.start,
+                    end_line: old_range.end.saturating_sub(1).max(old_range.start),
+                    new_start: new_range.start,
+                    old_lines: old[old_range].iter().map(|line| line.to_string()).collect(),
+                    new_lines: new[new_range].iter().map(|line| line.to_string()).collect(),
+                    inline: InlineChanges::from_ops(&diff, group),
+                })
+            })
+            .collect()
     }
 }
 
+/// What has been done with a hunk of a sed preview
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HunkStatus {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
 pub struct DiffState {
     pub hunks: Vec<Hunk>,
+    /// Indexed like `hunks`
+    pub statuses: Vec<HunkStatus>,
+    /// The hunks applied by each accept, most recent last, for undoing them
+    pub accept_history: Vec<Vec<usize>>,
     pub current_hunk: usize,
     pub original_buffer_id: crate::core::id::BufferId,
     pub original_window_id: crate::core::id::WindowId,
+    /// Set when the preview covers only a region of the buffer
+    pub region: Option<DiffRegion>,
+    /// The buffer on the right of a diff between two buffers; `None` for sed
+    /// previews, whose right side exists only in the view
+    pub modified_buffer_id: Option<BufferId>,
+    /// The `DiffOriginal` and `DiffModified` buffers showing the two sides
+    pub view_buffer_ids: (BufferId, BufferId),
+}
+
+impl DiffState {
+    /// Replace the hunks, all of them pending
+    pub fn set_hunks(&mut self, hunks: Vec<Hunk>) {
+        self.statuses = vec![HunkStatus::Pending; hunks.len()];
+        self.accept_history.clear();
+        self.hunks = hunks;
+    }
+
+    /// First line of hunk `index` in the original buffer as it is now, i.e.
+    /// shifted by the accepted hunks above it
+    pub fn current_start(&self, index: usize) -> usize {
+        // Hunks are kept in buffer order
+        let shift: isize = self.hunks[..index]
+            .iter()
+            .zip(&self.statuses)
+            .filter(|(_, status)| **status == HunkStatus::Accepted)
+            .map(|(hunk, _)| hunk.new_lines.len() as isize - hunk.old_lines.len() as isize)
+            .sum();
+        self.hunks[index].start_line.saturating_add_signed(shift)
+    }
+
+    /// The first pending hunk after `index`, wrapping around
+    pub fn next_pending(&self, index: usize) -> Option<usize> {
+        let count = self.hunks.len();
+        (1..=count)
+            .map(|step| (index + step) % count)
+            .find(|&i| self.statuses[i] == HunkStatus::Pending)
+    }
+}
+
+/// The region of a region-scoped preview, applied as a whole
+#[derive(Debug, Clone)]
+pub struct DiffRegion {
+    /// Byte offset of the region in the original buffer
+    pub start: usize,
+    /// The region's text when the preview was made
+    pub original: String,
+    /// The text that replaces it
+    pub modified: String,
+}
+
+/// Where the accepted hunks of one file in a review are applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReviewTarget {
+    /// An open buffer, edited in place
+    Buffer(BufferId),
+    /// A file that is not open, rewritten on disk
+    File(PathBuf),
+}
+
+/// One file of a multi-file review
+#[derive(Debug, Clone)]
+pub struct ReviewFile {
+    /// Name shown in the review buffer
+    pub name: String,
+    pub target: ReviewTarget,
+}
+
+/// One hunk of a multi-file review
+#[derive(Debug, Clone)]
+pub struct ReviewHunk {
+    /// Index into `ReviewState::files`
+    pub file: usize,
+    /// The change with its context lines (see `DiffView::context_hunks`)
+    pub hunk: Hunk,
+    /// Line of the hunk's `@@` header in the review buffer
+    pub review_line: usize,
+    /// Whether the hunk has been applied
+    pub accepted: bool,
+}
+
+/// A sed preview across many files, shown as one unified diff in a review
+/// buffer
+pub struct ReviewState {
+    pub files: Vec<ReviewFile>,
+    pub hunks: Vec<ReviewHunk>,
+    pub current_hunk: usize,
+    pub review_buffer_id: BufferId,
+    /// Buffer shown before the review started
+    pub original_buffer_id: BufferId,
+}
+
+impl ReviewState {
+    /// First line of hunk `index` in its file as it is now, i.e. shifted by
+    /// the hunks above it that have already been applied
+    pub fn current_start(&self, index: usize) -> usize {
+        let target = &self.hunks[index];
+        let shift: isize = self
+            .hunks
+            .iter()
+            .filter(|h| {
+                h.accepted && h.file == target.file && h.hunk.start_line < target.hunk.start_line
+            })
+            .map(|h| h.hunk.new_lines.len() as isize - h.hunk.old_lines.len() as isize)
+            .sum();
+        target.hunk.start_line.saturating_add_signed(shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> DiffView {
+        let original: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let modified: String = (1..=20)
+            .filter(|&n| n != 18)
+            .map(|n| match n {
+                2 => "two\n".to_string(),
+                n => format!("{}\n", n),
+            })
+            .collect();
+        DiffView::new(original, modified)
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = sample().unified_diff("a.txt", "a.txt");
+        assert!(diff.starts_with("--- a.txt\n+++ a.txt\n@@ -1,5 +1,5 @@\n"));
+        assert!(diff.contains("-2\n+two\n"));
+        assert!(diff.contains("-18\n"));
+        assert_eq!(
+            DiffView::new("a\n".into(), "a\n".into()).unified_diff("x", "x"),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_apply_selected_hunks() {
+        let view = sample();
+        assert_eq!(view.unified_hunks().len(), 2);
+        assert_eq!(view.apply_hunks(&[true, true]), view.modified);
+        assert_eq!(view.apply_hunks(&[false, false]), view.original);
+
+        let first_only = view.apply_hunks(&[true, false]);
+        assert!(first_only.contains("two\n") && first_only.contains("18\n"));
+        let second_only = view.apply_hunks(&[false, true]);
+        assert!(second_only.contains("\n2\n") && !second_only.contains("18\n"));
+    }
+
+    #[test]
+    fn test_context_hunks() {
+        let view = sample();
+        let hunks = view.context_hunks();
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].start_line, 0);
+        assert_eq!(hunks[0].old_lines.concat(), "1\n2\n3\n4\n5\n");
+        assert_eq!(hunks[0].new_lines.concat(), "1\ntwo\n3\n4\n5\n");
+        assert_eq!(hunks[1].start_line, 14);
+        assert_eq!(hunks[1].old_lines.len(), 6);
+        assert_eq!(hunks[1].new_lines.len(), 5);
+    }
+
+    #[test]
+    fn test_change_hunks() {
+        let hunks = sample().change_hunks();
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].start_line, hunks[0].new_start), (1, 1));
+        assert_eq!(hunks[0].old_lines, ["2\n"]);
+        assert_eq!(hunks[0].new_lines, ["two\n"]);
+        assert_eq!((hunks[1].start_line, hunks[1].new_start), (17, 17));
+        assert!(hunks[1].new_lines.is_empty());
+    }
+
+    #[test]
+    fn test_inline_changes() {
+        let view = DiffView::new(
+            "let total = count * 2;\nkeep\n".to_string(),
+            "let total = count * 3;\nkeep\nnew line\n".to_string(),
+        );
+        let hunks = view.change_hunks();
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].inline.old_line(0), [20..22]);
+        assert_eq!(hunks[0].inline.new_line(0), [20..22]);
+        // Whole inserted lines are already marked by the line tint
+        assert!(hunks[1].inline.new_line(2).is_empty());
+    }
+
+    #[test]
+    fn test_review_start_shifts_with_accepted_hunks() {
+        let hunk = |start_line, old: usize, new: usize| Hunk {
+            start_line,
+            end_line: start_line + old - 1,
+            new_start: start_line,
+            old_lines: vec!["x\n".to_string(); old],
+            new_lines: vec!["y\n".to_string(); new],
+            inline: InlineChanges::default(),
+        };
+        let review_hunk = |file, hunk, accepted| ReviewHunk {
+            file,
+            hunk,
+            review_line: 0,
+            accepted,
+        };
+        let mut state = ReviewState {
+            files: Vec::new(),
+            hunks: vec![
+                review_hunk(0, hunk(0, 3, 5), false),
+                review_hunk(1, hunk(0, 3, 1), true),
+                review_hunk(0, hunk(10, 2, 2), false),
+            ],
+            current_hunk: 0,
+            review_buffer_id: BufferId(0),
+            original_buffer_id: BufferId(0),
+        };
+        assert_eq!(state.current_start(2), 10);
+        state.hunks[0].accepted = true;
+        assert_eq!(state.current_start(2), 12);
+    }
 }
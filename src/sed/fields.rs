@@ -0,0 +1,138 @@
+//! Field splitting for sed's field mode (`-F`).
+//!
+//! In field mode each record is split into fields as awk does, and `$N`
+//! (1-based; `$0` is the whole record) or `$NF` (the last field) may be used
+//! in `$N/re/` addresses and in `s` replacements.
+
+use super::posix::{self, RegexSyntax};
+use regex::bytes::Regex;
+
+/// How records are split into fields
+#[derive(Debug, Clone)]
+pub enum FieldSeparator {
+    /// Runs of blanks, ignoring leading and trailing ones (`-F ' '`)
+    Blank,
+    /// A literal string, normally one character
+    Literal(Vec<u8>),
+    /// An extended regular expression
+    Regex(Regex),
+}
+
+impl FieldSeparator {
+    /// Parse a `-F` argument the way awk does: a single space means runs of
+    /// blanks, `\t` is a tab, another single character is literal and
+    /// anything longer is an extended regular expression
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec {
+            "" => Err("Empty field separator".to_string()),
+            " " => Ok(Self::Blank),
+            "\\t" => Ok(Self::Literal(b"\t".to_vec())),
+            _ if spec.chars().count() == 1 => Ok(Self::Literal(spec.as_bytes().to_vec())),
+            _ => {
+                let pattern = posix::translate(spec, RegexSyntax::Extended)?;
+                Regex::new(&pattern)
+                    .map(Self::Regex)
+                    .map_err(|e| format!("Invalid field separator: {}", e))
+            }
+        }
+    }
+
+    /// Split `record` into fields; an empty record has none
+    pub fn split<'a>(&self, record: &'a [u8]) -> Vec<&'a [u8]> {
+        match self {
+            Self::Blank => record
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|field| !field.is_empty())
+                .collect(),
+            _ if record.is_empty() => Vec::new(),
+            Self::Literal(separator) => {
+                let mut fields = Vec::new();
+                let mut rest = record;
+                while let Some(pos) = rest
+                    .windows(separator.len())
+                    .position(|window| window == separator.as_slice())
+                {
+                    fields.push(&rest[..pos]);
+                    rest = &rest[pos + separator.len()..];
+                }
+                fields.push(rest);
+                fields
+            }
+            Self::Regex(re) => re.split(record).collect(),
+        }
+    }
+}
+
+/// A field reference: `$N` or `$NF`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldRef {
+    /// Field N, 1-based; 0 is the whole record
+    Index(usize),
+    /// The last field (`$NF`)
+    Last,
+}
+
+impl FieldRef {
+    /// Parse the reference following a `$`, returning it and the rest
+    pub fn parse(text: &str) -> Option<(Self, &str)> {
+        if let Some(rest) = text.strip_prefix("NF") {
+            return Some((Self::Last, rest));
+        }
+        let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let index = text[..digits].parse().ok()?;
+        Some((Self::Index(index), &text[digits..]))
+    }
+
+    /// The text of this field of `record`, split with `separator`; missing
+    /// fields are empty
+    pub fn get<'a>(self, record: &'a [u8], separator: &FieldSeparator) -> &'a [u8] {
+        let fields = separator.split(record);
+        let field = match self {
+            Self::Index(0) => return record,
+            Self::Index(n) => fields.get(n - 1),
+            Self::Last => fields.last(),
+        };
+        field.copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(spec: &str, record: &str) -> Vec<String> {
+        let separator = match FieldSeparator::parse(spec) {
+            Ok(separator) => separator,
+            Err(e) => panic!("Invalid separator {:?}: {}", spec, e),
+        };
+        separator
+            .split(record.as_bytes())
+            .iter()
+            .map(|field| String::from_utf8_lossy(field).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(" ", "  a \tb  c "), ["a", "b", "c"]);
+        assert_eq!(split(":", "root:x::0"), ["root", "x", "", "0"]);
+        assert_eq!(split("\\t", "a\tb"), ["a", "b"]);
+        assert_eq!(split("[,;]+", "a,;b;c"), ["a", "b", "c"]);
+        assert!(split(":", "").is_empty());
+        assert!(FieldSeparator::parse("").is_err());
+    }
+
+    #[test]
+    fn test_field_refs() {
+        let separator = FieldSeparator::Literal(b":".to_vec());
+        let get = |spec: &str| match FieldRef::parse(spec) {
+            Some((field, "")) => field.get(b"a:b:c", &separator).to_vec(),
+            _ => panic!("Invalid field reference {:?}", spec),
+        };
+        assert_eq!(get("0"), b"a:b:c");
+        assert_eq!(get("2"), b"b");
+        assert_eq!(get("NF"), b"c");
+        assert_eq!(get("7"), b"");
+        assert_eq!(FieldRef::parse("x"), None);
+    }
+}
@@ -1,26 +1,99 @@
-use regex::Regex;
+use regex::bytes::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
 use std::io::{self, BufRead};
+use std::path::PathBuf;
 
+pub mod debug;
 pub mod diff;
+pub mod fields;
+pub mod patch;
+pub mod posix;
+pub mod replacement;
+pub mod structural;
+
+use fields::{FieldRef, FieldSeparator};
+use posix::RegexSyntax;
+use replacement::Replacement;
 
 /// Sed command types
 #[derive(Debug, Clone)]
 pub enum Command {
     /// Substitute pattern with replacement
     Substitute {
-        pattern: Regex,
-        replacement: String,
+        /// `None` for an empty pattern, which reuses the last regex used
+        pattern: Option<Regex>,
+        replacement: Replacement,
+        /// Replace starting from this match (1-based, numeric flag)
+        occurrence: usize,
+        /// Replace every match from `occurrence` on (`g`)
         global: bool,
+        /// Print the pattern space after a substitution (`p`)
         print: bool,
+        /// Write the pattern space to a file after a substitution (`w file`)
+        write: Option<PathBuf>,
+        /// Execute the pattern space as a shell command and replace it with
+        /// the output (`e`)
+        execute: bool,
     },
-    /// Delete line
+    /// Delete pattern space, start next cycle (`d`)
     Delete,
-    /// Print line
+    /// Delete up to the first newline, restart cycle without reading input (`D`)
+    DeleteFirstLine,
+    /// Print pattern space (`p`)
     Print,
+    /// Print up to the first newline (`P`)
+    PrintFirstLine,
+    /// Queue text for output at the end of the cycle (`a`)
+    Append(String),
+    /// Output text immediately (`i`)
+    Insert(String),
+    /// Replace the selected lines with text (`c`)
+    Change(String),
+    /// Transliterate characters (`y/src/dst/`)
+    Transliterate(HashMap<char, char>),
+    /// Print the current line number (`=`)
+    LineNumber,
+    /// Print pattern space unambiguously (`l`)
+    List,
+    /// Auto-print and stop processing with an exit code (`q`)
+    Quit(i32),
+    /// Stop processing without printing, with an exit code (`Q`)
+    QuitSilent(i32),
+    /// Auto-print, then replace pattern space with the next line (`n`)
+    Next,
+    /// Append a newline and the next line to pattern space (`N`)
+    NextAppend,
+    /// Copy pattern space to hold space (`h`)
+    Hold,
+    /// Append pattern space to hold space (`H`)
+    HoldAppend,
+    /// Copy hold space to pattern space (`g`)
+    Get,
+    /// Append hold space to pattern space (`G`)
+    GetAppend,
+    /// Exchange pattern and hold spaces (`x`)
+    Exchange,
+    /// Queue the contents of a file for output (`r`)
+    ReadFile(PathBuf),
+    /// Write pattern space to a file (`w`)
+    WriteFile(PathBuf),
+    /// Start of a `{ ... }` block; holds the index of the matching `}`
+    Block(usize),
+    /// End of a `{ ... }` block
+    BlockEnd,
+    /// Branch target (`:label`)
+    Label(String),
+    /// Branch to a label, or to the end of the script (`b`)
+    Branch(Option<String>),
+    /// Branch if a substitution succeeded since the last input line or `t`/`T` (`t`)
+    BranchIfSubstituted(Option<String>),
+    /// Branch if no substitution succeeded since the last input line or `t`/`T` (`T`)
+    BranchIfNotSubstituted(Option<String>),
 }
 
 /// Address specification for commands
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub enum Address {
     /// Specific line number (1-indexed)
     Line(usize),
@@ -28,13 +101,86 @@ pub enum Address {
     Range(usize, usize),
     /// All lines
     All,
+    /// Last line of input (`$`)
+    Last,
+    /// Lines whose pattern space matches a regex (`/re/` or `\cREc`)
+    Regex(Regex),
+    /// Lines matching the last regex used, at run time (`//`)
+    LastRegex,
+    /// Every `step`th line starting at `first` (GNU `first~step`)
+    Step(usize, usize),
+    /// Stateful range `addr1,addr2` where either end is not a plain line number
+    Span(Box<Address>, RangeEnd),
+    /// Lines whose field matches a regex (`$N/re/`, field mode)
+    Field {
+        field: FieldRef,
+        separator: FieldSeparator,
+        regex: Regex,
+    },
+}
+
+/// End of a stateful address range
+#[derive(Debug, Clone)]
+pub enum RangeEnd {
+    /// A line number, `$` or regex
+    Address(Box<Address>),
+    /// `addr1,+N`: the start line and the N lines after it
+    Relative(usize),
+    /// `addr1,~N`: up to the next line whose number is a multiple of N
+    Multiple(usize),
+}
+
+impl PartialEq for Address {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Address::Line(a), Address::Line(b)) => a == b,
+            (Address::Range(a1, a2), Address::Range(b1, b2)) => a1 == b1 && a2 == b2,
+            (Address::All, Address::All)
+            | (Address::Last, Address::Last)
+            | (Address::LastRegex, Address::LastRegex) => true,
+            // Regexes have no structural equality; compare their source
+            (Address::Regex(a), Address::Regex(b)) => a.as_str() == b.as_str(),
+            (Address::Step(a1, a2), Address::Step(b1, b2)) => a1 == b1 && a2 == b2,
+            (Address::Span(a_start, a_end), Address::Span(b_start, b_end)) => {
+                a_start == b_start && a_end == b_end
+            }
+            (
+                Address::Field {
+                    field: a_field,
+                    regex: a_regex,
+                    ..
+                },
+                Address::Field {
+                    field: b_field,
+                    regex: b_regex,
+                    ..
+                },
+            ) => a_field == b_field && a_regex.as_str() == b_regex.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for RangeEnd {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (RangeEnd::Address(a), RangeEnd::Address(b)) => a == b,
+            (RangeEnd::Relative(a), RangeEnd::Relative(b)) => a == b,
+            (RangeEnd::Multiple(a), RangeEnd::Multiple(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 /// A sed command with its address
 #[derive(Debug, Clone)]
 pub struct SedCommand {
     pub address: Address,
+    /// Whether the address is negated with `!`
+    pub negated: bool,
     pub command: Command,
+    /// The command as written in the script
+    pub source: String,
 }
 
 /// Sed script executor configuration
@@ -44,6 +190,264 @@ pub struct SedConfig {
     pub quiet: bool,
     /// Commands to execute
     pub commands: Vec<SedCommand>,
+    /// Regex dialect for scripts added after it is set
+    pub syntax: RegexSyntax,
+    /// Separate input records with NUL bytes instead of newlines (`-z`)
+    pub null_data: bool,
+    /// Reject GNU extensions to the command set and addresses (`--posix`)
+    pub posix: bool,
+    /// Split records into fields for `$N` references (`-F`), for scripts
+    /// added after it is set
+    pub field_separator: Option<FieldSeparator>,
+    /// Label name to command index, for `b`, `t` and `T`
+    labels: HashMap<String, usize>,
+    /// A structural expression run over the whole input instead of the
+    /// commands (see `structural`)
+    structural: Option<structural::Program>,
+}
+
+/// How the cycle continues after a command has run
+enum Flow {
+    /// Run the next command
+    Continue,
+    /// End the cycle normally (auto-print)
+    EndCycle,
+    /// End the cycle without auto-printing (`d`, `c`)
+    Delete,
+    /// Restart the cycle without reading new input (`D`)
+    Restart,
+    /// Continue at the command with the given index
+    Jump(usize),
+    /// Stop processing input
+    Quit { autoprint: bool, exit_code: i32 },
+}
+
+/// Where a run continues with its next step
+enum Position {
+    /// Read the next line and start a cycle
+    Read,
+    /// Start a cycle on the current pattern space without reading (`D`)
+    Restart,
+    /// Check the address of the command at this index and run it
+    Command(usize),
+    /// End the cycle as the given flow says
+    EndCycle(Flow),
+    /// Input is exhausted or the script quit
+    Done,
+}
+
+/// What one step of a run did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Started a cycle: read a line, or restarted after `D`
+    Cycle,
+    /// Ran the command at this index
+    Ran(usize),
+    /// Skipped the command at this index because its address didn't match
+    Skipped(usize),
+    /// Ended the cycle, auto-printing unless told otherwise
+    EndCycle,
+    /// Finished, with the exit code given to `q` or `Q` if the script quit
+    Finished(Option<i32>),
+}
+
+/// Output queued by `a` and `r`, flushed at the end of the cycle
+enum Queued {
+    Text(String),
+    File(PathBuf),
+}
+
+/// Whether a stateful range address is currently selecting lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeState {
+    Inactive,
+    /// Inside the range; `end_line` is known once the end resolves to a line number
+    Active {
+        end_line: Option<usize>,
+    },
+}
+
+/// Destination for sed output.
+///
+/// Pattern spaces are written back with the separator they were read with,
+/// so a final line without one stays without one. If more output follows
+/// such a line, the missing separator is written first.
+struct Output<W> {
+    inner: W,
+    separator: u8,
+    /// The last record written lacked its trailing separator
+    missing_separator: bool,
+}
+
+impl<W: io::Write> Output<W> {
+    fn new(inner: W, separator: u8) -> Self {
+        Self {
+            inner,
+            separator,
+            missing_separator: false,
+        }
+    }
+
+    /// Write raw bytes
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if std::mem::take(&mut self.missing_separator) {
+            self.inner.write_all(&[self.separator])?;
+        }
+        self.inner.write_all(bytes)
+    }
+
+    /// Write text followed by a newline (`=`, `a`, `i`, `c`)
+    fn write_line(&mut self, text: &[u8]) -> io::Result<()> {
+        self.write(text)?;
+        self.inner.write_all(b"\n")
+    }
+
+    /// Write a record, followed by the separator if `terminated`
+    fn write_record(&mut self, record: &[u8], terminated: bool) -> io::Result<()> {
+        self.write(record)?;
+        if terminated {
+            self.inner.write_all(&[self.separator])
+        } else {
+            self.missing_separator = true;
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Mutable state of a sed run
+struct ExecState<R> {
+    /// Inputs of the current stream, read one after the other
+    inputs: VecDeque<R>,
+    /// Input record separator: `\n`, or NUL with `-z`
+    separator: u8,
+    /// One record of lookahead, used to detect the last line for `$`
+    peeked: Option<(Vec<u8>, bool)>,
+    pattern: Vec<u8>,
+    hold: Vec<u8>,
+    line_num: usize,
+    /// Whether the last line read ended with the separator
+    terminated: bool,
+    append_queue: Vec<Queued>,
+    write_files: HashMap<PathBuf, Output<File>>,
+    /// Range state per command, indexed like `SedConfig::commands`
+    ranges: Vec<RangeState>,
+    /// Set by a successful `s`, cleared on new input and by `t`/`T`
+    substituted: bool,
+    /// The last regex an address or `s` matched with, for empty regexes
+    last_regex: Option<Regex>,
+    /// Where the next step continues
+    position: Position,
+    /// Exit code given to `q` or `Q`, once the script quit
+    exit_code: Option<i32>,
+}
+
+impl<R: BufRead> ExecState<R> {
+    fn new(separator: u8, commands: &[SedCommand]) -> Self {
+        let ranges = commands
+            .iter()
+            .map(|sed_cmd| match &sed_cmd.address {
+                // `0,/re/` is already active before the first line is read
+                Address::Span(start, _) if **start == Address::Line(0) => {
+                    RangeState::Active { end_line: None }
+                }
+                _ => RangeState::Inactive,
+            })
+            .collect();
+
+        Self {
+            inputs: VecDeque::new(),
+            separator,
+            peeked: None,
+            pattern: Vec::new(),
+            hold: Vec::new(),
+            line_num: 0,
+            terminated: true,
+            append_queue: Vec::new(),
+            write_files: HashMap::new(),
+            ranges,
+            substituted: false,
+            last_regex: None,
+            position: Position::Read,
+            exit_code: None,
+        }
+    }
+
+    /// Read one record from the input, without its separator, along with
+    /// whether the separator was present. The end of each input also ends a
+    /// record.
+    fn read_record(&mut self) -> io::Result<Option<(Vec<u8>, bool)>> {
+        let mut record = Vec::new();
+        while let Some(input) = self.inputs.front_mut() {
+            if input.read_until(self.separator, &mut record)? == 0 {
+                self.inputs.pop_front();
+                continue;
+            }
+            let terminated = record.last() == Some(&self.separator);
+            if terminated {
+                record.pop();
+            }
+            return Ok(Some((record, terminated)));
+        }
+        Ok(None)
+    }
+
+    /// Read the next input line, returning `None` at end of input
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let record = match self.peeked.take() {
+            Some(record) => Some(record),
+            None => self.read_record()?,
+        };
+        Ok(record.map(|(line, terminated)| {
+            self.line_num += 1;
+            self.terminated = terminated;
+            self.substituted = false;
+            line
+        }))
+    }
+
+    /// Check whether the current line is the last line of input
+    fn is_last_line(&mut self) -> io::Result<bool> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_record()?;
+        }
+        Ok(self.peeked.is_none())
+    }
+
+    /// Remember `regex` as the last regex used and return it, or return the
+    /// last one for an empty regex (`None`)
+    fn use_regex(&mut self, regex: Option<&Regex>) -> io::Result<Regex> {
+        if let Some(regex) = regex {
+            self.last_regex = Some(regex.clone());
+        }
+        self.last_regex.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No previous regular expression")
+        })
+    }
+
+    /// Write the pattern space as a record
+    fn print_pattern<W: io::Write>(&self, output: &mut Output<W>) -> io::Result<()> {
+        output.write_record(&self.pattern, self.terminated)
+    }
+
+    /// Write out and clear everything queued by `a` and `r`
+    fn flush_appends<W: io::Write>(&mut self, output: &mut Output<W>) -> io::Result<()> {
+        for queued in self.append_queue.drain(..) {
+            match queued {
+                Queued::Text(text) => output.write_line(text.as_bytes())?,
+                Queued::File(path) => {
+                    // Missing or unreadable files are silently ignored, as in POSIX sed
+                    if let Ok(content) = std::fs::read(&path) {
+                        output.write(&content)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl SedConfig {
@@ -51,196 +455,1286 @@ impl SedConfig {
         Self {
             quiet: false,
             commands: Vec::new(),
+            syntax: RegexSyntax::Basic,
+            null_data: false,
+            posix: false,
+            field_separator: None,
+            labels: HashMap::new(),
+            structural: None,
         }
     }
 
-    /// Parse a sed script string and add it to commands
+    /// Parse a sed script and add its commands.
+    ///
+    /// A script holds any number of commands separated by newlines or `;`,
+    /// grouped with `{ ... }`, plus `:label` definitions and `#` comments.
+    /// Branch targets must be defined by the time the script is added.
+    ///
+    /// A script starting with `,` is a structural expression instead, which
+    /// can't be combined with other scripts.
     pub fn add_script(&mut self, script: &str) -> Result<(), String> {
-        let cmd = parse_sed_command(script)?;
-        self.commands.push(cmd);
+        if let Some(expression) = script.trim_start().strip_prefix(',') {
+            if self.posix {
+                return Err("Structural expressions are not POSIX".to_string());
+            }
+            if !self.commands.is_empty() || self.structural.is_some() {
+                return Err("A structural expression must be the whole script".to_string());
+            }
+            self.structural = Some(structural::Program::parse(expression, self.syntax)?);
+            return Ok(());
+        }
+        if self.structural.is_some() {
+            return Err("A structural expression must be the whole script".to_string());
+        }
+
+        // `#n` on the first line of the first script acts like `-n`
+        if self.commands.is_empty() && (script == "#n" || script.starts_with("#n\n")) {
+            self.quiet = true;
+        }
+
+        let base = self.commands.len();
+        let mut parsed: Vec<SedCommand> = Vec::new();
+        let mut labels = HashMap::new();
+        let mut open_blocks = Vec::new();
+        let mut rest = script;
+
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(comment) = rest.strip_prefix('#') {
+                rest = match comment.find('\n') {
+                    Some(pos) => &comment[pos..],
+                    None => "",
+                };
+                continue;
+            }
+
+            let (sed_cmd, after) = parse_command(rest, self.syntax, self.field_separator.as_ref())?;
+            if self.posix {
+                check_posix(&sed_cmd)?;
+            }
+            let index = base + parsed.len();
+            match &sed_cmd.command {
+                Command::Block(_) => open_blocks.push(parsed.len()),
+                Command::BlockEnd => {
+                    let start = open_blocks.pop().ok_or("Unexpected '}'")?;
+                    parsed[start].command = Command::Block(index);
+                }
+                Command::Label(name)
+                    if self.labels.contains_key(name)
+                        || labels.insert(name.clone(), index).is_some() =>
+                {
+                    return Err(format!("Duplicate label: {}", name));
+                }
+                _ => {}
+            }
+            parsed.push(sed_cmd);
+            rest = after;
+        }
+
+        if !open_blocks.is_empty() {
+            return Err("Unmatched '{'".to_string());
+        }
+
+        for sed_cmd in &parsed {
+            if let Command::Branch(Some(label))
+            | Command::BranchIfSubstituted(Some(label))
+            | Command::BranchIfNotSubstituted(Some(label)) = &sed_cmd.command
+            {
+                if !labels.contains_key(label) && !self.labels.contains_key(label) {
+                    return Err(format!("Can't find label for jump to '{}'", label));
+                }
+            }
+        }
+
+        self.labels.extend(labels);
+        self.commands.extend(parsed);
+        Ok(())
+    }
+
+    /// Execute sed commands on input.
+    ///
+    /// Input is processed as bytes: invalid UTF-8, `\r\n` line endings and a
+    /// missing final newline all pass through unchanged unless the script
+    /// edits them. Returns the exit code given to `q` or `Q`, if the script
+    /// quit.
+    pub fn execute<R: BufRead, W: io::Write>(
+        &self,
+        reader: R,
+        writer: W,
+    ) -> io::Result<Option<i32>> {
+        let mut run = self.start()?;
+        let exit_code = run.process(vec![reader], writer)?;
+        run.finish()?;
+        Ok(exit_code)
+    }
+
+    /// Start a run that can process several streams, keeping the hold space,
+    /// range state and `w` files between them (separate-file and in-place
+    /// modes)
+    pub fn start<R: BufRead>(&self) -> io::Result<SedRun<'_, R>> {
+        let mut state = ExecState::new(self.separator(), &self.commands);
+        self.open_write_files(&mut state)?;
+        Ok(SedRun {
+            config: self,
+            state,
+        })
+    }
+
+    /// Input record separator
+    fn separator(&self) -> u8 {
+        if self.null_data { b'\0' } else { b'\n' }
+    }
+
+    /// Create (truncate) every `w` file up front, as POSIX requires
+    fn open_write_files<R>(&self, state: &mut ExecState<R>) -> io::Result<()> {
+        for sed_cmd in &self.commands {
+            let path = match &sed_cmd.command {
+                Command::WriteFile(path)
+                | Command::Substitute {
+                    write: Some(path), ..
+                } => path,
+                _ => continue,
+            };
+            if !state.write_files.contains_key(path) {
+                let file = Output::new(File::create(path)?, state.separator);
+                state.write_files.insert(path.clone(), file);
+            }
+        }
         Ok(())
     }
 
-    /// Execute sed commands on input
-    pub fn execute<R: BufRead, W: io::Write>(&self, reader: R, mut writer: W) -> io::Result<()> {
-        let mut line_num = 0;
+    /// Check whether the command at `index` applies to the current line,
+    /// updating its range state
+    fn selects<R: BufRead>(&self, index: usize, state: &mut ExecState<R>) -> io::Result<bool> {
+        let sed_cmd = &self.commands[index];
+        let matched = match &sed_cmd.address {
+            Address::Span(start, end) => match_range(index, start, end, state)?,
+            address => match_single(address, state)?,
+        };
+        Ok(matched != sed_cmd.negated)
+    }
 
-        for line_result in reader.lines() {
-            let mut line = line_result?;
-            line_num += 1;
-            let mut print_line = !self.quiet;
-            let mut deleted = false;
+    /// Check whether the current line ends the range selected by the command
+    /// at `index` (always true for non-range addresses)
+    fn at_range_end<R>(&self, index: usize, state: &ExecState<R>) -> bool {
+        let sed_cmd = &self.commands[index];
+        if sed_cmd.negated {
+            return true;
+        }
+        match sed_cmd.address {
+            Address::Range(_, end) => state.line_num >= end,
+            Address::Span(..) => state.ranges[index] == RangeState::Inactive,
+            _ => true,
+        }
+    }
 
-            for sed_cmd in &self.commands {
-                // Check if command applies to this line
-                if !matches_address(&sed_cmd.address, line_num) {
-                    continue;
+    /// Run a single command against the pattern space
+    fn run_command<R: BufRead, W: io::Write>(
+        &self,
+        index: usize,
+        state: &mut ExecState<R>,
+        writer: &mut Output<W>,
+    ) -> io::Result<Flow> {
+        match &self.commands[index].command {
+            Command::Substitute {
+                pattern,
+                replacement,
+                occurrence,
+                global,
+                print: print_flag,
+                write,
+                execute,
+            } => {
+                let pattern = state.use_regex(pattern.as_ref())?;
+                let Some(result) =
+                    substitute(&pattern, replacement, &state.pattern, *occurrence, *global)
+                else {
+                    return Ok(Flow::Continue);
+                };
+                state.pattern = result;
+                state.substituted = true;
+                if *execute {
+                    state.pattern = run_shell_command(&state.pattern)?;
                 }
-
-                match &sed_cmd.command {
-                    Command::Substitute {
-                        pattern,
-                        replacement,
-                        global,
-                        print: print_flag,
-                    } => {
-                        if *global {
-                            line = pattern.replace_all(&line, replacement).to_string();
-                        } else {
-                            line = if pattern.is_match(&line) {
-                                pattern.replacen(&line, 1, replacement).to_string()
-                            } else {
-                                line
-                            };
-                        }
-                        if *print_flag {
-                            writeln!(writer, "{}", line)?;
+                if *print_flag {
+                    state.print_pattern(writer)?;
+                }
+                if let Some(file) = write.as_ref().and_then(|p| state.write_files.get_mut(p)) {
+                    file.write_record(&state.pattern, state.terminated)?;
+                }
+            }
+            Command::Delete => return Ok(Flow::Delete),
+            Command::DeleteFirstLine => match find_byte(&state.pattern, state.separator) {
+                Some(pos) => {
+                    state.pattern.drain(..=pos);
+                    return Ok(Flow::Restart);
+                }
+                None => return Ok(Flow::Delete),
+            },
+            Command::Print => state.print_pattern(writer)?,
+            Command::PrintFirstLine => {
+                let end = find_byte(&state.pattern, state.separator).unwrap_or(state.pattern.len());
+                writer.write_record(&state.pattern[..end], true)?;
+            }
+            Command::Append(text) => {
+                state.append_queue.push(Queued::Text(text.clone()));
+            }
+            Command::Insert(text) => writer.write_line(text.as_bytes())?,
+            Command::Change(text) => {
+                // With a range, the text replaces the whole range and is
+                // printed once at its end
+                if self.at_range_end(index, state) {
+                    writer.write_line(text.as_bytes())?;
+                }
+                return Ok(Flow::Delete);
+            }
+            Command::Transliterate(map) => {
+                state.pattern = transliterate(&state.pattern, map);
+            }
+            Command::LineNumber => {
+                writer.write_line(state.line_num.to_string().as_bytes())?;
+            }
+            Command::List => {
+                writer.write(list_escape(&state.pattern, LIST_LINE_WIDTH).as_bytes())?;
+            }
+            Command::Quit(exit_code) => {
+                return Ok(Flow::Quit {
+                    autoprint: true,
+                    exit_code: *exit_code,
+                });
+            }
+            Command::QuitSilent(exit_code) => {
+                return Ok(Flow::Quit {
+                    autoprint: false,
+                    exit_code: *exit_code,
+                });
+            }
+            Command::Next => {
+                match state.read_line()? {
+                    Some(line) => {
+                        // Print the old line with the separator it was read with
+                        if !self.quiet {
+                            writer.write_record(&state.pattern, true)?;
                         }
+                        state.flush_appends(writer)?;
+                        state.pattern = line;
                     }
-                    Command::Delete => {
-                        deleted = true;
-                        print_line = false;
-                        break; // Stop processing this line
-                    }
-                    Command::Print => {
-                        writeln!(writer, "{}", line)?;
-                    }
+                    // No next line: GNU sed prints the pattern space and exits
+                    None => return Ok(Flow::EndCycle),
+                }
+            }
+            Command::NextAppend => match state.read_line()? {
+                Some(line) => {
+                    state.flush_appends(writer)?;
+                    state.pattern.push(state.separator);
+                    state.pattern.extend_from_slice(&line);
+                }
+                None => return Ok(Flow::EndCycle),
+            },
+            Command::Hold => state.hold.clone_from(&state.pattern),
+            Command::HoldAppend => {
+                state.hold.push(state.separator);
+                state.hold.extend_from_slice(&state.pattern);
+            }
+            Command::Get => state.pattern.clone_from(&state.hold),
+            Command::GetAppend => {
+                state.pattern.push(state.separator);
+                state.pattern.extend_from_slice(&state.hold);
+            }
+            Command::Exchange => std::mem::swap(&mut state.pattern, &mut state.hold),
+            Command::ReadFile(path) => {
+                state.append_queue.push(Queued::File(path.clone()));
+            }
+            Command::WriteFile(path) => {
+                if let Some(file) = state.write_files.get_mut(path) {
+                    file.write_record(&state.pattern, state.terminated)?;
+                }
+            }
+            Command::Block(_) | Command::BlockEnd | Command::Label(_) => {}
+            Command::Branch(label) => return Ok(self.branch(label.as_deref())),
+            Command::BranchIfSubstituted(label) => {
+                if std::mem::take(&mut state.substituted) {
+                    return Ok(self.branch(label.as_deref()));
+                }
+            }
+            Command::BranchIfNotSubstituted(label) => {
+                if !std::mem::take(&mut state.substituted) {
+                    return Ok(self.branch(label.as_deref()));
+                }
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Advance the run by one step: start a cycle, check and run one
+    /// command, or end the cycle
+    fn step<R: BufRead, W: io::Write>(
+        &self,
+        state: &mut ExecState<R>,
+        writer: &mut Output<W>,
+    ) -> io::Result<Step> {
+        if let Some(program) = &self.structural {
+            return step_structural(program, state, writer);
+        }
+        let step = match std::mem::replace(&mut state.position, Position::Done) {
+            Position::Read => match state.read_line()? {
+                Some(line) => {
+                    state.pattern = line;
+                    state.position = Position::Command(0);
+                    Step::Cycle
                 }
+                None => Step::Finished(state.exit_code),
+            },
+            Position::Restart => {
+                state.position = Position::Command(0);
+                Step::Cycle
+            }
+            Position::Command(pc) if pc >= self.commands.len() => {
+                self.end_cycle(Flow::EndCycle, state, writer)?
             }
+            Position::Command(pc) => {
+                // An unselected block is skipped as a whole
+                if !self.selects(pc, state)? {
+                    state.position = Position::Command(match self.commands[pc].command {
+                        Command::Block(end) => end + 1,
+                        _ => pc + 1,
+                    });
+                    return Ok(Step::Skipped(pc));
+                }
+                state.position = match self.run_command(pc, state, writer)? {
+                    Flow::Continue => Position::Command(pc + 1),
+                    Flow::Jump(target) => Position::Command(target),
+                    flow => Position::EndCycle(flow),
+                };
+                Step::Ran(pc)
+            }
+            Position::EndCycle(flow) => self.end_cycle(flow, state, writer)?,
+            Position::Done => Step::Finished(state.exit_code),
+        };
+        Ok(step)
+    }
 
-            // Print line if not deleted and auto-print is on
-            if !deleted && print_line {
-                writeln!(writer, "{}", line)?;
+    /// Finish the cycle: auto-print and flush queued output as `flow` says
+    fn end_cycle<R: BufRead, W: io::Write>(
+        &self,
+        flow: Flow,
+        state: &mut ExecState<R>,
+        writer: &mut Output<W>,
+    ) -> io::Result<Step> {
+        state.position = Position::Read;
+        match flow {
+            Flow::Continue | Flow::Jump(_) | Flow::EndCycle => {
+                if !self.quiet {
+                    state.print_pattern(writer)?;
+                }
+                state.flush_appends(writer)?;
+            }
+            Flow::Delete => state.flush_appends(writer)?,
+            Flow::Restart => {
+                state.flush_appends(writer)?;
+                state.position = Position::Restart;
+            }
+            Flow::Quit {
+                autoprint,
+                exit_code,
+            } => {
+                if autoprint {
+                    if !self.quiet {
+                        state.print_pattern(writer)?;
+                    }
+                    state.flush_appends(writer)?;
+                }
+                state.exit_code = Some(exit_code);
+                state.position = Position::Done;
             }
         }
+        Ok(Step::EndCycle)
+    }
+
+    /// Flow for a taken branch: jump to the label, or end the cycle without one
+    fn branch(&self, label: Option<&str>) -> Flow {
+        match label.and_then(|name| self.labels.get(name)) {
+            Some(&target) => Flow::Jump(target),
+            None => Flow::EndCycle,
+        }
+    }
+}
 
+/// A sed run in progress, created by `SedConfig::start`
+pub struct SedRun<'a, R> {
+    config: &'a SedConfig,
+    state: ExecState<R>,
+}
+
+impl<R: BufRead> SedRun<'_, R> {
+    /// Process one stream made of `inputs` read back to back, writing to
+    /// `writer`. Line numbers and `$` are relative to this stream. Returns
+    /// the exit code given to `q` or `Q`, if the script quit.
+    pub fn process<W: io::Write>(&mut self, inputs: Vec<R>, writer: W) -> io::Result<Option<i32>> {
+        let config = self.config;
+        let state = &mut self.state;
+        state.inputs = inputs.into();
+        state.peeked = None;
+        state.line_num = 0;
+        state.terminated = true;
+        state.position = Position::Read;
+        state.exit_code = None;
+        let mut writer = Output::new(writer, state.separator);
+
+        let exit_code = loop {
+            if let Step::Finished(exit_code) = config.step(state, &mut writer)? {
+                break exit_code;
+            }
+        };
+
+        writer.flush()?;
+        Ok(exit_code)
+    }
+
+    /// Flush the `w` files
+    pub fn finish(mut self) -> io::Result<()> {
+        for file in self.state.write_files.values_mut() {
+            file.flush()?;
+        }
         Ok(())
     }
 }
 
-/// Check if an address matches the current line number
-fn matches_address(address: &Address, line_num: usize) -> bool {
-    match address {
+/// Run a structural expression over the whole stream in one cycle
+fn step_structural<R: BufRead, W: io::Write>(
+    program: &structural::Program,
+    state: &mut ExecState<R>,
+    writer: &mut Output<W>,
+) -> io::Result<Step> {
+    if let Position::Done = std::mem::replace(&mut state.position, Position::Done) {
+        return Ok(Step::Finished(None));
+    }
+    let mut text = Vec::new();
+    while let Some(mut input) = state.inputs.pop_front() {
+        input.read_to_end(&mut text)?;
+    }
+    let result = program
+        .apply(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    writer.write(&result)?;
+    Ok(Step::EndCycle)
+}
+
+/// Line width used by `l` before wrapping output
+const LIST_LINE_WIDTH: usize = 70;
+
+/// Render text in the unambiguous form used by `l`: C escapes, octal for other
+/// non-printable bytes, long lines wrapped with `\`, and `$` marking the end
+fn list_escape(text: &[u8], width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    for &byte in text {
+        let piece = match byte {
+            b'\\' => "\\\\".to_string(),
+            0x07 => "\\a".to_string(),
+            0x08 => "\\b".to_string(),
+            0x0c => "\\f".to_string(),
+            b'\n' => "\\n".to_string(),
+            b'\r' => "\\r".to_string(),
+            b'\t' => "\\t".to_string(),
+            0x0b => "\\v".to_string(),
+            0x20..=0x7e => (byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        };
+        if width > 1 && col + piece.len() > width - 1 {
+            out.push_str("\\\n");
+            col = 0;
+        }
+        col += piece.len();
+        out.push_str(&piece);
+    }
+    out.push_str("$\n");
+    out
+}
+
+/// Check if a stateless address matches the current line
+fn match_single<R: BufRead>(address: &Address, state: &mut ExecState<R>) -> io::Result<bool> {
+    let line_num = state.line_num;
+    Ok(match address {
         Address::All => true,
         Address::Line(n) => line_num == *n,
-        Address::Range(start, end) => line_num >= *start && line_num <= *end,
+        // An end before the start selects only the start line
+        Address::Range(start, end) => line_num == *start || (line_num > *start && line_num <= *end),
+        Address::Last => state.is_last_line()?,
+        Address::Regex(re) => state.use_regex(Some(re))?.is_match(&state.pattern),
+        Address::LastRegex => state.use_regex(None)?.is_match(&state.pattern),
+        Address::Field {
+            field,
+            separator,
+            regex,
+        } => state
+            .use_regex(Some(regex))?
+            .is_match(field.get(&state.pattern, separator)),
+        Address::Step(first, step) => {
+            if *step == 0 {
+                line_num == *first
+            } else {
+                line_num >= *first && (line_num - first).is_multiple_of(*step)
+            }
+        }
+        // Spans are stateful and handled by `match_range`
+        Address::Span(..) => false,
+    })
+}
+
+/// Check if a stateful range matches the current line, opening or closing it
+fn match_range<R: BufRead>(
+    index: usize,
+    start: &Address,
+    end: &RangeEnd,
+    state: &mut ExecState<R>,
+) -> io::Result<bool> {
+    let line_num = state.line_num;
+
+    if let RangeState::Active { end_line } = state.ranges[index] {
+        let closes = match (end_line, end) {
+            (Some(last), _) => {
+                if line_num > last {
+                    // Input skipped past the end (e.g. with `N`); the range is over
+                    state.ranges[index] = RangeState::Inactive;
+                    return Ok(false);
+                }
+                line_num == last
+            }
+            (None, RangeEnd::Multiple(n)) => line_num.is_multiple_of(*n),
+            (None, RangeEnd::Address(address)) => match_single(address, state)?,
+            (None, RangeEnd::Relative(_)) => true,
+        };
+        if closes {
+            state.ranges[index] = RangeState::Inactive;
+        }
+        return Ok(true);
+    }
+
+    if !match_single(start, state)? {
+        return Ok(false);
+    }
+
+    // The start matched: work out whether the range extends past this line.
+    // A regex end is only checked from the next line on.
+    let next_state = match end {
+        RangeEnd::Address(address) => match address.as_ref() {
+            Address::Line(n) if *n > line_num => RangeState::Active { end_line: Some(*n) },
+            Address::Line(_) => RangeState::Inactive,
+            Address::Last if state.is_last_line()? => RangeState::Inactive,
+            _ => RangeState::Active { end_line: None },
+        },
+        RangeEnd::Relative(0) => RangeState::Inactive,
+        RangeEnd::Relative(n) => RangeState::Active {
+            end_line: Some(line_num + n),
+        },
+        RangeEnd::Multiple(n) if *n == 0 || line_num.is_multiple_of(*n) => RangeState::Inactive,
+        RangeEnd::Multiple(_) => RangeState::Active { end_line: None },
+    };
+    state.ranges[index] = next_state;
+    Ok(true)
+}
+
+/// Position of the first `byte` in `text`
+fn find_byte(text: &[u8], byte: u8) -> Option<usize> {
+    text.iter().position(|&b| b == byte)
+}
+
+/// Apply a `y` mapping to the valid UTF-8 in `text`, leaving other bytes alone
+fn transliterate(text: &[u8], map: &HashMap<char, char>) -> Vec<u8> {
+    let mut result = Vec::with_capacity(text.len());
+    for chunk in text.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let mapped = map.get(&c).copied().unwrap_or(c);
+            result.extend_from_slice(mapped.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        result.extend_from_slice(chunk.invalid());
+    }
+    result
+}
+
+/// Replace the `occurrence`-th match of `pattern` in `text` (and every later
+/// one when `global` is set), or `None` if there is no such match
+fn substitute(
+    pattern: &Regex,
+    replacement: &Replacement,
+    text: &[u8],
+    occurrence: usize,
+    global: bool,
+) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(text.len());
+    let mut last_end = 0;
+    let mut replaced = false;
+
+    for caps in pattern.captures_iter(text).skip(occurrence - 1) {
+        let whole = caps.get(0)?;
+        result.extend_from_slice(&text[last_end..whole.start()]);
+        replacement.expand(&caps, text, &mut result);
+        last_end = whole.end();
+        replaced = true;
+        if !global {
+            break;
+        }
+    }
+
+    if !replaced {
+        return None;
+    }
+    result.extend_from_slice(&text[last_end..]);
+    Some(result)
+}
+
+/// Run `command` with the shell and return its output without the trailing
+/// newline (`e` flag of `s`)
+fn run_shell_command(command: &[u8]) -> io::Result<Vec<u8>> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(String::from_utf8_lossy(command).as_ref())
+        .output()?;
+    let mut text = output.stdout;
+    if text.last() == Some(&b'\n') {
+        text.pop();
+    }
+    Ok(text)
+}
+
+/// Parse one command (with its address) from the start of `text`,
+/// returning it and the remaining script
+fn parse_command<'a>(
+    text: &'a str,
+    syntax: RegexSyntax,
+    fields: Option<&FieldSeparator>,
+) -> Result<(SedCommand, &'a str), String> {
+    // Try to parse address prefix
+    let (address, cmd_part) = parse_address(text, syntax, fields)?;
+    let cmd_part = cmd_part.trim_start_matches([' ', '\t']);
+    let (negated, cmd_part) = match cmd_part.strip_prefix('!') {
+        Some(rest) => (true, rest.trim_start_matches([' ', '\t'])),
+        None => (false, cmd_part),
+    };
+
+    let mut chars = cmd_part.chars();
+    let name = match chars.next() {
+        Some(c) if c != '\n' && c != ';' => c,
+        _ => return Err("Missing command".to_string()),
+    };
+    let args = chars.as_str();
+    let has_address = address != Address::All || negated;
+
+    // Parse the command
+    let (command, rest) = match name {
+        '{' => (Command::Block(0), args),
+        '}' | ':' if has_address => {
+            return Err(format!("'{}' doesn't want any addresses", name));
+        }
+        '}' => (Command::BlockEnd, args),
+        ':' => {
+            let (label, rest) = parse_label(args);
+            if label.is_empty() {
+                return Err("':' lacks a label".to_string());
+            }
+            (Command::Label(label.to_string()), rest)
+        }
+        'b' | 't' | 'T' => {
+            let (label, rest) = parse_label(args);
+            let label = (!label.is_empty()).then(|| label.to_string());
+            let command = match name {
+                'b' => Command::Branch(label),
+                't' => Command::BranchIfSubstituted(label),
+                _ => Command::BranchIfNotSubstituted(label),
+            };
+            (command, rest)
+        }
+        's' => parse_substitute(args, syntax, fields)?,
+        'y' => parse_transliterate(args)?,
+        'a' | 'i' | 'c' => {
+            let (text, rest) = parse_text_argument(args);
+            let command = match name {
+                'a' => Command::Append(text),
+                'i' => Command::Insert(text),
+                _ => Command::Change(text),
+            };
+            (command, rest)
+        }
+        'r' | 'w' => {
+            let (path, rest) = parse_filename(name, args)?;
+            let command = match name {
+                'r' => Command::ReadFile(path),
+                _ => Command::WriteFile(path),
+            };
+            (command, rest)
+        }
+        'd' => (Command::Delete, args),
+        'D' => (Command::DeleteFirstLine, args),
+        'p' => (Command::Print, args),
+        'P' => (Command::PrintFirstLine, args),
+        '=' => (Command::LineNumber, args),
+        'l' => (Command::List, args),
+        'q' | 'Q' => {
+            let args = args.trim_start_matches([' ', '\t']);
+            let (exit_code, rest) = match parse_number(args) {
+                Some((code, rest)) => {
+                    let code = i32::try_from(code).map_err(|_| "Exit code out of range")?;
+                    (code, rest)
+                }
+                None => (0, args),
+            };
+            let command = match name {
+                'q' => Command::Quit(exit_code),
+                _ => Command::QuitSilent(exit_code),
+            };
+            (command, rest)
+        }
+        'n' => (Command::Next, args),
+        'N' => (Command::NextAppend, args),
+        'h' => (Command::Hold, args),
+        'H' => (Command::HoldAppend, args),
+        'g' => (Command::Get, args),
+        'G' => (Command::GetAppend, args),
+        'x' => (Command::Exchange, args),
+        _ => return Err(format!("Unknown command: '{}'", name)),
+    };
+
+    // Only a separator, a comment or a block end may follow a command;
+    // a `{` may be followed directly by the first command of the block
+    let rest = rest.trim_start_matches([' ', '\t']);
+    let terminated = rest.is_empty() || rest.starts_with([';', '\n', '}', '#']);
+    if !terminated && !matches!(command, Command::Block(_)) {
+        return Err(format!("Extra characters after command: {}", rest));
+    }
+
+    let source = text[..text.len() - rest.len()].trim_end().to_string();
+    Ok((
+        SedCommand {
+            address,
+            negated,
+            command,
+            source,
+        },
+        rest,
+    ))
+}
+
+/// Reject the GNU extensions in a parsed command (`--posix`)
+fn check_posix(sed_cmd: &SedCommand) -> Result<(), String> {
+    let extension = match (&sed_cmd.address, &sed_cmd.command) {
+        (Address::Step(..), _) => "The 'first~step' address",
+        (Address::Span(start, _), _) if **start == Address::Line(0) => "The '0,/re/' address",
+        (Address::Span(_, RangeEnd::Relative(_)), _) => "The 'addr,+N' address",
+        (Address::Span(_, RangeEnd::Multiple(_)), _) => "The 'addr,~N' address",
+        (_, Command::BranchIfNotSubstituted(_)) => "The 'T' command",
+        (_, Command::QuitSilent(_)) => "The 'Q' command",
+        (_, Command::Quit(code)) if *code != 0 => "An exit code for 'q'",
+        (_, Command::Substitute { execute: true, .. }) => "The 'e' option to 's'",
+        _ => return Ok(()),
+    };
+    Err(format!(
+        "{} is a GNU extension (disabled by --posix)",
+        extension
+    ))
+}
+
+/// Parse a label for `:`, `b`, `t` and `T`, which ends at whitespace, `;` or `}`
+fn parse_label(args: &str) -> (&str, &str) {
+    let args = args.trim_start_matches([' ', '\t']);
+    let end = args
+        .find(|c: char| c.is_whitespace() || c == ';' || c == '}')
+        .unwrap_or(args.len());
+    (&args[..end], &args[end..])
+}
+
+/// Parse the text argument of `a`, `i` and `c`.
+///
+/// Accepts both the POSIX form (`a\` followed by the text on the next line)
+/// and the GNU one-liner form (`a text`). A backslash escapes the next
+/// character, and a backslash before a newline continues the text onto the
+/// next line. An unescaped newline ends the text.
+fn parse_text_argument(args: &str) -> (String, &str) {
+    let mut text = args.trim_start_matches([' ', '\t']);
+    if let Some(stripped) = text.strip_prefix('\\') {
+        text = stripped.strip_prefix('\n').unwrap_or(stripped);
+    }
+
+    let mut out = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, next)) = chars.next() {
+                    out.push(next);
+                }
+            }
+            '\n' => return (out, &text[i..]),
+            _ => out.push(c),
+        }
+    }
+    (out, "")
+}
+
+/// Parse the filename argument of `r` and `w`, which runs to the end of the line
+fn parse_filename(name: char, args: &str) -> Result<(PathBuf, &str), String> {
+    let args = args.trim_start_matches([' ', '\t']);
+    let end = args.find('\n').unwrap_or(args.len());
+    let filename = &args[..end];
+    if filename.is_empty() {
+        return Err(format!("Missing filename for '{}' command", name));
+    }
+    Ok((PathBuf::from(filename), &args[end..]))
+}
+
+/// Split off the text up to the next unescaped `delimiter`.
+///
+/// Returns the raw text (escapes left in place) and the remainder after the
+/// delimiter.
+fn split_delimited(text: &str, delimiter: char) -> Result<(&str, &str), String> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            return Ok((&text[..i], &text[i + c.len_utf8()..]));
+        }
     }
+    Err(format!("Unterminated expression: missing '{}'", delimiter))
 }
 
-/// Parse a sed command string
-pub fn parse_sed_command(script: &str) -> Result<SedCommand, String> {
-    let script = script.trim();
+/// Split off a regex up to the next unescaped `delimiter`.
+///
+/// Unlike `split_delimited`, a delimiter inside a bracket expression such as
+/// `[/]` does not end the regex, and `\<delimiter>` becomes a literal
+/// delimiter in the returned pattern.
+fn split_regex(text: &str, delimiter: char, syntax: RegexSyntax) -> Result<(String, &str), String> {
+    let mut pattern = String::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return Ok((pattern, &text[i + c.len_utf8()..]));
+        }
+        match c {
+            '\\' => match chars.next() {
+                Some((_, next)) if next == delimiter && next != 'n' => {
+                    pattern.push_str(&posix::escape_literal(next, syntax));
+                }
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                }
+                None => pattern.push('\\'),
+            },
+            '[' => {
+                // Copy the whole bracket expression; a `]` right after `[` or
+                // `[^` is a literal member, as are `[:class:]` and friends
+                pattern.push('[');
+                if let Some((_, '^')) = chars.peek() {
+                    pattern.push('^');
+                    chars.next();
+                }
+                if let Some((_, ']')) = chars.peek() {
+                    pattern.push(']');
+                    chars.next();
+                }
+                loop {
+                    let Some((_, c)) = chars.next() else {
+                        return Err("Unterminated bracket expression".to_string());
+                    };
+                    pattern.push(c);
+                    match c {
+                        ']' => break,
+                        '\\' => match chars.next() {
+                            // Bracket contents are literal, so drop the escape
+                            Some((_, next)) if next == delimiter => {
+                                pattern.pop();
+                                pattern.push(next);
+                            }
+                            Some((_, next)) => pattern.push(next),
+                            None => {}
+                        },
+                        '[' => {
+                            let Some(&(_, kind @ (':' | '.' | '='))) = chars.peek() else {
+                                continue;
+                            };
+                            pattern.push(kind);
+                            chars.next();
+                            let mut prev = kind;
+                            // Copy up to and including the closing `:]`
+                            for (_, c) in chars.by_ref() {
+                                pattern.push(c);
+                                if c == ']' && prev == kind {
+                                    break;
+                                }
+                                prev = c;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => pattern.push(c),
+        }
+    }
+    Err(format!("Unterminated expression: missing '{}'", delimiter))
+}
+
+/// Parse transliterate command: y/source/dest/
+fn parse_transliterate(args: &str) -> Result<(Command, &str), String> {
+    let mut chars = args.chars();
+    let delimiter = chars
+        .next()
+        .ok_or("Invalid transliterate format: expected y/source/dest/")?;
+    if delimiter == '\\' || delimiter == '\n' {
+        return Err("Invalid delimiter for 'y' command".to_string());
+    }
+
+    let (source, after) = split_delimited(chars.as_str(), delimiter)?;
+    let (dest, rest) = split_delimited(after, delimiter)?;
+
+    let unescape = |s: &str| -> Vec<char> {
+        let mut out = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some('n') => out.push('\n'),
+                    Some(other) => out.push(other),
+                    None => out.push('\\'),
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    };
+
+    let source = unescape(source);
+    let dest = unescape(dest);
+    if source.len() != dest.len() {
+        return Err("Strings for 'y' command are different lengths".to_string());
+    }
+
+    Ok((
+        Command::Transliterate(source.into_iter().zip(dest).collect()),
+        rest,
+    ))
+}
 
-    // Try to parse address prefix
-    let (address, cmd_part) = parse_address(script)?;
+/// Parse address portion of a sed command
+fn parse_address<'a>(
+    script: &'a str,
+    syntax: RegexSyntax,
+    fields: Option<&FieldSeparator>,
+) -> Result<(Address, &'a str), String> {
+    let (start, rest) = match parse_single_address(script, syntax, fields)? {
+        Some(parsed) => parsed,
+        // No address specified - applies to all lines
+        None => return Ok((Address::All, script)),
+    };
 
-    // Parse the command
-    let command = if cmd_part.starts_with('s') {
-        parse_substitute(cmd_part)?
-    } else if cmd_part == "d" {
-        Command::Delete
-    } else if cmd_part == "p" {
-        Command::Print
-    } else {
-        return Err(format!("Unknown command: {}", cmd_part));
+    let rest_trimmed = rest.trim_start();
+    let after_comma = match rest_trimmed.strip_prefix(',') {
+        Some(after) => after.trim_start(),
+        None => {
+            if start == Address::Line(0) {
+                return Err("Invalid usage of line address 0".to_string());
+            }
+            return Ok((start, rest));
+        }
     };
 
-    Ok(SedCommand { address, command })
-}
+    // GNU extensions: `addr1,+N` and `addr1,~N`
+    if let Some(after) = after_comma.strip_prefix(['+', '~']) {
+        let (n, cmd_part) = parse_number(after).ok_or("Expected number after '+' or '~'")?;
+        if start == Address::Line(0) {
+            return Err("Invalid usage of line address 0".to_string());
+        }
+        let end = if after_comma.starts_with('+') {
+            RangeEnd::Relative(n)
+        } else {
+            RangeEnd::Multiple(n)
+        };
+        return Ok((Address::Span(Box::new(start), end), cmd_part));
+    }
 
-/// Parse address portion of a sed command
-fn parse_address(script: &str) -> Result<(Address, &str), String> {
-    // Check for range: "1,10s/foo/bar/"
-    if let Some(comma_pos) = script.find(',') {
-        let start_str = &script[..comma_pos];
-        let rest = &script[comma_pos + 1..];
+    let (end, cmd_part) = match parse_single_address(after_comma, syntax, fields)? {
+        Some((Address::Step(..), _)) => {
+            return Err("'first~step' cannot end an address range".to_string());
+        }
+        Some(parsed) => parsed,
+        None => return Err("Unexpected ',': missing range end".to_string()),
+    };
 
-        // Find where the command starts (first non-digit character)
-        let end_pos = rest
-            .chars()
-            .position(|c| !c.is_ascii_digit())
-            .ok_or("Invalid range format")?;
+    // `0,/re/` lets the end regex match on the very first line
+    if start == Address::Line(0) && !matches!(end, Address::Regex(_) | Address::LastRegex) {
+        return Err("Invalid usage of line address 0".to_string());
+    }
 
-        let end_str = &rest[..end_pos];
-        let cmd_part = &rest[end_pos..];
+    let address = match (&start, &end) {
+        (Address::Line(first), Address::Line(last)) if *first > 0 => {
+            if *last == 0 {
+                return Err("Line numbers must be >= 1".to_string());
+            }
+            Address::Range(*first, *last)
+        }
+        _ => Address::Span(Box::new(start), RangeEnd::Address(Box::new(end))),
+    };
+    Ok((address, cmd_part))
+}
 
-        let start: usize = start_str.parse().map_err(|_| "Invalid start line number")?;
-        let end: usize = end_str.parse().map_err(|_| "Invalid end line number")?;
+/// Parse one address (`N`, `first~step`, `$`, `/re/`, `\cREc`) from the
+/// start of `text`, returning it and the remaining text
+fn parse_single_address<'a>(
+    mut text: &'a str,
+    syntax: RegexSyntax,
+    fields: Option<&FieldSeparator>,
+) -> Result<Option<(Address, &'a str)>, String> {
+    // `$N/re/` in field mode; a plain `$` is still the last line
+    let mut field = None;
+    if let Some(separator) = fields
+        && let Some((field_ref, rest)) = text.strip_prefix('$').and_then(FieldRef::parse)
+    {
+        if !rest.starts_with(['/', '\\']) {
+            return Err(format!(
+                "Expected /re/ after field reference {}",
+                &text[..text.len() - rest.len()]
+            ));
+        }
+        field = Some((field_ref, separator));
+        text = rest;
+    }
 
-        if start == 0 || end == 0 {
-            return Err("Line numbers must be >= 1".to_string());
+    if let Some((n, rest)) = parse_number(text) {
+        if let Some(after) = rest.strip_prefix('~') {
+            let (step, rest) = parse_number(after).ok_or("Expected step after '~'")?;
+            return Ok(Some((Address::Step(n, step), rest)));
         }
+        return Ok(Some((Address::Line(n), rest)));
+    }
 
-        return Ok((Address::Range(start, end), cmd_part));
+    if let Some(rest) = text.strip_prefix('$') {
+        return Ok(Some((Address::Last, rest)));
     }
 
-    // Check for single line: "2d"
-    let first_non_digit = script.chars().position(|c| !c.is_ascii_digit());
+    let (delimiter, body) = if let Some(body) = text.strip_prefix('/') {
+        ('/', body)
+    } else if let Some(body) = text.strip_prefix('\\') {
+        let mut chars = body.chars();
+        let delimiter = chars.next().ok_or("Expected delimiter after '\\'")?;
+        if delimiter == '\\' || delimiter == '\n' {
+            return Err("Invalid regex address delimiter".to_string());
+        }
+        (delimiter, chars.as_str())
+    } else {
+        return Ok(None);
+    };
 
-    if let Some(pos) = first_non_digit {
-        if pos > 0 {
-            let line_str = &script[..pos];
-            let cmd_part = &script[pos..];
-            let line_num: usize = line_str.parse().map_err(|_| "Invalid line number")?;
+    let (pattern, mut rest) = split_regex(body, delimiter, syntax)?;
 
-            if line_num == 0 {
-                return Err("Line numbers must be >= 1".to_string());
-            }
+    // GNU flags: `I` for case-insensitive, `M` for multi-line matching
+    let mut case_insensitive = false;
+    let mut multi_line = false;
+    loop {
+        if let Some(after) = rest.strip_prefix('I') {
+            case_insensitive = true;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('M') {
+            multi_line = true;
+            rest = after;
+        } else {
+            break;
+        }
+    }
 
-            return Ok((Address::Line(line_num), cmd_part));
+    // An empty regex is the last regex used
+    if pattern.is_empty() {
+        if case_insensitive || multi_line {
+            return Err(EMPTY_REGEX_MODIFIERS.to_string());
+        }
+        if field.is_some() {
+            return Err("A field address needs a regex".to_string());
         }
+        return Ok(Some((Address::LastRegex, rest)));
     }
 
-    // No address specified - applies to all lines
-    Ok((Address::All, script))
+    let regex = compile_regex(&pattern, syntax, case_insensitive, multi_line)
+        .map_err(|e| format!("Invalid regex address: {}", e))?;
+    let address = match field {
+        Some((field, separator)) => Address::Field {
+            field,
+            separator: separator.clone(),
+            regex,
+        },
+        None => Address::Regex(regex),
+    };
+    Ok(Some((address, rest)))
 }
 
-/// Parse substitute command: s/pattern/replacement/[flags]
-fn parse_substitute(cmd: &str) -> Result<Command, String> {
-    if !cmd.starts_with('s') {
-        return Err("Not a substitute command".to_string());
-    }
+/// Error for `I` or `M` on an empty regex, whose flags come from the regex
+/// it reuses
+const EMPTY_REGEX_MODIFIERS: &str = "Cannot specify modifiers on an empty regex";
 
-    // Find delimiter (typically '/')
-    let delimiter = cmd
-        .chars()
-        .nth(1)
-        .ok_or_else(|| "Invalid substitute format: expected s/pattern/replacement/".to_string())?;
+/// Parse a decimal number from the start of `text`
+fn parse_number(text: &str) -> Option<(usize, &str)> {
+    let end = text
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_digit())
+        .map_or(text.len(), |(i, _)| i);
+    let n = text[..end].parse().ok()?;
+    Some((n, &text[end..]))
+}
 
-    let parts: Vec<&str> = cmd[2..].split(delimiter).collect();
+/// Compile a sed regex written in `syntax`.
+///
+/// As in POSIX sed, `.` also matches the newlines that `N` and `G` put into
+/// the pattern space.
+fn compile_regex(
+    pattern: &str,
+    syntax: RegexSyntax,
+    case_insensitive: bool,
+    multi_line: bool,
+) -> Result<Regex, String> {
+    regex::bytes::RegexBuilder::new(&posix::translate(pattern, syntax)?)
+        .case_insensitive(case_insensitive)
+        .multi_line(multi_line)
+        .dot_matches_new_line(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
 
-    if parts.len() < 2 {
-        return Err("Invalid substitute format: expected s/pattern/replacement/".to_string());
-    }
+/// Parse substitute command: s/pattern/replacement/[flags]
+///
+/// `args` starts at the delimiter, just after the `s`.
+fn parse_substitute<'a>(
+    args: &'a str,
+    syntax: RegexSyntax,
+    fields: Option<&FieldSeparator>,
+) -> Result<(Command, &'a str), String> {
+    // Find delimiter (typically '/')
+    let mut chars = args.chars();
+    let delimiter = match chars.next() {
+        Some(c) if c != '\\' && c != '\n' => c,
+        _ => {
+            return Err("Invalid substitute format: expected s/pattern/replacement/".to_string());
+        }
+    };
 
-    let pattern_str = parts[0];
-    let replacement = parts[1];
-    let flags = if parts.len() > 2 { parts[2] } else { "" };
+    let (pattern_str, after) = split_regex(chars.as_str(), delimiter, syntax)
+        .map_err(|_| "Invalid substitute format: expected s/pattern/replacement/".to_string())?;
+    let (replacement, mut rest) = split_delimited(after, delimiter)
+        .map_err(|_| "Invalid substitute format: expected s/pattern/replacement/".to_string())?;
 
     // Parse flags
-    let global = flags.contains('g');
-    let print = flags.contains('p');
-
-    // Validate pattern string (basic validation)
-    if pattern_str.is_empty() {
-        return Err("Pattern cannot be empty".to_string());
+    let mut occurrence = None;
+    let mut global = false;
+    let mut print = false;
+    let mut case_insensitive = false;
+    let mut multi_line = false;
+    let mut execute = false;
+    let mut write = None;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '0'..='9' => {
+                if occurrence.is_some() {
+                    return Err("Multiple number options to 's' command".to_string());
+                }
+                let (n, after) = parse_number(rest).ok_or("Invalid number option to 's'")?;
+                if n == 0 {
+                    return Err("Number option to 's' command may not be zero".to_string());
+                }
+                occurrence = Some(n);
+                rest = after;
+                continue;
+            }
+            'g' if global => return Err("Multiple 'g' options to 's' command".to_string()),
+            'p' if print => return Err("Multiple 'p' options to 's' command".to_string()),
+            'g' => global = true,
+            'p' => print = true,
+            'i' | 'I' => case_insensitive = true,
+            'm' | 'M' => multi_line = true,
+            'e' => execute = true,
+            'w' => {
+                // The filename runs to the end of the line, so `w` comes last
+                let (path, after) = parse_filename('w', &rest[1..])?;
+                write = Some(path);
+                rest = after;
+                break;
+            }
+            ';' | '\n' | '}' | '#' | ' ' | '\t' => break,
+            _ => return Err(format!("Unknown option to 's': {}", c)),
+        }
+        rest = &rest[c.len_utf8()..];
     }
 
-    // Compile regex pattern
-    let regex = Regex::new(pattern_str).map_err(|e| format!("Invalid regex pattern: {}", e))?;
+    // An empty pattern is the last regex used, so back-references can only
+    // be checked against a pattern given here
+    let replacement = Replacement::parse(replacement, delimiter, fields);
+    let regex = if pattern_str.is_empty() {
+        if case_insensitive || multi_line {
+            return Err(EMPTY_REGEX_MODIFIERS.to_string());
+        }
+        None
+    } else {
+        let regex = compile_regex(&pattern_str, syntax, case_insensitive, multi_line)
+            .map_err(|e| format!("Invalid regex pattern: {}", e))?;
+        if replacement.max_group() >= regex.captures_len() {
+            return Err(format!(
+                "Invalid reference \\{} on 's' command's RHS",
+                replacement.max_group()
+            ));
+        }
+        Some(regex)
+    };
 
-    Ok(Command::Substitute {
-        pattern: regex,
-        replacement: replacement.to_string(),
-        global,
-        print,
-    })
+    Ok((
+        Command::Substitute {
+            pattern: regex,
+            replacement,
+            occurrence: occurrence.unwrap_or(1),
+            global,
+            print,
+            write,
+            execute,
+        },
+        rest,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Parse a script that must hold exactly one command
+    fn parse_sed_command(script: &str) -> Result<SedCommand, String> {
+        let mut config = SedConfig::new();
+        config.add_script(script)?;
+        match config.commands.pop() {
+            Some(cmd) if config.commands.is_empty() => Ok(cmd),
+            _ => Err("Expected exactly one command".to_string()),
+        }
+    }
+
+    fn run_sed(scripts: &[&str], quiet: bool, input: &str) -> String {
+        let mut config = SedConfig::new();
+        config.quiet = quiet;
+        for script in scripts {
+            if let Err(e) = config.add_script(script) {
+                panic!("Failed to add script {:?}: {}", script, e);
+            }
+        }
+        let mut output = Vec::new();
+        if config.execute(input.as_bytes(), &mut output).is_err() {
+            panic!("Failed to execute");
+        }
+        match String::from_utf8(output) {
+            Ok(result) => result,
+            Err(_) => panic!("Invalid UTF-8"),
+        }
+    }
+
     #[test]
     fn test_parse_substitute_basic() {
         if let Ok(cmd) = parse_sed_command("s/foo/bar/") {
@@ -370,4 +1864,528 @@ mod tests {
             panic!("Invalid UTF-8");
         }
     }
+
+    #[test]
+    fn test_append_insert_change() {
+        assert_eq!(
+            run_sed(&["2a after"], false, "a\nb\nc\n"),
+            "a\nb\nafter\nc\n"
+        );
+        assert_eq!(
+            run_sed(&["1i\\\n  before"], false, "a\nb\n"),
+            "  before\na\nb\n"
+        );
+        assert_eq!(
+            run_sed(&["2c changed"], false, "a\nb\nc\n"),
+            "a\nchanged\nc\n"
+        );
+        assert_eq!(run_sed(&["1,2c one"], false, "a\nb\nc\n"), "one\nc\n");
+    }
+
+    #[test]
+    fn test_transliterate() {
+        assert_eq!(run_sed(&["y/abc/xyz/"], false, "aabbcc\n"), "xxyyzz\n");
+        assert!(parse_sed_command("y/ab/x/").is_err());
+    }
+
+    #[test]
+    fn test_line_number_and_quit() {
+        assert_eq!(run_sed(&["="], false, "a\nb\n"), "1\na\n2\nb\n");
+        assert_eq!(run_sed(&["2q"], false, "a\nb\nc\n"), "a\nb\n");
+        assert_eq!(run_sed(&["2Q"], false, "a\nb\nc\n"), "a\n");
+    }
+
+    #[test]
+    fn test_next_commands() {
+        // Print every second line
+        assert_eq!(run_sed(&["n", "d"], false, "1\n2\n3\n4\n"), "1\n3\n");
+        // Join pairs of lines
+        assert_eq!(run_sed(&["N", "s/\n/ /"], false, "a\nb\nc\n"), "a b\nc\n");
+    }
+
+    #[test]
+    fn test_print_and_delete_first_line() {
+        // Classic sliding window: print all but the last line
+        assert_eq!(run_sed(&["N", "P", "D"], true, "1\n2\n3\n"), "1\n2\n");
+    }
+
+    #[test]
+    fn test_hold_space() {
+        let result = run_sed(&["1h", "1d", "G"], false, "a\nb\n");
+        assert_eq!(result, "b\na\n");
+        let result = run_sed(&["H", "2g"], false, "a\nb\n");
+        assert_eq!(result, "a\n\na\nb\n");
+    }
+
+    #[test]
+    fn test_exchange() {
+        assert_eq!(run_sed(&["x"], false, "a\nb\n"), "\na\n");
+    }
+
+    #[test]
+    fn test_list() {
+        assert_eq!(run_sed(&["l"], true, "a\tb\\\n"), "a\\tb\\\\$\n");
+        assert_eq!(list_escape(b"abcdef", 4), "abc\\\ndef$\n");
+    }
+
+    #[test]
+    fn test_read_and_write_files() {
+        let dir = std::env::temp_dir();
+        let read_path = dir.join("erax_sed_test_read.txt");
+        let write_path = dir.join("erax_sed_test_write.txt");
+        if std::fs::write(&read_path, "inserted\n").is_err() {
+            panic!("Failed to create test file");
+        }
+
+        let read_script = format!("1r {}", read_path.display());
+        let write_script = format!("2w {}", write_path.display());
+        let result = run_sed(&[&read_script, &write_script], false, "a\nb\n");
+        let written = std::fs::read_to_string(&write_path);
+
+        let _ = std::fs::remove_file(&read_path);
+        let _ = std::fs::remove_file(&write_path);
+
+        assert_eq!(result, "a\ninserted\nb\n");
+        assert_eq!(written.ok().as_deref(), Some("b\n"));
+    }
+
+    #[test]
+    fn test_parse_regex_and_last_addresses() {
+        if let Ok(cmd) = parse_sed_command("/foo/d") {
+            assert!(matches!(cmd.address, Address::Regex(ref re) if re.as_str() == "foo"));
+        } else {
+            panic!("Expected valid parse");
+        }
+        if let Ok(cmd) = parse_sed_command("$!N") {
+            assert_eq!(cmd.address, Address::Last);
+            assert!(cmd.negated);
+            assert!(matches!(cmd.command, Command::NextAppend));
+        } else {
+            panic!("Expected valid parse");
+        }
+        if let Ok(cmd) = parse_sed_command("\\,a/b,p") {
+            assert!(matches!(cmd.address, Address::Regex(ref re) if re.as_str() == "a/b"));
+        } else {
+            panic!("Expected valid parse");
+        }
+    }
+
+    #[test]
+    fn test_parse_range_forms() {
+        if let Ok(cmd) = parse_sed_command("10,$d") {
+            assert_eq!(
+                cmd.address,
+                Address::Span(
+                    Box::new(Address::Line(10)),
+                    RangeEnd::Address(Box::new(Address::Last))
+                )
+            );
+        } else {
+            panic!("Expected valid parse");
+        }
+        if let Ok(cmd) = parse_sed_command("2,+3d") {
+            assert_eq!(
+                cmd.address,
+                Address::Span(Box::new(Address::Line(2)), RangeEnd::Relative(3))
+            );
+        } else {
+            panic!("Expected valid parse");
+        }
+        if let Ok(cmd) = parse_sed_command("0~3d") {
+            assert_eq!(cmd.address, Address::Step(0, 3));
+        } else {
+            panic!("Expected valid parse");
+        }
+        assert!(parse_sed_command("0p").is_err());
+        assert!(parse_sed_command("0,5p").is_err());
+        assert!(parse_sed_command("0,/x/p").is_ok());
+    }
+
+    #[test]
+    fn test_regex_range_delete() {
+        let input = "keep\nSTART\ndrop\nEND\nkeep\nSTART\ndrop\n";
+        assert_eq!(run_sed(&["/START/,/END/d"], false, input), "keep\nkeep\n");
+    }
+
+    #[test]
+    fn test_regex_range_end_not_checked_on_start_line() {
+        // The end regex also matches the start line, so the range continues
+        let input = "x\na\nx\nb\n";
+        assert_eq!(run_sed(&["/x/,/x/d"], false, input), "b\n");
+        // `0,/re/` lets the end match on the first line
+        assert_eq!(run_sed(&["0,/x/d"], false, input), "a\nx\nb\n");
+    }
+
+    #[test]
+    fn test_last_line_and_negation() {
+        assert_eq!(run_sed(&["$d"], false, "a\nb\nc\n"), "a\nb\n");
+        assert_eq!(run_sed(&["2!d"], false, "a\nb\nc\n"), "b\n");
+        assert_eq!(run_sed(&["2,$!d"], false, "a\nb\nc\n"), "b\nc\n");
+        assert_eq!(run_sed(&["/b/!s/$/!/"], false, "a\nb\n"), "a!\nb\n");
+    }
+
+    #[test]
+    fn test_empty_regex_reuses_last_regex() {
+        let input = "foo bar\nbaz\n";
+        assert_eq!(run_sed(&["/foo/s//x/"], false, input), "x bar\nbaz\n");
+        assert_eq!(run_sed(&["s/a/b/;s//c/"], false, "banana\n"), "bbncna\n");
+        assert_eq!(run_sed(&[r"s/\(b\)a/\1/;//d"], false, input), "foo br\nbz\n");
+        assert_eq!(run_sed(&["/baz/h;//!d"], false, input), "baz\n");
+        assert!(parse_sed_command("//Id").is_err());
+        assert!(parse_sed_command("s//x/i").is_err());
+
+        // Nothing to reuse yet
+        let mut config = SedConfig::new();
+        if let Err(e) = config.add_script("//d") {
+            panic!("Failed to add script: {}", e);
+        }
+        assert!(config.execute(&b"a\n"[..], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_step_and_relative_addresses() {
+        let input = "1\n2\n3\n4\n5\n6\n";
+        assert_eq!(run_sed(&["0~2d"], false, input), "1\n3\n5\n");
+        assert_eq!(run_sed(&["1~3d"], false, input), "2\n3\n5\n6\n");
+        assert_eq!(run_sed(&["/2/,+2d"], false, input), "1\n5\n6\n");
+        assert_eq!(run_sed(&["2,~4d"], false, input), "1\n5\n6\n");
+        // A numeric end before the start selects only the start line
+        assert_eq!(run_sed(&["4,2d"], false, input), "1\n2\n3\n5\n6\n");
+    }
+
+    #[test]
+    fn test_tac_with_hold_space() {
+        let result = run_sed(&["1!G", "h", "$!d"], false, "1\n2\n3\n");
+        assert_eq!(result, "3\n2\n1\n");
+    }
+
+    #[test]
+    fn test_change_with_regex_range() {
+        let input = "a\n<\nb\n>\nc\n";
+        assert_eq!(run_sed(&["/</,/>/c gone"], false, input), "a\ngone\nc\n");
+    }
+
+    #[test]
+    fn test_multiple_commands_in_one_script() {
+        assert_eq!(run_sed(&["s/a/b/;s/b/c/"], false, "a\n"), "c\n");
+        assert_eq!(run_sed(&["1d\n3d"], false, "1\n2\n3\n"), "2\n");
+        assert_eq!(run_sed(&["2{p;p}"], true, "a\nb\n"), "b\nb\n");
+        assert_eq!(
+            run_sed(&["/x/!{s/^/-/;s/$/-/}"], false, "x\ny\n"),
+            "x\n-y-\n"
+        );
+        // Nested blocks
+        assert_eq!(
+            run_sed(&["1,3{/2/{d}}"], false, "1\n2\n3\n4\n"),
+            "1\n3\n4\n"
+        );
+    }
+
+    #[test]
+    fn test_labels_and_branches() {
+        // Join all lines
+        let result = run_sed(&[":a;N;$!ba;s/\\n/ /g"], false, "a\nb\nc\n");
+        assert_eq!(result, "a b c\n");
+        // `b` without a label skips the rest of the script
+        assert_eq!(run_sed(&["/a/b;s/./X/"], false, "a\nb\n"), "a\nX\n");
+        // Labels may be defined across separately added scripts
+        assert_eq!(
+            run_sed(&[":top", "s/aa/a/", "t top"], false, "aaaa\n"),
+            "a\n"
+        );
+    }
+
+    #[test]
+    fn test_conditional_branches() {
+        let script = "s/x/y/;t done;s/$/ no/;:done";
+        assert_eq!(run_sed(&[script], false, "x\nz\n"), "y\nz no\n");
+        let script = "s/x/y/;T;s/$/ yes/";
+        assert_eq!(run_sed(&[script], false, "x\nz\n"), "y yes\nz\n");
+        // The flag is reset on each new input line
+        assert_eq!(run_sed(&["2s/^/-/;tx;d;:x"], false, "a\nb\nc\n"), "-b\n");
+    }
+
+    #[test]
+    fn test_comments_and_quiet_marker() {
+        let script = "# leading comment\np # print it\n";
+        assert_eq!(run_sed(&[script], true, "a\n"), "a\n");
+        assert_eq!(run_sed(&["#n\np"], false, "a\n"), "a\n");
+        // `#n` only counts as the whole first line
+        assert_eq!(run_sed(&["#no\np"], false, "a\n"), "a\na\n");
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let mut config = SedConfig::new();
+        assert!(config.add_script("1{p").is_err());
+        assert!(config.add_script("p}").is_err());
+        assert!(config.add_script("b missing").is_err());
+        assert!(config.add_script(":a;:a").is_err());
+        assert!(config.add_script("1:a").is_err());
+        assert!(config.add_script("p x").is_err());
+        assert!(config.add_script("s/a/b/q").is_err());
+        // A failed script leaves the configuration untouched
+        assert!(config.commands.is_empty());
+    }
+
+    #[test]
+    fn test_substitute_replacement_syntax() {
+        let input = "John Smith\n";
+        assert_eq!(
+            run_sed(&[r"s/\(\w\+\) \(\w\+\)/\2, \1/"], false, input),
+            "Smith, John\n"
+        );
+        assert_eq!(run_sed(&[r"s/\w\+/\U&/g"], false, input), "JOHN SMITH\n");
+        assert_eq!(run_sed(&[r"s/ /\n/"], false, input), "John\nSmith\n");
+        assert_eq!(run_sed(&["s/$/ costs $5/"], false, "it\n"), "it costs $5\n");
+        assert_eq!(run_sed(&[r"s|/|\|\\|g"], false, "a/b\n"), "a|\\b\n");
+    }
+
+    #[test]
+    fn test_substitute_invalid_back_reference() {
+        assert!(parse_sed_command(r"s/a/\1/").is_err());
+        assert!(parse_sed_command(r"s/\(a\)/\1\2/").is_err());
+        assert!(parse_sed_command(r"s/\(a\)/\1/").is_ok());
+    }
+
+    #[test]
+    fn test_substitute_numeric_flags() {
+        assert_eq!(run_sed(&["s/a/x/2"], false, "aaaa\n"), "axaa\n");
+        assert_eq!(run_sed(&["s/a/x/2g"], false, "aaaa\n"), "axxx\n");
+        assert_eq!(run_sed(&["s/a/x/5"], false, "aaaa\n"), "aaaa\n");
+        assert!(parse_sed_command("s/a/x/0").is_err());
+        assert!(parse_sed_command("s/a/x/gg").is_err());
+    }
+
+    #[test]
+    fn test_substitute_case_and_multiline_flags() {
+        assert_eq!(run_sed(&["s/abc/x/i"], false, "ABC\n"), "x\n");
+        assert_eq!(run_sed(&["s/abc/x/Ig"], false, "abcABC\n"), "xx\n");
+        assert_eq!(run_sed(&["N;s/^/> /Mg"], false, "a\nb\n"), "> a\n> b\n");
+        assert_eq!(run_sed(&["N;s/^/> /g"], false, "a\nb\n"), "> a\nb\n");
+    }
+
+    #[test]
+    fn test_substitute_delimiters() {
+        assert_eq!(
+            run_sed(&[r"s/\/usr/\/opt/"], false, "/usr/bin\n"),
+            "/opt/bin\n"
+        );
+        assert_eq!(run_sed(&["s/[/]/|/g"], false, "a/b/c\n"), "a|b|c\n");
+        assert_eq!(run_sed(&["s,[^,],x,"], false, ",a\n"), ",x\n");
+        assert_eq!(run_sed(&["s/[]/]/_/g"], false, "a]b/\n"), "a_b_\n");
+        assert_eq!(run_sed(&["s/[[:digit:]/]/#/g"], false, "a1/\n"), "a##\n");
+        assert_eq!(run_sed(&[r"s|a\|b|x|"], false, "a|b\n"), "x\n");
+        assert_eq!(run_sed(&["/[/]/d"], false, "a\n/\n"), "a\n");
+    }
+
+    #[test]
+    fn test_substitute_write_flag() {
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => dir,
+            Err(e) => panic!("Failed to create temp dir: {}", e),
+        };
+        let out_path = dir.path().join("changed.txt");
+        let script = format!("s/a/A/w {}", out_path.display());
+        assert_eq!(run_sed(&[&script], false, "a\nb\nca\n"), "A\nb\ncA\n");
+        match std::fs::read_to_string(&out_path) {
+            Ok(content) => assert_eq!(content, "A\ncA\n"),
+            Err(e) => panic!("Failed to read write file: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_substitute_execute_flag() {
+        assert_eq!(run_sed(&["s/.*/echo &/e"], false, "hi\n"), "hi\n");
+        assert_eq!(run_sed(&["s/^/echo x/e"], false, "y\n"), "xy\n");
+    }
+
+    #[test]
+    fn test_basic_regex_syntax() {
+        assert_eq!(run_sed(&[r"s/\(foo\)\{2\}/x/"], false, "foofoo\n"), "x\n");
+        assert_eq!(run_sed(&["s/(a)+/x/"], false, "(a)+\n"), "x\n");
+        assert_eq!(run_sed(&["s/a*b/x/"], false, "aab\n"), "x\n");
+        assert_eq!(run_sed(&["s/^*/x/"], false, "*a\n"), "xa\n");
+        assert_eq!(run_sed(&[r"/a\|b/d"], false, "a\nb\nc\n"), "c\n");
+        // `.` matches newlines in the pattern space
+        assert_eq!(run_sed(&["N;s/a.b/x/"], false, "a\nb\n"), "x\n");
+    }
+
+    #[test]
+    fn test_extended_regex_syntax() {
+        let mut config = SedConfig::new();
+        config.syntax = RegexSyntax::Extended;
+        if let Err(e) = config.add_script("s/(foo){2}|bar+/x/g") {
+            panic!("Failed to add script: {}", e);
+        }
+        let mut output = Vec::new();
+        if config
+            .execute("foofoo barr\n".as_bytes(), &mut output)
+            .is_err()
+        {
+            panic!("Failed to execute");
+        }
+        assert_eq!(output, b"x x\n");
+    }
+
+    #[test]
+    fn test_back_reference_in_pattern_is_an_error() {
+        match parse_sed_command(r"s/\(a\)\1/x/") {
+            Err(e) => assert!(e.contains("Back-references")),
+            Ok(_) => panic!("Expected an error"),
+        }
+        assert!(parse_sed_command(r"/\(a\)\1/d").is_err());
+    }
+
+    /// Run a script over raw bytes, optionally with NUL-separated records
+    fn run_sed_bytes(script: &str, null_data: bool, input: &[u8]) -> Vec<u8> {
+        let mut config = SedConfig::new();
+        config.null_data = null_data;
+        if let Err(e) = config.add_script(script) {
+            panic!("Failed to add script {:?}: {}", script, e);
+        }
+        let mut output = Vec::new();
+        if config.execute(input, &mut output).is_err() {
+            panic!("Failed to execute");
+        }
+        output
+    }
+
+    #[test]
+    fn test_input_round_trips_byte_for_byte() {
+        let inputs: [&[u8]; 4] = [
+            b"a\r\nb\r\n",
+            b"no final newline",
+            b"bad \xff\xfe utf8\n\xc3\n",
+            b"mixed\r\nend\nlast\r",
+        ];
+        for input in inputs {
+            assert_eq!(run_sed_bytes("s/zzz/y/", false, input), input);
+        }
+    }
+
+    #[test]
+    fn test_edits_keep_line_endings() {
+        assert_eq!(run_sed_bytes("s/a/x/", false, b"a\r\nb\r\n"), b"x\r\nb\r\n");
+        assert_eq!(run_sed_bytes("s/b/x/", false, b"a\nb"), b"a\nx");
+        assert_eq!(
+            run_sed_bytes("s/o/0/g", false, b"\xffo\xfeo\n"),
+            b"\xff0\xfe0\n"
+        );
+        assert_eq!(run_sed_bytes("y/o/0/", false, b"\xffo\n"), b"\xff0\n");
+        // Output after an unterminated last line gets the missing newline first
+        assert_eq!(run_sed_bytes("p", false, b"a"), b"a\na");
+        assert_eq!(run_sed_bytes("$a end", false, b"a"), b"a\nend\n");
+    }
+
+    #[test]
+    fn test_null_data_records() {
+        assert_eq!(run_sed_bytes("s/\n/ /g", true, b"a\nb\0c\0"), b"a b\0c\0");
+        assert_eq!(run_sed_bytes("$!d", true, b"a\0b\0"), b"b\0");
+        assert_eq!(run_sed_bytes("N;P;D", true, b"a\0b"), b"a\0b");
+    }
+
+    #[test]
+    fn test_quit_exit_codes() {
+        let mut config = SedConfig::new();
+        if let Err(e) = config.add_script("2q5") {
+            panic!("Failed to add script: {}", e);
+        }
+        let mut output = Vec::new();
+        match config.execute("a\nb\nc\n".as_bytes(), &mut output) {
+            Ok(exit_code) => assert_eq!(exit_code, Some(5)),
+            Err(e) => panic!("Failed to execute: {}", e),
+        }
+        assert_eq!(output, b"a\nb\n");
+        assert!(matches!(
+            parse_sed_command("Q 7").map(|cmd| cmd.command),
+            Ok(Command::QuitSilent(7))
+        ));
+    }
+
+    #[test]
+    fn test_streams_of_several_inputs() {
+        let mut config = SedConfig::new();
+        if let Err(e) = config.add_script("$!d;x;G") {
+            panic!("Failed to add script: {}", e);
+        }
+        let mut run = match config.start::<&[u8]>() {
+            Ok(run) => run,
+            Err(e) => panic!("Failed to start: {}", e),
+        };
+
+        // One stream of two inputs; an unterminated input still ends a line
+        let mut output = Vec::new();
+        if let Err(e) = run.process(vec![&b"a\nb"[..], &b"c\n"[..]], &mut output) {
+            panic!("Failed to process: {}", e);
+        }
+        assert_eq!(output, b"\nc\n");
+
+        // A second stream sees the hold space left by the first
+        let mut output = Vec::new();
+        if let Err(e) = run.process(vec![&b"d\n"[..]], &mut output) {
+            panic!("Failed to process: {}", e);
+        }
+        assert_eq!(output, b"c\nd\n");
+    }
+
+    #[test]
+    fn test_posix_rejects_gnu_extensions() {
+        for script in ["1~2d", "0,/a/d", "/a/,+2d", "T", "Q", "q1", "s/a/b/e"] {
+            let mut config = SedConfig::new();
+            config.posix = true;
+            assert!(config.add_script(script).is_err(), "{} should fail", script);
+        }
+        let mut config = SedConfig::new();
+        config.posix = true;
+        assert!(config.add_script("1,/a/{s/a/b/g;q}").is_ok());
+    }
+
+    #[test]
+    fn test_structural_expression() {
+        // Edits may span lines, unlike `s`
+        assert_eq!(
+            run_sed(&[",x/\\n\\n*/ c/\\n/"], false, "a\n\n\nb\n\nc\n"),
+            "a\nb\nc\n"
+        );
+
+        let mut config = SedConfig::new();
+        assert!(config.add_script("p").is_ok());
+        assert!(config.add_script(",x/a/d").is_err());
+        let mut config = SedConfig::new();
+        assert!(config.add_script(",x/a/d").is_ok());
+        assert!(config.add_script("p").is_err());
+    }
+
+    #[test]
+    fn test_field_mode() {
+        let run = |separator: &str, script: &str, input: &str| {
+            let mut config = SedConfig::new();
+            config.quiet = true;
+            config.field_separator = match FieldSeparator::parse(separator) {
+                Ok(separator) => Some(separator),
+                Err(e) => panic!("Invalid separator: {}", e),
+            };
+            if let Err(e) = config.add_script(script) {
+                panic!("Failed to add script {:?}: {}", script, e);
+            }
+            let mut output = Vec::new();
+            if let Err(e) = config.execute(input.as_bytes(), &mut output) {
+                panic!("Failed to execute: {}", e);
+            }
+            String::from_utf8_lossy(&output).into_owned()
+        };
+        let passwd = "root:x:0:0\nbin:x:1:1\nsync:x:4:65534\n";
+
+        // Print column 3 where column 1 matches
+        assert_eq!(run(":", "$1/^root$/s/.*/$3/p", passwd), "0\n");
+        // Reorder fields; `$` alone is still the last line
+        assert_eq!(run(":", "$s/.*/$NF:$1/p", passwd), "65534:sync\n");
+        assert_eq!(run(" ", "$2/b/!p", "a b\n c  d\n"), " c  d\n");
+        assert_eq!(run(":", "s/.*/\\$1/p", "a\n"), "$1\n");
+
+        let mut config = SedConfig::new();
+        config.field_separator = Some(FieldSeparator::Blank);
+        assert!(config.add_script("$1p").is_err());
+    }
 }
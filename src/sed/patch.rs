@@ -0,0 +1,471 @@
+//! Unified patch files: parsing, and applying hunks with offset and fuzz
+//! tolerance the way `patch(1)` does.
+
+/// Default number of context lines a hunk may lose at each end and still
+/// apply, as in `patch(1)`
+pub const DEFAULT_FUZZ: usize = 2;
+
+/// One line of a hunk body, with the `\r` of a CRLF line kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// One `@@` hunk
+#[derive(Debug, Clone)]
+pub struct PatchHunk {
+    /// First original line, 1-based as in the header (0 for an empty file)
+    pub old_start: usize,
+    /// First new line, 1-based
+    pub new_start: usize,
+    pub lines: Vec<PatchLine>,
+    /// Whether the last original and new lines lack a final newline
+    pub old_no_newline: bool,
+    pub new_no_newline: bool,
+    /// Line of the `@@` header in the patch
+    pub header_line: usize,
+    /// The header itself
+    pub header: String,
+}
+
+/// The hunks for one file
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    /// Paths from the `---` and `+++` lines, without timestamps
+    pub old_path: String,
+    pub new_path: String,
+    pub hunks: Vec<PatchHunk>,
+    /// Line of the `---` header in the patch
+    pub header_line: usize,
+}
+
+/// How one hunk fared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HunkResult {
+    /// Applied `offset` lines from where the header put it, ignoring `fuzz`
+    /// context lines at each end
+    Applied {
+        offset: isize,
+        fuzz: usize,
+    },
+    Failed,
+}
+
+impl PatchHunk {
+    /// The lines the hunk expects (`reverse`: produces) and the lines it
+    /// leaves, without `\n`
+    fn sides(&self, reverse: bool) -> (Vec<&str>, Vec<&str>) {
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+        for line in &self.lines {
+            match line {
+                PatchLine::Context(text) => {
+                    old.push(text.as_str());
+                    new.push(text.as_str());
+                }
+                PatchLine::Removed(text) => old.push(text.as_str()),
+                PatchLine::Added(text) => new.push(text.as_str()),
+            }
+        }
+        if reverse { (new, old) } else { (old, new) }
+    }
+
+    /// Handle `\ No newline at end of file`, which follows the line it
+    /// applies to
+    fn mark_no_newline(&mut self) {
+        match self.lines.last() {
+            Some(PatchLine::Removed(_)) => self.old_no_newline = true,
+            Some(PatchLine::Added(_)) => self.new_no_newline = true,
+            _ => {
+                self.old_no_newline = true;
+                self.new_no_newline = true;
+            }
+        }
+    }
+
+    /// Context lines before the first change and after the last one
+    fn context_ends(&self) -> (usize, usize) {
+        let is_context = |line: &&PatchLine| matches!(line, PatchLine::Context(_));
+        let leading = self.lines.iter().take_while(is_context).count();
+        let trailing = self.lines.iter().rev().take_while(is_context).count();
+        (leading, trailing)
+    }
+}
+
+impl FilePatch {
+    /// The path the patch applies to, with a leading `a/` or `b/` removed;
+    /// `None` when the patch deletes the file
+    pub fn target_path(&self) -> Option<&str> {
+        let path = match self.new_path.as_str() {
+            "/dev/null" => return None,
+            path => path,
+        };
+        Some(
+            path.strip_prefix("b/")
+                .or_else(|| path.strip_prefix("a/"))
+                .unwrap_or(path),
+        )
+    }
+
+    /// Whether the patch creates the file
+    pub fn creates_file(&self) -> bool {
+        self.old_path == "/dev/null"
+    }
+}
+
+/// The path of a `---`/`+++` line, without the tab-separated timestamp
+fn header_path(rest: &str) -> String {
+    rest.split('\t')
+        .next()
+        .unwrap_or(rest)
+        .trim_end()
+        .to_string()
+}
+
+/// Parse `-start,len +start,len` from an `@@` header
+fn parse_range(spec: &str) -> Option<(usize, usize)> {
+    let (start, len) = match spec.split_once(',') {
+        Some((start, len)) => (start, len.parse().ok()?),
+        None => (spec, 1),
+    };
+    Some((start.parse().ok()?, len))
+}
+
+/// Parse the file patches of a unified diff; text outside them (commit
+/// messages, `diff --git` and `index` lines) is skipped
+pub fn parse(text: &str) -> Result<Vec<FilePatch>, String> {
+    // Split on `\n` only, so hunk lines keep the `\r` of CRLF endings
+    let lines: Vec<&str> = text
+        .split_inclusive('\n')
+        .map(|line| line.strip_suffix('\n').unwrap_or(line))
+        .collect();
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        if let (Some(old), Some(new)) = (
+            line.strip_prefix("--- "),
+            lines
+                .get(index + 1)
+                .and_then(|next| next.strip_prefix("+++ ")),
+        ) {
+            files.push(FilePatch {
+                old_path: header_path(old),
+                new_path: header_path(new),
+                hunks: Vec::new(),
+                header_line: index,
+            });
+            index += 2;
+            continue;
+        }
+
+        let Some(header) = line.strip_prefix("@@ ") else {
+            index += 1;
+            continue;
+        };
+        let file = files
+            .last_mut()
+            .ok_or_else(|| format!("Line {}: hunk before any file header", index + 1))?;
+        let mut specs = header.split_whitespace();
+        let ranges = match (specs.next(), specs.next()) {
+            (Some(old), Some(new)) => old
+                .strip_prefix('-')
+                .and_then(parse_range)
+                .zip(new.strip_prefix('+').and_then(parse_range)),
+            _ => None,
+        };
+        let Some(((old_start, mut old_left), (new_start, mut new_left))) = ranges else {
+            return Err(format!("Line {}: malformed hunk header", index + 1));
+        };
+
+        let mut hunk = PatchHunk {
+            old_start,
+            new_start,
+            lines: Vec::new(),
+            old_no_newline: false,
+            new_no_newline: false,
+            header_line: index,
+            header: line.trim_end_matches('\r').to_string(),
+        };
+        index += 1;
+        while old_left > 0 || new_left > 0 {
+            let Some(&body) = lines.get(index) else {
+                return Err(format!(
+                    "Hunk at line {} is truncated",
+                    hunk.header_line + 1
+                ));
+            };
+            // Some tools drop the space of empty context lines
+            let (marker, text) = match body.chars().next() {
+                Some('\r') | None => (' ', body.to_string()),
+                Some(marker) => (marker, body[marker.len_utf8()..].to_string()),
+            };
+            match marker {
+                ' ' if old_left > 0 && new_left > 0 => {
+                    old_left -= 1;
+                    new_left -= 1;
+                    hunk.lines.push(PatchLine::Context(text));
+                }
+                '-' if old_left > 0 => {
+                    old_left -= 1;
+                    hunk.lines.push(PatchLine::Removed(text));
+                }
+                '+' if new_left > 0 => {
+                    new_left -= 1;
+                    hunk.lines.push(PatchLine::Added(text));
+                }
+                '\\' => hunk.mark_no_newline(),
+                _ => {
+                    return Err(format!(
+                        "Line {}: unexpected line in hunk at line {}",
+                        index + 1,
+                        hunk.header_line + 1
+                    ));
+                }
+            }
+            index += 1;
+        }
+
+        while lines.get(index).is_some_and(|line| line.starts_with('\\')) {
+            hunk.mark_no_newline();
+            index += 1;
+        }
+        file.hunks.push(hunk);
+    }
+    Ok(files)
+}
+
+/// Apply `hunks` in order to `text`, or take them back out when `reverse`
+/// is set. Each hunk is looked for where its header says, then at growing
+/// distances from there, then again with up to `max_fuzz` context lines
+/// dropped from each end.
+///
+/// Lines the patch gives with a CRLF ending keep it; the others end like
+/// the lines they replace, so LF patches keep a CRLF text CRLF.
+pub fn apply(
+    text: &str,
+    hunks: &[PatchHunk],
+    reverse: bool,
+    max_fuzz: usize,
+) -> (String, Vec<HunkResult>) {
+    let mut lines: Vec<String> = text.split_inclusive('\n').map(str::to_string).collect();
+    let mut results = Vec::with_capacity(hunks.len());
+    // Lines added so far minus lines removed, and where the last hunk ended
+    let mut shift: isize = 0;
+    let mut floor = 0;
+
+    for hunk in hunks {
+        let (old, new) = hunk.sides(reverse);
+        let header_start = if reverse {
+            hunk.new_start
+        } else {
+            hunk.old_start
+        };
+        // Pure insertions name the line after which they go
+        let header_start = if old.is_empty() {
+            header_start
+        } else {
+            header_start.saturating_sub(1)
+        };
+        let expected = header_start.saturating_add_signed(shift);
+
+        let (leading, trailing) = hunk.context_ends();
+        let found = (0..=max_fuzz).find_map(|fuzz| {
+            let front = fuzz.min(leading);
+            let back = fuzz.min(trailing);
+            if front + back > old.len() || (fuzz > 0 && front == 0 && back == 0) {
+                return None;
+            }
+            let wanted = &old[front..old.len() - back];
+            find_block(&lines, wanted, expected + front, floor)
+                .map(|pos| (pos - front, front, back, fuzz))
+        });
+
+        let Some((pos, front, back, fuzz)) = found else {
+            results.push(HunkResult::Failed);
+            continue;
+        };
+        let old_span = pos + front..pos + old.len() - back;
+        let new_body = &new[front..new.len() - back];
+        let no_newline = if reverse {
+            hunk.old_no_newline
+        } else {
+            hunk.new_no_newline
+        };
+        let at_end = old_span.end >= lines.len();
+        let replacement: Vec<String> = new_body
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                if at_end && no_newline && back == 0 && i + 1 == new_body.len() {
+                    line.to_string()
+                } else if line.ends_with('\r') {
+                    format!("{}\n", line)
+                } else {
+                    format!("{}{}", line, line_break_near(&lines, &old_span, i))
+                }
+            })
+            .collect();
+        let added = replacement.len();
+        lines.splice(old_span.clone(), replacement);
+
+        results.push(HunkResult::Applied {
+            offset: pos as isize - expected as isize,
+            fuzz,
+        });
+        shift += added as isize - old_span.len() as isize + (pos as isize - expected as isize);
+        floor = old_span.start + added;
+    }
+    (lines.concat(), results)
+}
+
+/// The line break of the line replacing line `i` of `span`: that of the
+/// old line at the same place, or of the nearest line with one
+fn line_break_near(lines: &[String], span: &std::ops::Range<usize>, i: usize) -> &'static str {
+    let at = (span.start + i).min(span.end.saturating_sub(1));
+    let nearest = (at..lines.len())
+        .chain((0..at).rev())
+        .find(|&index| lines[index].ends_with('\n'));
+    match nearest {
+        Some(index) if lines[index].ends_with("\r\n") => "\r\n",
+        _ => "\n",
+    }
+}
+
+/// The position nearest `expected`, and not before `floor`, where `wanted`
+/// matches `lines` (compared without line endings)
+fn find_block(lines: &[String], wanted: &[&str], expected: usize, floor: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(wanted.len())?;
+    let matches_at = |pos: usize| {
+        wanted
+            .iter()
+            .zip(&lines[pos..])
+            .all(|(want, line)| {
+                line.trim_end_matches(['\n', '\r']) == want.trim_end_matches('\r')
+            })
+    };
+    let expected = expected.clamp(floor.min(last), last);
+    (0..=last.max(expected))
+        .flat_map(|distance| {
+            let before = expected.checked_sub(distance);
+            let after = Some(expected + distance).filter(|_| distance > 0);
+            [before, after]
+        })
+        .flatten()
+        .filter(|&pos| pos >= floor && pos <= last)
+        .find(|&pos| matches_at(pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+diff --git a/greet.txt b/greet.txt
+index 1111111..2222222 100644
+--- a/greet.txt
++++ b/greet.txt
+@@ -1,4 +1,4 @@
+ one
+-two
++TWO
+ three
+ four
+@@ -7,3 +7,4 @@
+ seven
+ eight
+ nine
++ten
+";
+
+    fn numbers(range: std::ops::RangeInclusive<usize>) -> String {
+        let names = [
+            "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+        ];
+        range.map(|n| format!("{}\n", names[n])).collect()
+    }
+
+    fn hunks() -> Vec<PatchHunk> {
+        match parse(PATCH) {
+            Ok(mut files) => {
+                assert_eq!(files.len(), 1);
+                assert_eq!(files[0].target_path(), Some("greet.txt"));
+                files.remove(0).hunks
+            }
+            Err(e) => panic!("Failed to parse: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let hunks = hunks();
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].old_start, hunks[0].new_start), (1, 1));
+        assert_eq!(hunks[0].header_line, 4);
+        assert_eq!(
+            hunks[1].lines.last(),
+            Some(&PatchLine::Added("ten".to_string()))
+        );
+        assert!(parse("--- a\n+++ b\n@@ -1,2 +1,2 @@\n x\n").is_err());
+    }
+
+    #[test]
+    fn test_apply_and_reverse() {
+        let (patched, results) = apply(&numbers(1..=9), &hunks(), false, DEFAULT_FUZZ);
+        assert_eq!(patched, numbers(1..=9).replace("two", "TWO") + "ten\n");
+        assert!(
+            results
+                .iter()
+                .all(|r| *r == HunkResult::Applied { offset: 0, fuzz: 0 })
+        );
+
+        let (restored, results) = apply(&patched, &hunks(), true, DEFAULT_FUZZ);
+        assert_eq!(restored, numbers(1..=9));
+        assert_eq!(results.len(), 2);
+
+        // An LF patch keeps a CRLF text CRLF, and so does a CRLF patch
+        let crlf = numbers(1..=9).replace('\n', "\r\n");
+        let crlf_patched = patched.replace('\n', "\r\n");
+        let (patched, _) = apply(&crlf, &hunks(), false, DEFAULT_FUZZ);
+        assert_eq!(patched, crlf_patched);
+        assert_eq!(apply(&patched, &hunks(), true, DEFAULT_FUZZ).0, crlf);
+        let crlf_hunks = match parse(&PATCH.replace('\n', "\r\n")) {
+            Ok(files) => files[0].hunks.clone(),
+            Err(e) => panic!("Failed to parse: {}", e),
+        };
+        assert_eq!(crlf_hunks[0].header, "@@ -1,4 +1,4 @@");
+        let (patched, results) = apply(&crlf, &crlf_hunks, false, 0);
+        assert_eq!(patched, crlf_patched);
+        assert!(results.iter().all(|r| *r != HunkResult::Failed));
+    }
+
+    #[test]
+    fn test_offset_and_fuzz() {
+        // Two extra lines at the top move both hunks down
+        let (patched, results) = apply(&numbers(0..=9), &hunks()[..1], false, 0);
+        assert_eq!(patched, numbers(0..=9).replace("two", "TWO"));
+        assert_eq!(results, [HunkResult::Applied { offset: 1, fuzz: 0 }]);
+
+        // A changed context line only matches with fuzz
+        let text = numbers(1..=9).replace("four", "FOUR");
+        let (_, results) = apply(&text, &hunks()[..1], false, 0);
+        assert_eq!(results, [HunkResult::Failed]);
+        let (patched, results) = apply(&text, &hunks()[..1], false, 1);
+        assert!(patched.starts_with("one\nTWO\nthree\nFOUR\n"));
+        assert_eq!(results, [HunkResult::Applied { offset: 0, fuzz: 1 }]);
+    }
+
+    #[test]
+    fn test_missing_newline_at_end() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n";
+        let hunks = match parse(patch) {
+            Ok(files) => files[0].hunks.clone(),
+            Err(e) => panic!("Failed to parse: {}", e),
+        };
+        assert_eq!(apply("a", &hunks, false, 0).0, "b");
+        assert_eq!(apply("b", &hunks, true, 0).0, "a");
+    }
+}
@@ -0,0 +1,303 @@
+//! POSIX regular expressions for sed.
+//!
+//! sed scripts are written in POSIX basic (BRE) or extended (ERE) regex
+//! syntax, including the usual GNU extensions (`\+`, `\?`, `\|`, `\<`, `\>`,
+//! `\w`, ...). This module translates them into the syntax of the `regex`
+//! crate, and reports constructs that engine cannot express, such as
+//! back-references inside a pattern.
+
+/// Regex dialect used for sed patterns
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RegexSyntax {
+    /// POSIX basic regular expressions (the default)
+    #[default]
+    Basic,
+    /// POSIX extended regular expressions (`-E` / `-r`)
+    Extended,
+}
+
+/// Translate a sed regex into `regex` crate syntax
+pub fn translate(pattern: &str, syntax: RegexSyntax) -> Result<String, String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let basic = syntax == RegexSyntax::Basic;
+    let mut out = String::with_capacity(pattern.len());
+    let mut i = 0;
+    // Whether the previous token starts an expression, where `*` is literal
+    // and `^` is an anchor in a BRE
+    let mut at_start = true;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let was_start = std::mem::replace(&mut at_start, false);
+
+        match c {
+            '\\' => {
+                let Some(&next) = chars.get(i) else {
+                    return Err("Trailing backslash in regex".to_string());
+                };
+                i += 1;
+                match next {
+                    '(' | ')' | '{' | '}' | '|' | '+' | '?' if basic => match next {
+                        '{' => i = translate_interval(&chars, i, basic, &mut out)?,
+                        '(' | '|' => {
+                            out.push(next);
+                            at_start = true;
+                        }
+                        _ => out.push(next),
+                    },
+                    '1'..='9' => {
+                        return Err(format!(
+                            "Back-references in patterns (\\{}) are not supported",
+                            next
+                        ));
+                    }
+                    '<' => out.push_str(r"\b{start}"),
+                    '>' => out.push_str(r"\b{end}"),
+                    '`' => out.push_str(r"\A"),
+                    '\'' => out.push_str(r"\z"),
+                    'w' | 'W' | 's' | 'S' | 'b' | 'B' | 'n' | 't' | 'r' | 'f' | 'v' => {
+                        out.push('\\');
+                        out.push(next);
+                    }
+                    c if c.is_alphanumeric() => {
+                        return Err(format!("Unsupported escape in regex: \\{}", c));
+                    }
+                    c => out.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            '[' => i = translate_bracket(&chars, i, &mut out)?,
+            '*' if was_start => out.push_str(r"\*"),
+            '^' if basic && !was_start => out.push_str(r"\^"),
+            '^' => {
+                out.push('^');
+                at_start = true;
+            }
+            '$' if basic && !at_bre_end(&chars, i) => out.push_str(r"\$"),
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' if basic => {
+                out.push('\\');
+                out.push(c);
+            }
+            '{' => i = translate_interval(&chars, i, basic, &mut out)?,
+            '(' | '|' => {
+                out.push(c);
+                at_start = true;
+            }
+            _ => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Spell `c` so that `translate` treats it as a literal character
+pub fn escape_literal(c: char, syntax: RegexSyntax) -> String {
+    let special = match syntax {
+        RegexSyntax::Basic => ".[]\\*^$",
+        RegexSyntax::Extended => ".[]\\*^$()+?{}|",
+    };
+    if special.contains(c) {
+        format!("\\{}", c)
+    } else {
+        c.to_string()
+    }
+}
+
+/// Whether a `$` just before index `i` is an anchor in a BRE: at the end of
+/// the pattern or before `\)` or `\|`
+fn at_bre_end(chars: &[char], i: usize) -> bool {
+    match chars.get(i) {
+        None => true,
+        Some('\\') => matches!(chars.get(i + 1), Some(')' | '|')),
+        _ => false,
+    }
+}
+
+/// Copy an interval such as `{2,3}` whose `{` precedes index `i`; a BRE
+/// interval ends with `\}`. Returns the index after the interval.
+fn translate_interval(
+    chars: &[char],
+    mut i: usize,
+    basic: bool,
+    out: &mut String,
+) -> Result<usize, String> {
+    let mut body = String::new();
+    loop {
+        match chars.get(i) {
+            Some('\\') if basic && chars.get(i + 1) == Some(&'}') => {
+                i += 2;
+                break;
+            }
+            Some('}') if !basic => {
+                i += 1;
+                break;
+            }
+            Some(&c) if c.is_ascii_digit() || c == ',' => {
+                body.push(c);
+                i += 1;
+            }
+            _ => return Err("Invalid content of \\{\\}".to_string()),
+        }
+    }
+
+    // `{,n}` is a GNU shorthand for `{0,n}`
+    if body.starts_with(',') {
+        body.insert(0, '0');
+    }
+    if body.is_empty() {
+        return Err("Invalid content of \\{\\}".to_string());
+    }
+    out.push('{');
+    out.push_str(&body);
+    out.push('}');
+    Ok(i)
+}
+
+/// Translate a bracket expression whose `[` precedes index `i`.
+///
+/// POSIX brackets treat `\` literally (GNU still accepts `\n`, `\t` and
+/// `\\`), while the `regex` crate needs `[`, `\`, `&`, `~` and a literal
+/// `-` escaped. Returns the index after the closing `]`.
+fn translate_bracket(chars: &[char], mut i: usize, out: &mut String) -> Result<usize, String> {
+    out.push('[');
+    if chars.get(i) == Some(&'^') {
+        out.push('^');
+        i += 1;
+    }
+    let body_start = out.len();
+    if chars.get(i) == Some(&']') {
+        out.push_str(r"\]");
+        i += 1;
+    }
+
+    loop {
+        let Some(&c) = chars.get(i) else {
+            return Err("Unterminated bracket expression".to_string());
+        };
+        i += 1;
+        match c {
+            ']' => break,
+            '[' => match chars.get(i) {
+                Some(':') => {
+                    let end = find_bracket_term(chars, i + 1, ':')?;
+                    out.push_str("[:");
+                    out.extend(&chars[i + 1..end]);
+                    out.push_str(":]");
+                    i = end + 2;
+                }
+                Some(&kind @ ('.' | '=')) => {
+                    // Collating symbols and equivalence classes of a single
+                    // character stand for that character
+                    let end = find_bracket_term(chars, i + 1, kind)?;
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    if inner.chars().count() != 1 {
+                        return Err(format!("Unsupported collating element: {}", inner));
+                    }
+                    out.push_str(&regex::escape(&inner));
+                    i = end + 2;
+                }
+                _ => out.push_str(r"\["),
+            },
+            '\\' => match chars.get(i) {
+                Some('n') => {
+                    out.push_str(r"\n");
+                    i += 1;
+                }
+                Some('t') => {
+                    out.push_str(r"\t");
+                    i += 1;
+                }
+                Some('\\') => {
+                    out.push_str(r"\\");
+                    i += 1;
+                }
+                _ => out.push_str(r"\\"),
+            },
+            '&' | '~' => {
+                out.push('\\');
+                out.push(c);
+            }
+            // A `-` first or last in the brackets is literal
+            '-' if chars.get(i) == Some(&']') || out.len() == body_start => {
+                out.push_str(r"\-");
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out.push(']');
+    Ok(i)
+}
+
+/// Find the `kind]` that ends a `[:class:]`, `[.sym.]` or `[=equiv=]` term,
+/// starting at index `i`; returns the index of `kind`
+fn find_bracket_term(chars: &[char], i: usize, kind: char) -> Result<usize, String> {
+    (i..chars.len().saturating_sub(1))
+        .find(|&j| chars[j] == kind && chars[j + 1] == ']')
+        .ok_or_else(|| "Unterminated bracket expression".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bre(pattern: &str) -> String {
+        match translate(pattern, RegexSyntax::Basic) {
+            Ok(translated) => translated,
+            Err(e) => panic!("Failed to translate {:?}: {}", pattern, e),
+        }
+    }
+
+    fn ere(pattern: &str) -> String {
+        match translate(pattern, RegexSyntax::Extended) {
+            Ok(translated) => translated,
+            Err(e) => panic!("Failed to translate {:?}: {}", pattern, e),
+        }
+    }
+
+    #[test]
+    fn test_bre_groups_and_intervals() {
+        assert_eq!(bre(r"\(foo\)\{2\}"), "(foo){2}");
+        assert_eq!(bre(r"a\{,3\}"), "a{0,3}");
+        assert_eq!(bre(r"a\+b\?c\|d"), "a+b?c|d");
+        assert_eq!(bre("(a){1}+?|"), r"\(a\)\{1\}\+\?\|");
+    }
+
+    #[test]
+    fn test_bre_anchors_and_star() {
+        assert_eq!(bre("^a$"), "^a$");
+        assert_eq!(bre("a^b$c"), r"a\^b\$c");
+        assert_eq!(bre("*a"), r"\*a");
+        assert_eq!(bre(r"\(*a\)"), r"(\*a)");
+        assert_eq!(bre(r"^*"), r"^\*");
+        assert_eq!(bre(r"\(a$\)"), "(a$)");
+    }
+
+    #[test]
+    fn test_ere_passthrough() {
+        assert_eq!(ere("(foo){2}|b+c?"), "(foo){2}|b+c?");
+        assert_eq!(ere(r"\(\)"), r"\(\)");
+        assert_eq!(ere("a{,2}"), "a{0,2}");
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(bre(r"\<word\>"), r"\b{start}word\b{end}");
+        assert_eq!(bre(r"\w\s\n\.\/"), r"\w\s\n\./");
+        assert!(translate(r"\(a\)\1", RegexSyntax::Basic).is_err());
+        assert!(translate(r"(a)\1", RegexSyntax::Extended).is_err());
+        assert!(translate(r"\d", RegexSyntax::Basic).is_err());
+    }
+
+    #[test]
+    fn test_bracket_expressions() {
+        assert_eq!(bre("[]a]"), r"[\]a]");
+        assert_eq!(bre("[^]a]"), r"[^\]a]");
+        assert_eq!(bre(r"[\]"), r"[\\]");
+        assert_eq!(bre("[[:digit:]x]"), "[[:digit:]x]");
+        assert_eq!(bre("[a-z-]"), r"[a-z\-]");
+        assert_eq!(bre("[[.-.]]"), r"[\-]");
+        assert_eq!(bre("[&&[]"), r"[\&\&\[]");
+        assert!(translate("[abc", RegexSyntax::Basic).is_err());
+    }
+}
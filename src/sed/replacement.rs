@@ -0,0 +1,251 @@
+//! Replacement text for the sed `s` command.
+//!
+//! Translates sed replacement syntax (`&`, `\1`..`\9`, `\n` and the GNU case
+//! escapes `\U \L \u \l \E`) into a sequence of parts that is expanded for
+//! every match. Unlike `Regex::replace_all` with a plain string, `$` has no
+//! special meaning here, except for `$N` field references in field mode.
+
+use super::fields::{FieldRef, FieldSeparator};
+use regex::bytes::Captures;
+
+/// Case conversion applied to the text that follows
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Case {
+    /// Uppercase until `\E` or another case escape (`\U`)
+    Upper,
+    /// Lowercase until `\E` or another case escape (`\L`)
+    Lower,
+    /// Uppercase the next character only (`\u`)
+    UpperNext,
+    /// Lowercase the next character only (`\l`)
+    LowerNext,
+    /// Stop case conversion (`\E`)
+    End,
+}
+
+/// One piece of a parsed replacement
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    /// Literal text
+    Literal(String),
+    /// Capture group; 0 is the whole match (`&`)
+    Group(usize),
+    /// Case conversion escape
+    Case(Case),
+    /// Field of the record (`$N`, `$NF`), in field mode
+    Field(FieldRef),
+}
+
+/// Parsed replacement text of an `s` command
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    parts: Vec<Part>,
+    /// Separator for field references, in field mode
+    fields: Option<FieldSeparator>,
+}
+
+impl Replacement {
+    /// Parse raw replacement text as written between the `s` delimiters.
+    ///
+    /// `\` followed by the delimiter stands for the delimiter itself. With a
+    /// field separator, `$N` and `$NF` refer to fields and `\$` is a literal
+    /// `$`.
+    pub fn parse(text: &str, delimiter: char, fields: Option<&FieldSeparator>) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars();
+
+        let push = |parts: &mut Vec<Part>, literal: &mut String, part: Part| {
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(literal)));
+            }
+            parts.push(part);
+        };
+
+        while let Some(c) = chars.next() {
+            match c {
+                '&' => push(&mut parts, &mut literal, Part::Group(0)),
+                '$' if fields.is_some() => match FieldRef::parse(chars.as_str()) {
+                    Some((field, rest)) => {
+                        push(&mut parts, &mut literal, Part::Field(field));
+                        chars = rest.chars();
+                    }
+                    None => literal.push('$'),
+                },
+                '\\' => match chars.next() {
+                    Some(c) if c == delimiter => literal.push(c),
+                    Some(d @ '0'..='9') => {
+                        let group = d as usize - '0' as usize;
+                        push(&mut parts, &mut literal, Part::Group(group));
+                    }
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('U') => push(&mut parts, &mut literal, Part::Case(Case::Upper)),
+                    Some('L') => push(&mut parts, &mut literal, Part::Case(Case::Lower)),
+                    Some('u') => push(&mut parts, &mut literal, Part::Case(Case::UpperNext)),
+                    Some('l') => push(&mut parts, &mut literal, Part::Case(Case::LowerNext)),
+                    Some('E') => push(&mut parts, &mut literal, Part::Case(Case::End)),
+                    Some(other) => literal.push(other),
+                    None => literal.push('\\'),
+                },
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Self {
+            parts,
+            fields: fields.cloned(),
+        }
+    }
+
+    /// Highest capture group referenced, for validation against the pattern
+    pub fn max_group(&self) -> usize {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Group(group) => Some(*group),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Append the expansion of this replacement for one match in `record`
+    /// to `dst`
+    pub fn expand(&self, caps: &Captures<'_>, record: &[u8], dst: &mut Vec<u8>) {
+        let mut mode: Option<Case> = None;
+        let mut next: Option<Case> = None;
+
+        for part in &self.parts {
+            let text = match part {
+                Part::Literal(text) => text.as_bytes(),
+                Part::Group(group) => caps.get(*group).map_or(&[][..], |m| m.as_bytes()),
+                Part::Field(field) => match &self.fields {
+                    Some(separator) => field.get(record, separator),
+                    None => &[],
+                },
+                Part::Case(case @ (Case::UpperNext | Case::LowerNext)) => {
+                    next = Some(*case);
+                    continue;
+                }
+                Part::Case(Case::End) => {
+                    mode = None;
+                    next = None;
+                    continue;
+                }
+                Part::Case(case) => {
+                    mode = Some(*case);
+                    continue;
+                }
+            };
+
+            if mode.is_none() && next.is_none() {
+                dst.extend_from_slice(text);
+                continue;
+            }
+            // Case conversion applies to valid UTF-8; other bytes are copied
+            for chunk in text.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    push_case(dst, c, next.take().or(mode));
+                }
+                if !chunk.invalid().is_empty() {
+                    next = None;
+                    dst.extend_from_slice(chunk.invalid());
+                }
+            }
+        }
+    }
+}
+
+/// Push `c` to `dst` as UTF-8, converted according to `case`
+fn push_case(dst: &mut Vec<u8>, c: char, case: Option<Case>) {
+    let mut buf = [0; 4];
+    match case {
+        Some(Case::Upper | Case::UpperNext) => {
+            for upper in c.to_uppercase() {
+                dst.extend_from_slice(upper.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        Some(Case::Lower | Case::LowerNext) => {
+            for lower in c.to_lowercase() {
+                dst.extend_from_slice(lower.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        _ => dst.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::bytes::Regex;
+
+    fn expand_all(pattern: &str, replacement: &Replacement, input: &str) -> String {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(e) => panic!("Invalid pattern: {}", e),
+        };
+        let mut result = Vec::new();
+        let mut last_end = 0;
+        for caps in regex.captures_iter(input.as_bytes()) {
+            let Some(whole) = caps.get(0) else {
+                continue;
+            };
+            result.extend_from_slice(&input.as_bytes()[last_end..whole.start()]);
+            replacement.expand(&caps, input.as_bytes(), &mut result);
+            last_end = whole.end();
+        }
+        result.extend_from_slice(&input.as_bytes()[last_end..]);
+        String::from_utf8_lossy(&result).into_owned()
+    }
+
+    fn apply(pattern: &str, replacement: &str, input: &str) -> String {
+        expand_all(pattern, &Replacement::parse(replacement, '/', None), input)
+    }
+
+    #[test]
+    fn test_whole_match_and_groups() {
+        assert_eq!(apply("o+", "[&]", "foo"), "f[oo]");
+        assert_eq!(
+            apply(r"(\w+) (\w+)", r"\2 \1", "hello world"),
+            "world hello"
+        );
+        assert_eq!(apply("o", r"\&\\", "o"), r"&\");
+        assert_eq!(apply("a", r"x\/y", "a"), "x/y");
+    }
+
+    #[test]
+    fn test_dollar_is_literal() {
+        assert_eq!(apply("(a)", "$1$", "a"), "$1$");
+    }
+
+    #[test]
+    fn test_field_references() {
+        let separator = FieldSeparator::Literal(b":".to_vec());
+        let replacement = Replacement::parse(r"$3 $1 $NF \$2", '/', Some(&separator));
+        assert_eq!(expand_all("^.*$", &replacement, "a:b:c:d"), "c a d $2");
+    }
+
+    #[test]
+    fn test_newline_escape() {
+        assert_eq!(apply(",", r"\n", "a,b"), "a\nb");
+    }
+
+    #[test]
+    fn test_case_escapes() {
+        assert_eq!(apply(r"\w+", r"\U&", "foo bar"), "FOO BAR");
+        assert_eq!(apply(r"(\w+) (\w+)", r"\U\1\E \2", "foo bar"), "FOO bar");
+        assert_eq!(apply(r"\w+", r"\u&", "foo bar"), "Foo Bar");
+        assert_eq!(apply(r"\w+", r"\L\u&", "FOO"), "Foo");
+        assert_eq!(apply(r"\w+", r"\l&", "FOO"), "fOO");
+    }
+
+    #[test]
+    fn test_max_group() {
+        assert_eq!(Replacement::parse(r"\2-&", '/', None).max_group(), 2);
+        assert_eq!(Replacement::parse("plain", '/', None).max_group(), 0);
+    }
+}
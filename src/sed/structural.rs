@@ -0,0 +1,333 @@
+//! Structural regular expressions in the style of sam and acme.
+//!
+//! A structural expression runs over the whole input rather than line by
+//! line. It starts with `,` (sam's address for the whole file), which never
+//! begins a sed script, and is made of:
+//!
+//! - `x/re/ cmd`: run `cmd` on every match of `re` in dot
+//! - `y/re/ cmd`: run `cmd` on every piece of dot between matches of `re`
+//! - `g/re/ cmd`, `v/re/ cmd`: run `cmd` on dot if it does (does not) contain
+//!   a match of `re`
+//! - `c/text/`, `a/text/`, `i/text/`, `d`: change, append to, insert before
+//!   or delete dot
+//! - `{ cmd ... }`: run each command on the same dot
+//!
+//! Edits are collected against the original text and applied together, so
+//! later commands never see the results of earlier ones.
+
+use super::posix::{self, RegexSyntax};
+use regex::bytes::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// One command of a structural expression
+#[derive(Debug, Clone)]
+enum Structural {
+    /// Run the command on each match (`x`)
+    Extract(Regex, Box<Structural>),
+    /// Run the command on each piece between matches (`y`)
+    Complement(Regex, Box<Structural>),
+    /// Run the command if dot contains a match (`g`)
+    Guard(Regex, Box<Structural>),
+    /// Run the command if dot contains no match (`v`)
+    GuardNot(Regex, Box<Structural>),
+    Change(String),
+    Append(String),
+    Insert(String),
+    Delete,
+    Group(Vec<Structural>),
+}
+
+/// A parsed structural expression
+#[derive(Debug, Clone)]
+pub struct Program {
+    command: Structural,
+}
+
+/// A replacement of a byte range of the original text
+struct Edit {
+    range: Range<usize>,
+    text: String,
+}
+
+impl Program {
+    /// Parse the expression after the leading `,`
+    pub fn parse(expression: &str, syntax: RegexSyntax) -> Result<Self, String> {
+        let (command, rest) = parse_structural(expression, syntax)?;
+        let rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+        if !rest.is_empty() {
+            return Err(format!("Extra characters after command: {}", rest));
+        }
+        Ok(Self { command })
+    }
+
+    /// Run the expression over `text` and return the edited text
+    pub fn apply(&self, text: &[u8]) -> Result<Vec<u8>, String> {
+        let mut edits = Vec::new();
+        run(&self.command, text, 0..text.len(), &mut edits);
+
+        // Inserts at the same position keep the order they were made in
+        edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+        let mut result = Vec::with_capacity(text.len());
+        let mut pos = 0;
+        for edit in edits {
+            if edit.range.start < pos {
+                return Err("Changes overlap".to_string());
+            }
+            result.extend_from_slice(&text[pos..edit.range.start]);
+            result.extend_from_slice(edit.text.as_bytes());
+            pos = edit.range.end;
+        }
+        result.extend_from_slice(&text[pos..]);
+        Ok(result)
+    }
+}
+
+/// Run `command` with `dot` set to a range of `text`, collecting its edits
+fn run(command: &Structural, text: &[u8], dot: Range<usize>, edits: &mut Vec<Edit>) {
+    match command {
+        Structural::Extract(re, command) => {
+            for found in matches(re, text, dot) {
+                run(command, text, found, edits);
+            }
+        }
+        Structural::Complement(re, command) => {
+            let mut start = dot.start;
+            for found in matches(re, text, dot.clone()) {
+                run(command, text, start..found.start, edits);
+                start = found.end;
+            }
+            run(command, text, start..dot.end, edits);
+        }
+        Structural::Guard(re, command) => {
+            if re.find_at(&text[..dot.end], dot.start).is_some() {
+                run(command, text, dot, edits);
+            }
+        }
+        Structural::GuardNot(re, command) => {
+            if re.find_at(&text[..dot.end], dot.start).is_none() {
+                run(command, text, dot, edits);
+            }
+        }
+        Structural::Change(new_text) => edits.push(Edit {
+            range: dot,
+            text: new_text.clone(),
+        }),
+        Structural::Append(new_text) => edits.push(Edit {
+            range: dot.end..dot.end,
+            text: new_text.clone(),
+        }),
+        Structural::Insert(new_text) => edits.push(Edit {
+            range: dot.start..dot.start,
+            text: new_text.clone(),
+        }),
+        Structural::Delete => edits.push(Edit {
+            range: dot,
+            text: String::new(),
+        }),
+        Structural::Group(commands) => {
+            for command in commands {
+                run(command, text, dot.clone(), edits);
+            }
+        }
+    }
+}
+
+/// The matches of `re` within `dot`. Text before dot is still visible to
+/// anchors and word boundaries; an empty match right after the previous
+/// match is skipped, as in sam.
+fn matches(re: &Regex, text: &[u8], dot: Range<usize>) -> Vec<Range<usize>> {
+    let haystack = &text[..dot.end];
+    let mut found = Vec::new();
+    let mut pos = dot.start;
+    let mut last_end = None;
+
+    while pos <= dot.end {
+        let Some(m) = re.find_at(haystack, pos) else {
+            break;
+        };
+        if m.is_empty() {
+            pos = m.end() + 1;
+            if last_end == Some(m.start()) {
+                continue;
+            }
+        } else {
+            pos = m.end();
+        }
+        last_end = Some(m.end());
+        found.push(m.range());
+    }
+    found
+}
+
+/// Parse one command, returning it and the text after it
+fn parse_structural(text: &str, syntax: RegexSyntax) -> Result<(Structural, &str), String> {
+    let text = text.trim_start();
+    let mut chars = text.chars();
+    let Some(name) = chars.next() else {
+        return Err("Missing command".to_string());
+    };
+    let args = chars.as_str();
+
+    match name {
+        'x' | 'y' | 'g' | 'v' => {
+            let (re, rest) = parse_regex(name, args, syntax)?;
+            if rest.trim_start().is_empty() {
+                return Err(format!("Missing command after {}/re/", name));
+            }
+            let (command, rest) = parse_structural(rest, syntax)?;
+            let command = Box::new(command);
+            let structural = match name {
+                'x' => Structural::Extract(re, command),
+                'y' => Structural::Complement(re, command),
+                'g' => Structural::Guard(re, command),
+                _ => Structural::GuardNot(re, command),
+            };
+            Ok((structural, rest))
+        }
+        'c' | 'a' | 'i' => {
+            let (new_text, rest) = parse_text(name, args)?;
+            let structural = match name {
+                'c' => Structural::Change(new_text),
+                'a' => Structural::Append(new_text),
+                _ => Structural::Insert(new_text),
+            };
+            Ok((structural, rest))
+        }
+        'd' => Ok((Structural::Delete, args)),
+        '{' => {
+            let mut commands = Vec::new();
+            let mut rest = args;
+            loop {
+                rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ';');
+                if let Some(after) = rest.strip_prefix('}') {
+                    return Ok((Structural::Group(commands), after));
+                }
+                if rest.is_empty() {
+                    return Err("Unmatched '{'".to_string());
+                }
+                let (command, after) = parse_structural(rest, syntax)?;
+                commands.push(command);
+                rest = after;
+            }
+        }
+        _ => Err(format!("Unknown structural command: '{}'", name)),
+    }
+}
+
+/// The delimiter after a command name
+fn delimiter(name: char, args: &str) -> Result<(char, &str), String> {
+    let mut chars = args.chars();
+    match chars.next() {
+        Some(delim) if !delim.is_alphanumeric() && !delim.is_whitespace() && delim != '\\' => {
+            Ok((delim, chars.as_str()))
+        }
+        _ => Err(format!("Missing delimiter after '{}'", name)),
+    }
+}
+
+/// Parse and compile the `/re/` of `x`, `y`, `g` and `v`. As in sam, `.`
+/// does not match a newline and `^`/`$` match at line boundaries.
+fn parse_regex(name: char, args: &str, syntax: RegexSyntax) -> Result<(Regex, &str), String> {
+    let (delim, args) = delimiter(name, args)?;
+    let (pattern, rest) = super::split_regex(args, delim, syntax)?;
+    if pattern.is_empty() {
+        return Err(format!("Empty regular expression in '{}'", name));
+    }
+    let re = RegexBuilder::new(&posix::translate(&pattern, syntax)?)
+        .multi_line(true)
+        .build()
+        .map_err(|e| e.to_string())?;
+    Ok((re, rest))
+}
+
+/// Parse the `/text/` of `c`, `a` and `i`, where `\n` is a newline, `\t` a
+/// tab, and a backslash escapes the delimiter and itself
+fn parse_text(name: char, args: &str) -> Result<(String, &str), String> {
+    let (delim, args) = delimiter(name, args)?;
+    let (raw, rest) = super::split_delimited(args, delim)?;
+    let mut new_text = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            new_text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => new_text.push('\n'),
+            Some('t') => new_text.push('\t'),
+            Some(next) if next == delim || next == '\\' => new_text.push(next),
+            Some(next) => {
+                new_text.push('\\');
+                new_text.push(next);
+            }
+            None => new_text.push('\\'),
+        }
+    }
+    Ok((new_text, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(expression: &str, text: &str) -> Result<String, String> {
+        let program = Program::parse(expression, RegexSyntax::Extended)?;
+        let result = program.apply(text.as_bytes())?;
+        String::from_utf8(result).map_err(|e| e.to_string())
+    }
+
+    fn check(expression: &str, text: &str, expected: &str) {
+        match apply(expression, text) {
+            Ok(result) => assert_eq!(result, expected, "expression {:?}", expression),
+            Err(e) => panic!("{:?} failed: {}", expression, e),
+        }
+    }
+
+    #[test]
+    fn test_extract_and_change() {
+        check("x/foo/c/bar/", "foo food\nfoo\n", "bar bard\nbar\n");
+        // Matches may span lines
+        check("x/a\\nb/c/ab/", "a\nb\na\nc\n", "ab\na\nc\n");
+        check("x/[0-9]+/ { i/</ a/>/ }", "a 12 b 3\n", "a <12> b <3>\n");
+    }
+
+    #[test]
+    fn test_guards_and_nesting() {
+        // Delete lines mentioning TODO
+        check(
+            "x/.*\\n/ g/TODO/ d",
+            "keep\nTODO: drop\nkeep too\n",
+            "keep\nkeep too\n",
+        );
+        // Rename `x` only in lines without `let`
+        check(
+            "x/.*\\n/ v/let/ x/\\bx\\b/ c/y/",
+            "let x = 1;\nx += 1;\n",
+            "let x = 1;\ny += 1;\n",
+        );
+    }
+
+    #[test]
+    fn test_complement() {
+        // Every field, empty ones included, becomes `_`
+        check("y/,/ c/_/", "a,b,,c", "_,_,_,_");
+        check("y/\\n/ g/^#/ d", "#x\ny\n#z\n", "\ny\n\n");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(apply("x/foo/", "").is_err());
+        assert!(apply("x/foo/ { d", "").is_err());
+        assert!(apply("z", "").is_err());
+        assert!(apply("c/a", "").is_err());
+        assert!(matches!(
+            apply("{ c/a/ c/b/ }", "text"),
+            Err(e) if e == "Changes overlap"
+        ));
+    }
+
+    #[test]
+    fn test_text_escapes() {
+        check("x/;/ c/\\n\\t\\//", "a;b", "a\n\t/b");
+    }
+}
@@ -203,12 +203,6 @@ impl Display {
         Ok((cols, rows))
     }
 
-    /// Set a status message to display
-    pub fn set_message(&mut self, msg: String) {
-        self.message = msg;
-        self.dirty = true;
-    }
-
     /// Set the current key sequence state
     pub fn set_key_sequence(&mut self, seq: String) {
         self.key_sequence = seq;
@@ -295,6 +289,13 @@ impl Display {
         // Collect updates to apply after rendering (to avoid borrow issues)
         let mut width_updates: Vec<(crate::core::id::WindowId, usize)> = Vec::new();
 
+        // Hunks highlighted in diff views, from a diff or conflict session
+        let diff_hunks: &[crate::sed::diff::Hunk] = match (&app.diff_state, &app.conflict_state) {
+            (Some(state), _) => &state.hunks,
+            (None, Some(state)) => &state.hunks,
+            (None, None) => &[],
+        };
+
         for (window_id, rect) in &window_rects {
             // Mark this window's rect as dirty for incremental rendering
             self.dirty_tracker.mark_rect(rect);
@@ -315,7 +316,7 @@ impl Display {
                         &self.dirty_tracker,
                         is_active,
                         self.show_line_numbers,
-                        app.diff_state.as_ref(),
+                        diff_hunks,
                         app.terminal_host.as_ref(),
                     );
 
@@ -365,7 +366,10 @@ impl Display {
                         }
                     } else {
                         0
-                    };
+                    } + app
+                        .buffers
+                        .get(&window.buffer_id)
+                        .map_or(0, |buffer| buffer.vcs.gutter_width());
 
                     let screen_y = rect.y + (window.cursor_y.saturating_sub(window.scroll_offset));
                     let screen_x = rect.x + gutter_width + window.visual_cursor_x;
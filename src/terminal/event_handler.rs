@@ -90,14 +90,15 @@ pub fn process_terminal_event(
                     DispatchResult::NeedsInput { prompt, action } => {
                         // Enter prompt mode via focus manager
                         use crate::core::focus::{FocusState, FocusTarget};
-                        let target = match action {
+                        let target = match &action {
                             InputAction::Calculator => FocusTarget::Calculator,
                             InputAction::GotoLine => FocusTarget::GoToLine,
                             InputAction::SearchForward => FocusTarget::ISearch,
                             InputAction::QueryReplace => FocusTarget::FindReplace,
                             _ => FocusTarget::Minibuffer,
                         };
-                        app.focus_manager.push(FocusState::new(target, &prompt));
+                        app.focus_manager
+                            .push(FocusState::new(target, &prompt).with_action(action));
                         display.dirty = true;
                     }
                     DispatchResult::AwaitKey(action) => {
@@ -111,7 +112,14 @@ pub fn process_terminal_event(
                         display.dirty = true;
                     }
                     DispatchResult::FileModified => {
-                        display.set_message("File changed on disk. Reload? (y/n)".into());
+                        use crate::core::focus::{FocusState, FocusTarget};
+                        app.focus_manager.push(
+                            FocusState::new(
+                                FocusTarget::Minibuffer,
+                                "File changed on disk: (r)eload, (o)verwrite or (d)iff? ",
+                            )
+                            .with_action(InputAction::FileChanged),
+                        );
                         display.dirty = true;
                     }
                     _ => {}
@@ -193,7 +201,7 @@ pub fn process_terminal_event(
                             let gutter_width = crate::core::input_router::gutter_width(
                                 buffer.line_count(),
                                 display.show_line_numbers,
-                            );
+                            ) + buffer.vcs.gutter_width();
 
                             // Check if click is on vertical scrollbar (rightmost column)
                             let scrollbar_x = rect.x + rect.width.saturating_sub(1);
@@ -418,7 +426,7 @@ fn handle_focus_input(
             display.dirty = true;
             return Ok(false);
         } else if focus.target.uses_minibuffer() {
-            Some((focus.target, focus.handle_key(key)))
+            Some((focus.target, focus.action.clone(), focus.handle_key(key)))
         } else {
             None
         }
@@ -426,19 +434,24 @@ fn handle_focus_input(
         None
     };
 
-    if let Some((target, result)) = focus_result {
+    if let Some((target, action, result)) = focus_result {
         match result {
             crate::core::focus::FocusResult::Continue => {
                 display.dirty = true;
                 return Ok(false);
             }
             crate::core::focus::FocusResult::Confirmed(input) => {
-                let action = match target {
-                    crate::core::focus::FocusTarget::Calculator => InputAction::Calculator,
-                    crate::core::focus::FocusTarget::Minibuffer => InputAction::ExecuteNamedCommand,
-                    crate::core::focus::FocusTarget::GoToLine => InputAction::GotoLine,
-                    crate::core::focus::FocusTarget::ISearch => InputAction::SearchForward,
-                    crate::core::focus::FocusTarget::FindReplace => InputAction::QueryReplace,
+                let action = match (action, target) {
+                    (Some(action), _) => action,
+                    (None, crate::core::focus::FocusTarget::Calculator) => InputAction::Calculator,
+                    (None, crate::core::focus::FocusTarget::Minibuffer) => {
+                        InputAction::ExecuteNamedCommand
+                    }
+                    (None, crate::core::focus::FocusTarget::GoToLine) => InputAction::GotoLine,
+                    (None, crate::core::focus::FocusTarget::ISearch) => InputAction::SearchForward,
+                    (None, crate::core::focus::FocusTarget::FindReplace) => {
+                        InputAction::QueryReplace
+                    }
                     _ => InputAction::ExecuteNamedCommand,
                 };
 
@@ -537,6 +550,56 @@ fn handle_special_buffer_input(
             }
             _ => {}
         },
+        BufferKind::DiffReview => match key.key {
+            Key::Char('j') | Key::Char('n') => {
+                dispatch(app, Some("review-next-hunk"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('k') => {
+                dispatch(app, Some("review-previous-hunk"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('y') => {
+                dispatch(app, Some("review-accept-hunk"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('q') => {
+                dispatch(app, Some("review-quit"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            _ => {}
+        },
+        BufferKind::SedDebug => {
+            let command = match key.key {
+                Key::Char('s') | Key::Char(' ') | Key::Char('n') => "sed-debug-step",
+                Key::Char('c') => "sed-debug-finish-cycle",
+                Key::Char('g') => "sed-debug-run",
+                Key::Char('q') => "sed-debug-quit",
+                _ => return Ok(false),
+            };
+            dispatch(app, Some(command), None, 1);
+            display.dirty = true;
+            return Ok(true);
+        }
+        BufferKind::DiffOriginal | BufferKind::DiffModified if app.conflict_state.is_some() => {
+            let command = match key.key {
+                Key::Char('o') => "conflict-keep-ours",
+                Key::Char('t') => "conflict-keep-theirs",
+                Key::Char('b') => "conflict-keep-base",
+                Key::Char('a') => "conflict-keep-both",
+                Key::Char('n') | Key::Char('j') => "conflict-next",
+                Key::Char('p') | Key::Char('k') => "conflict-previous",
+                Key::Char('q') => "conflict-quit",
+                _ => return Ok(false),
+            };
+            dispatch(app, Some(command), None, 1);
+            display.dirty = true;
+            return Ok(true);
+        }
         BufferKind::DiffOriginal | BufferKind::DiffModified => match key.key {
             Key::Char('j') => {
                 dispatch(app, Some("diff-next-hunk"), None, 1);
@@ -563,6 +626,48 @@ fn handle_special_buffer_input(
                 display.dirty = true;
                 return Ok(true);
             }
+            Key::Char('r') => {
+                dispatch(app, Some("diff-reject-hunk"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('a') => {
+                dispatch(app, Some("diff-accept-all"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('u') => {
+                dispatch(app, Some("diff-undo-accept"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('s') => {
+                dispatch(app, Some("diff-split-hunk"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('w') => {
+                if let DispatchResult::NeedsInput { prompt, action } =
+                    dispatch(app, Some("diff-export-patch"), None, 1)
+                {
+                    use crate::core::focus::{FocusState, FocusTarget};
+                    app.focus_manager.push(
+                        FocusState::new(FocusTarget::Minibuffer, &prompt).with_action(action),
+                    );
+                }
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('<') => {
+                dispatch(app, Some("diff-copy-hunk-left"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
+            Key::Char('>') => {
+                dispatch(app, Some("diff-copy-hunk-right"), None, 1);
+                display.dirty = true;
+                return Ok(true);
+            }
             _ => {}
         },
         BufferKind::Terminal => {
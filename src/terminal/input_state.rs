@@ -109,6 +109,8 @@ impl InputStateMachine {
                 BufferKind::Diagnostics
                 | BufferKind::DiffOriginal
                 | BufferKind::DiffModified
+                | BufferKind::DiffReview
+                | BufferKind::SedDebug
                 | BufferKind::Terminal => {
                     self.mode = InputMode::SpecialBuffer { kind };
                     return;
@@ -40,7 +40,13 @@ impl StatusRenderer {
         };
 
         let modified = if buffer.modified { "[+]" } else { "" };
-        let pos_info = format!("Ln {}, Col {}", window.cursor_y + 1, window.cursor_x + 1);
+        let pos_info = format!(
+            "{}  {}  Ln {}, Col {}",
+            buffer.encoding.name(),
+            buffer.line_ending.name(),
+            window.cursor_y + 1,
+            window.cursor_x + 1
+        );
 
         let left = format!(" {} {} ", filename, modified);
         let right = format!(" {} ", pos_info);
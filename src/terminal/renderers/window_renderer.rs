@@ -4,8 +4,9 @@ use crate::core::lexer::{Token, TokenKind};
 use crate::core::spell::SpellChecker;
 use crate::core::syntax::{SyntaxHighlighter, TokenType};
 use crate::core::terminal_host::TerminalHost;
+use crate::core::vcs::LineChange;
 use crate::core::window::Window;
-use crate::sed::diff::DiffState;
+use crate::sed::diff::Hunk;
 use crate::terminal::display::{Cell, Color, ScreenBuffer};
 use crate::terminal::renderers::DirtyTracker;
 use crate::terminal::theme::Theme;
@@ -28,7 +29,7 @@ impl WindowRenderer {
         dirty_tracker: &DirtyTracker,
         is_active: bool,
         show_line_numbers: bool,
-        diff_state: Option<&DiffState>,
+        diff_hunks: &[Hunk],
         terminal_host: Option<&TerminalHost>,
     ) -> usize {
         // Ensure the syntax state cache is the right size
@@ -39,6 +40,10 @@ impl WindowRenderer {
             );
         }
 
+        // Git change markers take a column left of the line numbers
+        crate::core::vcs::refresh(buffer);
+        let vcs_width = buffer.vcs.gutter_width();
+
         // Pre-calculate gutter width to determine text area width
         let pre_text_height = rect.height.saturating_sub(1);
         let gutter_width_estimate: usize = if show_line_numbers {
@@ -47,7 +52,7 @@ impl WindowRenderer {
             format!("{}", max_line_num).len() + 1
         } else {
             0
-        };
+        } + vcs_width;
 
         // Calculate text width for horizontal scrollbar decision
         let text_width_estimate = rect.width.saturating_sub(gutter_width_estimate + 1);
@@ -67,6 +72,7 @@ impl WindowRenderer {
         let kind = buffer.buffer_kind();
         let is_original_diff = kind == BufferKind::DiffOriginal;
         let is_modified_diff = kind == BufferKind::DiffModified;
+        let is_review_diff = kind == BufferKind::DiffReview;
 
         // Special handling for Terminal buffers - render from TerminalHost grid
         if kind == BufferKind::Terminal {
@@ -103,13 +109,17 @@ impl WindowRenderer {
         });
 
         // Calculate gutter width if line numbers are enabled (now accurate with text_height)
-        let gutter_width: usize = if show_line_numbers {
+        let number_width: usize = if show_line_numbers {
             let max_line = window.scroll_offset + text_height;
             let max_line_num = buffer.line_count().min(max_line).max(1);
             format!("{}", max_line_num).len() + 1
         } else {
             0
         };
+        let gutter_width = number_width + vcs_width;
+        let vcs_marks: Vec<Option<LineChange>> = (0..text_height)
+            .map(|y| buffer.vcs.line_change(window.scroll_offset + y))
+            .collect();
 
         // Initialize references for rendering loop using split borrow
         let (rope, diagnostics_vec, syntax_cache, filename) = buffer.split_for_render();
@@ -153,33 +163,66 @@ impl WindowRenderer {
                 theme.bg().clone().into()
             };
 
-            // Diff highlighting
-            if let Some(state) = diff_state {
-                for hunk in &state.hunks {
+            // Diff highlighting, with the changed words of edited lines
+            // picked out within the line tint
+            let mut inline_ranges: &[std::ops::Range<usize>] = &[];
+            if is_original_diff || is_modified_diff {
+                for hunk in diff_hunks {
                     if is_original_diff
                         && buffer_line_idx >= hunk.start_line
                         && buffer_line_idx <= hunk.end_line
                     {
                         line_bg = Self::mix_colors(line_bg, Color::Red, 0.2);
+                        inline_ranges = hunk.inline.old_line(buffer_line_idx);
                     } else if is_modified_diff
-                        && buffer_line_idx >= hunk.start_line
-                        && buffer_line_idx
-                            <= hunk.start_line + hunk.new_lines.len().saturating_sub(1)
+                        && buffer_line_idx >= hunk.new_start
+                        && buffer_line_idx < hunk.new_start + hunk.new_lines.len()
                     {
                         line_bg = Self::mix_colors(line_bg, Color::Green, 0.2);
+                        inline_ranges = hunk.inline.new_line(buffer_line_idx);
                     }
                 }
             }
 
+            // Removed and added lines of a unified diff; `---`/`+++` lines
+            // are file headers
+            if is_review_diff && buffer_line_idx < len_lines {
+                let marker: String = rope.line(buffer_line_idx).chars().take(4).collect();
+                let tint = match marker.chars().next() {
+                    _ if marker.starts_with("--- ") || marker.starts_with("+++ ") => None,
+                    Some('-') => Some(Color::Red),
+                    Some('+') => Some(Color::Green),
+                    _ => None,
+                };
+                if let Some(color) = tint {
+                    line_bg = Self::mix_colors(line_bg, color, 0.2);
+                }
+            }
+
+            // Draw git change marker
+            if vcs_width > 0 && rect.width > 0 {
+                let (marker, fg) = match vcs_marks.get(y).copied().flatten() {
+                    Some(LineChange::Added) => ('+', theme.diff_added()),
+                    Some(LineChange::Modified) => ('~', theme.diff_modified()),
+                    Some(LineChange::Deleted) => ('_', theme.diff_removed()),
+                    None => (' ', theme.gutter_fg()),
+                };
+                screen_buffer.set(
+                    rect.x as u16,
+                    screen_y,
+                    Cell::new(marker, (*fg).into(), (*theme.gutter_bg()).into()),
+                );
+            }
+
             // Draw line number in gutter
-            if show_line_numbers && gutter_width > 0 {
+            if show_line_numbers && number_width > 0 {
                 let line_num_str = if buffer_line_idx < len_lines {
-                    format!("{:>width$}│", buffer_line_idx + 1, width = gutter_width - 1)
+                    format!("{:>width$}│", buffer_line_idx + 1, width = number_width - 1)
                 } else {
-                    format!("{}│", " ".repeat(gutter_width - 1))
+                    format!("{}│", " ".repeat(number_width - 1))
                 };
                 for (i, ch) in line_num_str.chars().enumerate() {
-                    let gx = rect.x as u16 + i as u16;
+                    let gx = (rect.x + vcs_width) as u16 + i as u16;
                     if gx < (rect.x + rect.width) as u16 {
                         screen_buffer.set(
                             gx,
@@ -195,7 +238,7 @@ impl WindowRenderer {
             }
 
             // Draw diagnostic gutter icon
-            if (has_error || has_warning) && gutter_width > 0 {
+            if (has_error || has_warning) && number_width > 0 {
                 let icon = if has_error { '!' } else { 'W' };
                 let icon_fg = if has_error {
                     theme.error()
@@ -355,6 +398,10 @@ impl WindowRenderer {
                             )
                         } else {
                             let mut final_bg = line_bg;
+                            if inline_ranges.iter().any(|r| r.contains(&byte_offset)) {
+                                final_bg =
+                                    Self::mix_colors(final_bg, (*theme.diff_inline()).into(), 0.35);
+                            }
                             if misspelled_ranges.iter().any(|r| r.contains(&byte_offset)) {
                                 final_bg = Self::mix_colors(
                                     final_bg,
@@ -379,12 +426,18 @@ impl WindowRenderer {
                                 }
                             }
                         } else {
+                            // Undecodable bytes show as replacement characters
+                            let (glyph, token_color) =
+                                match crate::core::encoding::escaped_byte(*ch) {
+                                    Some(_) => ('\u{FFFD}', (*theme.error()).into()),
+                                    None => (*ch, token_color),
+                                };
                             let x = text_start_x as u16 + char_visible_start as u16;
                             if x < (text_start_x + text_width) as u16 {
                                 screen_buffer.set(
                                     x,
                                     screen_y,
-                                    Cell::new(*ch, token_color, bg_color),
+                                    Cell::new(glyph, token_color, bg_color),
                                 );
 
                                 if char_width > 1 {
@@ -518,6 +571,8 @@ impl WindowRenderer {
             TokenType::Function => theme.function().clone().into(),
             TokenType::Operator => theme.operator().clone().into(),
             TokenType::Punctuation => theme.punctuation().clone().into(),
+            TokenType::Inserted => (*theme.diff_added()).into(),
+            TokenType::Deleted => (*theme.diff_removed()).into(),
             TokenType::Normal => theme.normal().clone().into(),
         }
     }
@@ -250,6 +250,26 @@ impl Theme {
         &self.palette.spell_tint
     }
 
+    /// Added lines of a patch
+    pub fn diff_added(&self) -> &Color {
+        &self.palette.green
+    }
+
+    /// Removed lines of a patch
+    pub fn diff_removed(&self) -> &Color {
+        &self.palette.red
+    }
+
+    /// Lines changed since the last commit
+    pub fn diff_modified(&self) -> &Color {
+        &self.palette.blue
+    }
+
+    /// Tint for the changed words within changed lines of a diff
+    pub fn diff_inline(&self) -> &Color {
+        &self.palette.yellow
+    }
+
     // =========================================================================
     // SYNTAX COLOR ACCESSORS
     // =========================================================================
@@ -145,6 +145,8 @@ pub fn configure(config: &mut Config) {
     // Sed/Diff preview
     config.bind("ESC-s", "sed-preview"); // M-s sed preview
     config.bind("Esc s", "sed-preview");
+    config.bind("ESC-S", "sed-preview-region"); // M-S sed preview of the region
+    config.bind("Esc S", "sed-preview-region");
 
     // Count words
     config.bind("ESC-=", "count-words"); // M-=